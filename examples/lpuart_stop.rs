@@ -0,0 +1,69 @@
+//! This example demonstrates receiving on LPUART1 at 9600 baud while in Stop 2 mode, clocked
+//! from the LSE. The start bit of an incoming character wakes the MCU; the character is then
+//! received normally.
+
+#![no_main]
+#![no_std]
+
+use cortex_m::peripheral::NVIC;
+use cortex_m_rt::entry;
+
+use stm32_hal2::{
+    clocks::Clocks,
+    gpio::{Pin, PinMode, Port},
+    low_power::{self, StopMode},
+    pac::{self, interrupt},
+    usart::{Lpuart1, LpuartClockSrc, StopWakeup, Usart, UsartInterrupt},
+};
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    // The LSE keeps running in Stop 2; at 32.768kHz, the LPUART can run at up to 9600 baud.
+    // `setup` starts it, if it's not already running.
+    let clock_cfg = Clocks {
+        lpuart1_src: LpuartClockSrc::Lse,
+        ..Default::default()
+    };
+    clock_cfg.setup().unwrap();
+
+    let _lpuart_tx = Pin::new(Port::C, 1, PinMode::Alt(8));
+    let _lpuart_rx = Pin::new(Port::C, 0, PinMode::Alt(8));
+
+    let lpuart = Lpuart1::new(dp.LPUART1).unwrap();
+    let mut uart = Usart::new(lpuart, 9_600, Default::default(), &clock_cfg);
+
+    uart.enable_stop_wakeup(StopWakeup::StartBit);
+    uart.enable_interrupt(UsartInterrupt::ReadNotEmpty);
+
+    unsafe {
+        NVIC::unmask(interrupt::LPUART1);
+    }
+
+    loop {
+        low_power::stop(StopMode::Two);
+        // We've woken up; the LPUART interrupt has run. Restore the PLL etc.
+        clock_cfg.reselect_input();
+    }
+}
+
+#[interrupt]
+/// A character is being, or has been received.
+fn LPUART1() {
+    let regs = unsafe { &(*pac::LPUART1::ptr()) };
+
+    // Clear the wakeup flag.
+    regs.icr.write(|w| w.wucf().set_bit());
+
+    if regs.isr.read().rxne().bit_is_set() {
+        let _byte = regs.rdr.read().rdr().bits();
+    }
+}
+
+// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[defmt::panic_handler]
+fn panic() -> ! {
+    cortex_m::asm::udf()
+}
@@ -0,0 +1,95 @@
+//! This example demonstrates interrupt-driven, buffered UART reads and writes using
+//! `BufferedUsart`. Unlike `serial_nonblocking.rs`, the ring buffers and the ISR logic are
+//! provided by the library; the ISR only needs to call `on_interrupt()`.
+
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use cortex_m::{
+    interrupt::{free, Mutex},
+    peripheral::NVIC,
+};
+use cortex_m_rt::entry;
+
+use stm32_hal2::{
+    clocks::Clocks,
+    gpio::{Pin, PinMode, Port},
+    low_power,
+    pac::{self, interrupt},
+    usart::{BufferedUsart, Usart, UsartConfig},
+};
+
+const TX_BUF_SIZE: usize = 64;
+const RX_BUF_SIZE: usize = 64;
+
+static UART: Mutex<RefCell<Option<BufferedUsart<pac::USART1, TX_BUF_SIZE, RX_BUF_SIZE>>>> =
+    Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    // Set up CPU peripherals
+    let mut cp = cortex_m::Peripherals::take().unwrap();
+    // Set up microcontroller peripherals
+    let mut dp = pac::Peripherals::take().unwrap();
+
+    let clock_cfg = Clocks::default();
+    clock_cfg.setup().unwrap();
+
+    // Configure pins for UART, according to the user manual.
+    let _uart_tx = Pin::new(Port::A, 9, PinMode::Alt(7));
+    let _uart_rx = Pin::new(Port::A, 10, PinMode::Alt(7));
+
+    let uart = Usart::new(dp.USART1, 9_600, UsartConfig::default(), &clock_cfg);
+
+    // `BufferedUsart::new` enables the RXNE, IDLE and error interrupts.
+    let mut uart = BufferedUsart::new(uart);
+    uart.write(b"Hello!");
+
+    free(|cs| {
+        UART.borrow(cs).replace(Some(uart));
+    });
+
+    unsafe {
+        // Unmask interrupt lines associated with the USART1.
+        NVIC::unmask(interrupt::USART1);
+    }
+
+    let mut buf = [0; RX_BUF_SIZE];
+
+    loop {
+        free(|cs| {
+            let mut u = UART.borrow(cs).borrow_mut();
+            let uart = u.as_mut().unwrap();
+
+            // Echo back whatever was received, once the sender pauses.
+            if uart.idle_detected() {
+                let len = uart.read(&mut buf);
+                uart.write(&buf[..len]);
+            }
+        });
+
+        low_power::sleep_now();
+    }
+}
+
+#[interrupt]
+/// Move data between the USART and the buffers.
+fn USART1() {
+    free(|cs| {
+        let mut u = UART.borrow(cs).borrow_mut();
+        let uart = u.as_mut().unwrap();
+
+        if let Err(_e) = uart.on_interrupt() {
+            // Handle framing, parity, noise and overrun errors here.
+        }
+    });
+}
+
+// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[defmt::panic_handler]
+fn panic() -> ! {
+    cortex_m::asm::udf()
+}
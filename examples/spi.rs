@@ -100,8 +100,8 @@ fn main() -> ! {
     // Alternatively, use the blocking, non-DMA SPI API` (Also supports `embedded-hal` traits):
 
     // We read 3 bytes from the `0x9f` register.
-    let mut write_buf = [0x80, 100];
-    let mut read_buf = [0x9f, 0, 0, 0];
+    let mut write_buf = [0x80_u8, 100];
+    let mut read_buf = [0x9f_u8, 0, 0, 0];
     spi.write(&write_buf).ok();
     spi.transfer(&mut read_buf).ok();
     defmt::println!("Data: {}", read_buf);
@@ -0,0 +1,134 @@
+//! This example demonstrates sharing one SPI peripheral between an IMU and an external flash
+//! chip, using `SpiBus`. Each device has its own chip select pin, SPI mode, and speed; the bus
+//! applies these when switching devices. The IMU is read over DMA; the flash can't access the bus
+//! until the IMU's transfer-complete interrupt releases it.
+
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use cortex_m::{
+    interrupt::{free, Mutex},
+    peripheral::NVIC,
+};
+use cortex_m_rt::entry;
+
+use stm32_hal2::{
+    bus::{self, SharedBus, SpiBus, SpiDevice},
+    clocks::Clocks,
+    dma::{Dma, DmaChannel, DmaInterrupt},
+    gpio::{Pin, PinMode, Port},
+    low_power,
+    pac::{self, interrupt, DMA1, SPI1},
+    spi::{BaudRate, Spi, SpiConfig},
+};
+
+static SPI_BUS: SharedBus<SpiBus<SPI1>> = SharedBus::new();
+
+static IMU: Mutex<RefCell<Option<SpiDevice>>> = Mutex::new(RefCell::new(None));
+static DMA: Mutex<RefCell<Option<Dma<DMA1>>>> = Mutex::new(RefCell::new(None));
+
+static WRITE_BUF: [u8; 13] = [0x1f | 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+static mut IMU_READINGS: [u8; 13] = [0; 13];
+
+#[entry]
+fn main() -> ! {
+    let dp = pac::Peripherals::take().unwrap();
+
+    let clock_cfg = Clocks::default();
+    clock_cfg.setup().unwrap();
+
+    let _sck = Pin::new(Port::A, 5, PinMode::Alt(5));
+    let _miso = Pin::new(Port::A, 6, PinMode::Alt(5));
+    let _mosi = Pin::new(Port::A, 7, PinMode::Alt(5));
+
+    let cs_imu = Pin::new(Port::B, 0, PinMode::Output);
+    let cs_flash = Pin::new(Port::B, 1, PinMode::Output);
+
+    let spi = Spi::new(dp.SPI1, Default::default(), BaudRate::Div32);
+    let mut spi_bus = SpiBus::new(spi);
+
+    // The IMU uses mode 3 at a moderate speed; the flash uses mode 0, at a higher one.
+    let imu_cfg = SpiConfig {
+        mode: embedded_hal::spi::MODE_3,
+        ..Default::default()
+    };
+    let imu = spi_bus.add_device(cs_imu, imu_cfg, BaudRate::Div16);
+    let mut flash = spi_bus.add_device(cs_flash, Default::default(), BaudRate::Div4);
+
+    let mut dma = Dma::new(dp.DMA1);
+    dma.enable_interrupt(DmaChannel::C2, DmaInterrupt::TransferComplete);
+
+    SPI_BUS.init(spi_bus);
+
+    free(|cs| {
+        IMU.borrow(cs).replace(Some(imu));
+        DMA.borrow(cs).replace(Some(dma));
+    });
+
+    unsafe {
+        NVIC::unmask(interrupt::DMA1_CH2);
+    }
+
+    loop {
+        // Start an IMU reading over DMA. The bus is held until the transfer completes.
+        free(|cs| {
+            let mut imu = IMU.borrow(cs).borrow_mut();
+            let mut dma = DMA.borrow(cs).borrow_mut();
+
+            SPI_BUS
+                .lock(|bus| {
+                    bus.begin_dma(imu.as_mut().unwrap(), |spi| unsafe {
+                        spi.transfer_dma(
+                            &WRITE_BUF,
+                            &mut IMU_READINGS,
+                            DmaChannel::C1,
+                            DmaChannel::C2,
+                            Default::default(),
+                            Default::default(),
+                            dma.as_mut().unwrap(),
+                        );
+                    })
+                })
+                .ok();
+        });
+
+        // Read the flash's JEDEC ID. This returns `Busy` while the IMU transfer is in progress.
+        let mut id = [0x9f_u8, 0, 0, 0];
+        let result =
+            SPI_BUS.lock(|bus| bus.transaction(&mut flash, |spi| spi.transfer(&mut id[..])));
+        if let Err(bus::Error::Busy) = result {
+            // Try again on the next loop.
+        }
+
+        low_power::sleep_now();
+    }
+}
+
+#[interrupt]
+/// The IMU DMA read is complete; stop the transfer, and release the bus.
+fn DMA1_CH2() {
+    free(|cs| {
+        let mut imu = IMU.borrow(cs).borrow_mut();
+        let mut d = DMA.borrow(cs).borrow_mut();
+        let dma = d.as_mut().unwrap();
+
+        dma.clear_interrupt(DmaChannel::C2, DmaInterrupt::TransferComplete);
+
+        SPI_BUS
+            .lock(|bus| {
+                bus.end_dma(imu.as_mut().unwrap(), |spi| {
+                    spi.stop_dma(DmaChannel::C1, Some(DmaChannel::C2), dma)
+                })
+            })
+            .ok();
+    });
+}
+
+// same panicking *behavior* as `panic-probe` but doesn't print a panic message
+// this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[defmt::panic_handler]
+fn panic() -> ! {
+    cortex_m::asm::udf()
+}
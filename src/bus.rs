@@ -0,0 +1,299 @@
+//! Share a single SPI or I2C peripheral between multiple devices. Each device on an SPI bus
+//! owns its chip select pin, and its own `SpiConfig` and baud rate; these are applied to the
+//! peripheral when switching devices. Access is serialized by the bus: a device that starts a
+//! DMA transfer holds the bus until it ends it, and other devices' transactions return
+//! `Error::Busy` in the meantime.
+//!
+//! `SpiBus` and `I2cBus` take `&mut self`, so they can be used directly as an RTIC shared
+//! resource, where RTIC's `lock` serializes access. Without RTIC, place them in a `SharedBus`,
+//! which wraps them in a critical-section mutex, and can be declared as a `static`.
+
+use core::{cell::RefCell, ops::Deref};
+
+use cortex_m::interrupt::{free, Mutex};
+
+use crate::{
+    gpio::Pin,
+    i2c::I2c,
+    pac,
+    spi::{BaudRate, Spi, SpiConfig},
+    util::RccPeriph,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Bus arbitration errors.
+pub enum Error {
+    /// Another device holds the bus for an ongoing DMA transfer.
+    Busy,
+    /// The device ending a DMA transfer isn't the one that started it.
+    NotOwner,
+}
+
+/// Tracks device IDs, and which device, if any, holds the bus for a DMA transfer.
+struct Arbiter {
+    next_id: u32,
+    dma_owner: Option<u32>,
+}
+
+impl Arbiter {
+    const fn new() -> Self {
+        Self {
+            next_id: 0,
+            dma_owner: None,
+        }
+    }
+
+    fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        // IDs must be unique, so don't wrap.
+        self.next_id = self
+            .next_id
+            .checked_add(1)
+            .expect("Too many devices added to the bus.");
+        id
+    }
+
+    fn check_free(&self) -> Result<(), Error> {
+        if self.dma_owner.is_some() {
+            Err(Error::Busy)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_owner(&self, id: u32) -> Result<(), Error> {
+        if self.dma_owner == Some(id) {
+            Ok(())
+        } else {
+            Err(Error::NotOwner)
+        }
+    }
+}
+
+/// A device on a shared SPI bus. Create with `SpiBus::add_device`.
+pub struct SpiDevice {
+    /// The chip select pin; held low for the duration of each transaction.
+    pub cs: Pin,
+    /// SPI configuration to apply when this device accesses the bus.
+    pub cfg: SpiConfig,
+    /// Baud rate to apply when this device accesses the bus.
+    pub baud_rate: BaudRate,
+    id: u32,
+}
+
+/// An SPI peripheral shared between several devices.
+pub struct SpiBus<R> {
+    spi: Spi<R>,
+    /// The device whose configuration is currently applied to the peripheral.
+    active: Option<u32>,
+    arbiter: Arbiter,
+}
+
+impl<R> SpiBus<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+{
+    /// Create a bus from an initialized SPI peripheral.
+    pub fn new(spi: Spi<R>) -> Self {
+        Self {
+            spi,
+            active: None,
+            arbiter: Arbiter::new(),
+        }
+    }
+
+    /// Register a device on this bus. Sets its chip select pin high. (deselected)
+    pub fn add_device(&mut self, mut cs: Pin, cfg: SpiConfig, baud_rate: BaudRate) -> SpiDevice {
+        cs.set_high();
+
+        SpiDevice {
+            cs,
+            cfg,
+            baud_rate,
+            id: self.arbiter.new_id(),
+        }
+    }
+
+    /// Apply a device's configuration to the peripheral, if it isn't already active. This
+    /// rewrites the configuration registers, but doesn't reset the peripheral.
+    fn select(&mut self, device: &SpiDevice) {
+        if self.active == Some(device.id) {
+            return;
+        }
+
+        self.spi.reconfigure(device.cfg.clone(), device.baud_rate);
+        self.active = Some(device.id);
+    }
+
+    /// Run a blocking transaction for a device: Apply its configuration if required, assert
+    /// its chip select pin, run `f`, then de-assert the pin. Returns `Error::Busy` without
+    /// running `f` if a DMA transfer is in progress.
+    pub fn transaction<T>(
+        &mut self,
+        device: &mut SpiDevice,
+        f: impl FnOnce(&mut Spi<R>) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_free()?;
+        self.select(device);
+
+        device.cs.set_low();
+        let result = f(&mut self.spi);
+        device.cs.set_high();
+
+        Ok(result)
+    }
+
+    /// Start a DMA transfer for a device: Apply its configuration if required, assert its
+    /// chip select pin, and run `f`, which should start the transfer. The bus is held by this
+    /// device until `end_dma` is called, eg from the DMA transfer-complete interrupt.
+    pub fn begin_dma<T>(
+        &mut self,
+        device: &mut SpiDevice,
+        f: impl FnOnce(&mut Spi<R>) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_free()?;
+        self.select(device);
+
+        self.arbiter.dma_owner = Some(device.id);
+        device.cs.set_low();
+
+        Ok(f(&mut self.spi))
+    }
+
+    /// End a DMA transfer started with `begin_dma`: Run `f`, which should stop the transfer
+    /// (eg `Spi::stop_dma`), de-assert the chip select pin, and release the bus.
+    pub fn end_dma<T>(
+        &mut self,
+        device: &mut SpiDevice,
+        f: impl FnOnce(&mut Spi<R>) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_owner(device.id)?;
+
+        let result = f(&mut self.spi);
+        device.cs.set_high();
+        self.arbiter.dma_owner = None;
+
+        Ok(result)
+    }
+
+    /// Returns `true` if a device holds the bus for a DMA transfer.
+    pub fn is_busy(&self) -> bool {
+        self.arbiter.dma_owner.is_some()
+    }
+
+    /// Release the SPI peripheral.
+    pub fn free(self) -> Spi<R> {
+        self.spi
+    }
+}
+
+/// A device on a shared I2C bus. Create with `I2cBus::add_device`.
+pub struct I2cDevice {
+    /// The device's address.
+    pub addr: u8,
+    id: u32,
+}
+
+/// An I2C peripheral shared between several devices. Unlike SPI, all devices share the bus
+/// speed set by the peripheral's `I2cConfig`; it's not changed per-device.
+pub struct I2cBus<R> {
+    pub i2c: I2c<R>,
+    arbiter: Arbiter,
+}
+
+impl<R> I2cBus<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock>,
+{
+    /// Create a bus from an initialized I2C peripheral.
+    pub fn new(i2c: I2c<R>) -> Self {
+        Self {
+            i2c,
+            arbiter: Arbiter::new(),
+        }
+    }
+
+    /// Register a device on this bus.
+    pub fn add_device(&mut self, addr: u8) -> I2cDevice {
+        I2cDevice {
+            addr,
+            id: self.arbiter.new_id(),
+        }
+    }
+
+    /// Run a blocking transaction for a device. `f` is passed the peripheral, and the device's
+    /// address. Returns `Error::Busy` without running `f` if a DMA transfer is in progress.
+    pub fn transaction<T>(
+        &mut self,
+        device: &mut I2cDevice,
+        f: impl FnOnce(&mut I2c<R>, u8) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_free()?;
+        Ok(f(&mut self.i2c, device.addr))
+    }
+
+    /// Start a DMA transfer for a device by running `f`. The bus is held by this device until
+    /// `end_dma` is called.
+    pub fn begin_dma<T>(
+        &mut self,
+        device: &mut I2cDevice,
+        f: impl FnOnce(&mut I2c<R>, u8) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_free()?;
+        self.arbiter.dma_owner = Some(device.id);
+
+        Ok(f(&mut self.i2c, device.addr))
+    }
+
+    /// End a DMA transfer started with `begin_dma`: Run `f`, and release the bus.
+    pub fn end_dma<T>(
+        &mut self,
+        device: &mut I2cDevice,
+        f: impl FnOnce(&mut I2c<R>) -> T,
+    ) -> Result<T, Error> {
+        self.arbiter.check_owner(device.id)?;
+
+        let result = f(&mut self.i2c);
+        self.arbiter.dma_owner = None;
+
+        Ok(result)
+    }
+
+    /// Returns `true` if a device holds the bus for a DMA transfer.
+    pub fn is_busy(&self) -> bool {
+        self.arbiter.dma_owner.is_some()
+    }
+}
+
+/// Wraps a `SpiBus` or `I2cBus` in a critical-section mutex, for sharing between the main loop
+/// and interrupt handlers without RTIC. Declare as a `static`, and populate with `init`:
+/// ```
+/// static SPI_BUS: SharedBus<SpiBus<SPI1>> = SharedBus::new();
+///
+/// SPI_BUS.init(SpiBus::new(spi));
+/// SPI_BUS.lock(|bus| bus.transaction(&mut imu, |spi| spi.write(&[0x4e_u8, 0x0f])));
+/// ```
+pub struct SharedBus<B> {
+    bus: Mutex<RefCell<Option<B>>>,
+}
+
+impl<B> SharedBus<B> {
+    pub const fn new() -> Self {
+        Self {
+            bus: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Place a bus in the mutex.
+    pub fn init(&self, bus: B) {
+        free(|cs| {
+            self.bus.borrow(cs).replace(Some(bus));
+        });
+    }
+
+    /// Run `f` with exclusive access to the bus, with interrupts disabled. Panics if `init`
+    /// hasn't been called.
+    pub fn lock<T>(&self, f: impl FnOnce(&mut B) -> T) -> T {
+        free(|cs| f(self.bus.borrow(cs).borrow_mut().as_mut().unwrap()))
+    }
+}
@@ -5,18 +5,16 @@
 // Similar in from to the H7 clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
+    clocks::{
+        lse_ready, setup_lse, wait_ready, ClockTree, KernelClockPeriph, LseConfig, SpeedError,
+        HSE_READY_POLLS,
+    },
     pac::{self, FLASH, RCC},
     util::rcc_en_reset,
 };
 
-#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "g4"))]
-use crate::pac::CRS;
-
 use cfg_if::cfg_if;
 
-// todo: WB is missing second LSI2, and perhaps other things.
-
 #[cfg(not(any(feature = "g0", feature = "wl")))]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -31,16 +29,6 @@ pub enum Clk48Src {
     Msi = 0b11,
 }
 
-#[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "g4"))]
-#[derive(Clone, Copy)]
-#[repr(u8)]
-/// Select the SYNC signal source. Sets the CRS_CFGR register, SYNCSRC field.
-pub enum CrsSyncSrc {
-    Gpio = 0b00,
-    Lse = 0b01,
-    Usb = 0b10,
-}
-
 #[cfg(not(any(feature = "g0", feature = "g4")))]
 #[derive(Clone, Copy, PartialEq)]
 pub enum PllSrc {
@@ -80,6 +68,19 @@ impl PllSrc {
     }
 }
 
+impl PllSrc {
+    /// The frequency of this source, in Hz. 0 for `None`.
+    fn freq(&self) -> u32 {
+        match self {
+            #[cfg(not(any(feature = "g0", feature = "g4")))]
+            Self::Msi(range) => range.value(),
+            Self::Hsi => 16_000_000,
+            Self::Hse(freq) => *freq,
+            Self::None => 0,
+        }
+    }
+}
+
 #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -186,6 +187,44 @@ enum WaitState {
     W5 = 5,
 }
 
+// Maximum SYSCLK, HCLK, and APB frequencies; and PLL limits: The VCO input (after PLLM) and
+// output (after PLLN) frequency ranges, in Hz, and the PLLN range. From the datasheets' PLL
+// characteristics tables.
+cfg_if! {
+    if #[cfg(feature = "l4")] {
+        // todo: L4+ (ie R, S, P, Q) can go up to 120_000.
+        const MAX_SYSCLK: u32 = 80_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (4_000_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (64_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (8, 86);
+    } else if #[cfg(feature = "l5")] {
+        const MAX_SYSCLK: u32 = 110_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (4_000_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (64_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (8, 86);
+    } else if #[cfg(feature = "g0")] {
+        const MAX_SYSCLK: u32 = 64_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (2_660_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (64_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (9, 86);
+    } else if #[cfg(feature = "g4")] {
+        const MAX_SYSCLK: u32 = 170_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (2_660_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (96_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (8, 127);
+    } else if #[cfg(feature = "wb")] {
+        const MAX_SYSCLK: u32 = 64_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (2_660_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (96_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (8, 86);
+    } else { // WL
+        const MAX_SYSCLK: u32 = 48_000_000;
+        const VCO_IN_RANGE: (u32, u32) = (2_660_000, 16_000_000);
+        const VCO_OUT_RANGE: (u32, u32) = (96_000_000, 344_000_000);
+        const PLLN_RANGE: (u8, u8) = (6, 127);
+    }
+}
+
 #[cfg(not(any(feature = "g0", feature = "g4")))]
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -228,6 +267,7 @@ impl MsiRange {
 
 /// Configures the speeds, and enable status of an individual PLL (PLL1, or SAIPLL). Note that the `enable`
 /// field has no effect for PLL1.
+#[derive(Clone)]
 pub struct PllCfg {
     /// Only relevant for PLLSAI1.
     pub enabled: bool,
@@ -421,6 +461,53 @@ impl Pllr {
     }
 }
 
+// The PLLM and PLLR (and PLLQ) values searched by `Clocks::with_sysclk`.
+#[cfg(not(any(feature = "l5", feature = "g4")))]
+const PLLM_DIVS: [Pllm; 8] = [
+    Pllm::Div1,
+    Pllm::Div2,
+    Pllm::Div3,
+    Pllm::Div4,
+    Pllm::Div5,
+    Pllm::Div6,
+    Pllm::Div7,
+    Pllm::Div8,
+];
+
+#[cfg(any(feature = "l5", feature = "g4"))]
+const PLLM_DIVS: [Pllm; 16] = [
+    Pllm::Div1,
+    Pllm::Div2,
+    Pllm::Div3,
+    Pllm::Div4,
+    Pllm::Div5,
+    Pllm::Div6,
+    Pllm::Div7,
+    Pllm::Div8,
+    Pllm::Div9,
+    Pllm::Div10,
+    Pllm::Div11,
+    Pllm::Div12,
+    Pllm::Div13,
+    Pllm::Div14,
+    Pllm::Div15,
+    Pllm::Div16,
+];
+
+#[cfg(any(feature = "g0", feature = "wb"))]
+const PLLR_DIVS: [Pllr; 7] = [
+    Pllr::Div2,
+    Pllr::Div3,
+    Pllr::Div4,
+    Pllr::Div5,
+    Pllr::Div6,
+    Pllr::Div7,
+    Pllr::Div8,
+];
+
+#[cfg(not(any(feature = "g0", feature = "wb")))]
+const PLLR_DIVS: [Pllr; 4] = [Pllr::Div2, Pllr::Div4, Pllr::Div6, Pllr::Div8];
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// Divisor for PLLP. Sets `PLLCFGR` reg, `PLLP` field.
@@ -527,9 +614,109 @@ pub enum SaiSrc {
     ExtClk = 0b11,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// USART and LPUART kernel clock source. Sets RCC_CCIPR register, USARTxSEL and LPUART1SEL fields.
+/// `Hsi16` and `Lse` keep the baud rate independent of SYSCLK changes, and can run in Stop mode.
+pub enum UsartSrc {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Hsi16 = 0b10,
+    Lse = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2C kernel clock source. Sets RCC_CCIPR register, I2CxSEL fields.
+pub enum I2cSrc {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Hsi16 = 0b10,
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LPTIM kernel clock source. Sets RCC_CCIPR register, LPTIMxSEL fields.
+/// `setup` enables the HSI16 and LSE if selected, but not the LSI; it can be enabled by RTC setup.
+pub enum LptimSrc {
+    Pclk = 0b00,
+    Lsi = 0b01,
+    Hsi16 = 0b10,
+    Lse = 0b11,
+}
+
+#[cfg(any(feature = "l4", feature = "l5", feature = "wb"))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source. Sets RCC_CCIPR register, ADCSEL field. Only used if the ADC is
+/// configured to use its asynchronous clock.
+pub enum AdcSrc {
+    None = 0b00,
+    /// PLLSAI1 "R" clock (PLLADC1CLK)
+    PllSai1R = 0b01,
+    #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+    /// PLLSAI2 "R" clock (PLLADC2CLK)
+    PllSai2R = 0b10,
+    #[cfg(feature = "wb")]
+    /// PLL "P" clock
+    Pllp = 0b10,
+    Sysclk = 0b11,
+}
+
+#[cfg(feature = "g4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source. Sets RCC_CCIPR register, ADC12SEL and ADC345SEL fields. Only used if
+/// the ADC is configured to use its asynchronous clock.
+pub enum AdcSrc {
+    None = 0b00,
+    /// PLL "P" clock
+    Pllp = 0b01,
+    Sysclk = 0b10,
+}
+
+#[cfg(feature = "g0")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source. Sets RCC_CCIPR register, ADCSEL field. Only used if the ADC is
+/// configured to use its asynchronous clock.
+pub enum AdcSrc {
+    Sysclk = 0b00,
+    /// PLL "P" clock
+    Pllp = 0b01,
+    Hsi16 = 0b10,
+}
+
+#[cfg(feature = "wl")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source. Sets RCC_CCIPR register, ADCSEL field. Only used if the ADC is
+/// configured to use its asynchronous clock.
+pub enum AdcSrc {
+    None = 0b00,
+    Hsi16 = 0b01,
+    /// PLL "P" clock
+    Pllp = 0b10,
+    Sysclk = 0b11,
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The input source for the system and peripheral clocks. Eg HSE, HSI, PLL etc
     pub input_src: InputSrc,
@@ -581,6 +768,68 @@ pub struct Clocks {
     /// Range 1 boost mode: Used to increase regulator voltage to 1.28v, for when system
     /// clock frequency is up to 170Mhz. Defaults to true.
     pub boost_mode: bool,
+    /// USART1 kernel clock source
+    pub usart1_src: UsartSrc,
+    #[cfg(not(any(feature = "wb", feature = "g030", feature = "g031", feature = "g041")))]
+    /// USART2 kernel clock source
+    pub usart2_src: UsartSrc,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    /// USART3 kernel clock source
+    pub usart3_src: UsartSrc,
+    #[cfg(any(
+        feature = "l4",
+        feature = "l5",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl",
+        feature = "g031",
+        feature = "g041",
+        feature = "g071",
+        feature = "g081",
+        feature = "g0b1",
+        feature = "g0c1",
+    ))]
+    /// LPUART1 kernel clock source. To receive in Stop mode, use `Hsi16` or `Lse`.
+    pub lpuart1_src: UsartSrc,
+    /// I2C1 kernel clock source
+    pub i2c1_src: I2cSrc,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wl"))]
+    /// I2C2 kernel clock source
+    pub i2c2_src: I2cSrc,
+    #[cfg(not(feature = "g0"))]
+    /// I2C3 kernel clock source
+    pub i2c3_src: I2cSrc,
+    /// ADC kernel clock source. On G4, this applies to all ADCs.
+    pub adc_src: AdcSrc,
+    #[cfg(any(
+        feature = "l4",
+        feature = "l5",
+        feature = "g4",
+        feature = "wb",
+        feature = "wl",
+        feature = "g031",
+        feature = "g041",
+        feature = "g071",
+        feature = "g081",
+        feature = "g0b1",
+        feature = "g0c1",
+    ))]
+    /// LPTIM1 kernel clock source
+    pub lptim1_src: LptimSrc,
+    #[cfg(any(
+        feature = "l4",
+        feature = "l5",
+        feature = "wb",
+        feature = "wl",
+        feature = "g031",
+        feature = "g041",
+        feature = "g071",
+        feature = "g081",
+        feature = "g0b1",
+        feature = "g0c1",
+    ))]
+    /// LPTIM2 kernel clock source
+    pub lptim2_src: LptimSrc,
 }
 
 // todo: On L4/5, add a way to enable the MSI for use as CLK48.
@@ -590,7 +839,29 @@ impl Clocks {
     /// are invalid.
     /// Use the STM32CubeIDE Clock Configuration tab to help identify valid configs.
     /// Use the `default()` implementation as a safe baseline.
+    /// Returns an error if the HSE is used, and doesn't become ready; eg if the crystal is missing.
+    /// This also enables and resets SYSCFG, which clears its EXTI line mappings; run it once, at
+    /// startup, and use `change_speed` to change clock speeds afterwards.
     pub fn setup(&self) -> Result<(), SpeedError> {
+        self.setup_rcc()?;
+
+        // Enable and reset System Configuration Controller, ie for interrupts.
+        // todo: Is this the right module to do this in?
+        // todo: Do interrupts work without enabling syscfg on wb, which
+        // todo doesn't have this?
+        #[cfg(not(any(feature = "wb", feature = "wl")))]
+        {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc_en_reset!(apb2, syscfg, rcc);
+        }
+
+        Ok(())
+    }
+
+    /// Validate the config, and apply it: Oscillators, PLL, prescalers, kernel clock sources, and
+    /// flash wait states. This doesn't touch SYSCFG, so is safe to run again at runtime;
+    /// `change_speed`, and `handle_hse_failure` use it.
+    pub(crate) fn setup_rcc(&self) -> Result<(), SpeedError> {
         if let Err(e) = self.validate_speeds() {
             return Err(e);
         }
@@ -598,12 +869,6 @@ impl Clocks {
         let rcc = unsafe { &(*RCC::ptr()) };
         let flash = unsafe { &(*FLASH::ptr()) };
 
-        // Enable and reset System Configuration Controller, ie for interrupts.
-        // todo: Is this the right module to do this in?
-        #[cfg(not(any(feature = "wb", feature = "wl")))] // todo: Do interrupts work without enabling syscfg on wb, which
-                                                         // todo doesn't have this?
-        rcc_en_reset!(apb2, syscfg, rcc);
-
         // Adjust flash wait states according to the HCLK frequency.
         // We need to do this before enabling PLL, or it won't enable.
         let sysclk = self.sysclk();
@@ -776,8 +1041,11 @@ impl Clocks {
             }
             InputSrc::Hse(_) => {
                 rcc.cr.modify(|_, w| w.hseon().set_bit());
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+                wait_ready(
+                    || rcc.cr.read().hserdy().bit_is_set(),
+                    HSE_READY_POLLS,
+                    "HSE failed to start",
+                )?;
             }
             InputSrc::Hsi => {
                 rcc.cr.modify(|_, w| w.hsion().set_bit());
@@ -798,7 +1066,11 @@ impl Clocks {
                     }
                     PllSrc::Hse(_) => {
                         rcc.cr.modify(|_, w| w.hseon().set_bit());
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                        wait_ready(
+                            || rcc.cr.read().hserdy().bit_is_set(),
+                            HSE_READY_POLLS,
+                            "HSE failed to start",
+                        )?;
                     }
                     PllSrc::Hsi => {
                         rcc.cr.modify(|_, w| w.hsion().set_bit());
@@ -853,7 +1125,7 @@ impl Clocks {
 
         rcc.cr.modify(|_, w| w.csson().bit(self.security_system));
 
-        #[cfg(any(feature = "l4", feature = "g4"))]
+        #[cfg(any(feature = "l4", feature = "g4", feature = "wb"))]
         rcc.ccipr
             .modify(|_, w| unsafe { w.clk48sel().bits(self.clk48_src as u8) });
 
@@ -998,6 +1270,87 @@ impl Clocks {
         rcc.ccipr2
             .modify(|_, w| unsafe { w.sai1sel().bits(self.sai1_src as u8) });
 
+        // Peripheral kernel clock selection. The HSI16 and LSE must be running for the peripherals
+        // that use them.
+        if self.hsi_kernel_used() {
+            rcc.cr.modify(|_, w| w.hsion().set_bit());
+            while rcc.cr.read().hsirdy().bit_is_clear() {}
+        }
+
+        if self.lse_kernel_used() && !lse_ready() {
+            setup_lse(&LseConfig::default())?;
+        }
+
+        #[cfg(feature = "l5")]
+        let ccipr = &rcc.ccipr1;
+        #[cfg(not(feature = "l5"))]
+        let ccipr = &rcc.ccipr;
+
+        ccipr.modify(|_, w| unsafe {
+            w.usart1sel().bits(self.usart1_src as u8);
+            #[cfg(not(any(
+                feature = "wb",
+                feature = "g030",
+                feature = "g031",
+                feature = "g041"
+            )))]
+            w.usart2sel().bits(self.usart2_src as u8);
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+            w.usart3sel().bits(self.usart3_src as u8);
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            w.lpuart1sel().bits(self.lpuart1_src as u8);
+            w.i2c1sel().bits(self.i2c1_src as u8);
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wl"))]
+            w.i2c2sel().bits(self.i2c2_src as u8);
+            #[cfg(not(feature = "g0"))]
+            w.i2c3sel().bits(self.i2c3_src as u8);
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            w.lptim1sel().bits(self.lptim1_src as u8);
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            w.lptim2sel().bits(self.lptim2_src as u8);
+            #[cfg(feature = "g4")]
+            w.adc12sel().bits(self.adc_src as u8);
+            #[cfg(feature = "g4")]
+            return w.adc345sel().bits(self.adc_src as u8);
+            #[cfg(not(feature = "g4"))]
+            w.adcsel().bits(self.adc_src as u8)
+        });
+
         // If we're not using the default clock source as input source or for PLL, turn it off.
         cfg_if! {
             if #[cfg(any(feature = "l4", feature = "l5"))] {
@@ -1019,6 +1372,8 @@ impl Clocks {
             } else {
                  match self.input_src {
                     InputSrc::Hsi => (),
+                    // Peripherals with HSI16 selected as their kernel clock need it on.
+                    _ if self.hsi_kernel_used() => (),
                     InputSrc::Pll(pll_src) => {
                         match pll_src {
                         PllSrc::Hsi => (),
@@ -1041,6 +1396,68 @@ impl Clocks {
         Ok(())
     }
 
+    /// Change clock speeds at runtime, eg to scale SYSCLK down when idle. Validates `new`, then
+    /// switches SYSCLK to the HSI while the oscillators, PLL, flash wait states, and (on G4) boost
+    /// mode are reconfigured. Since the HSI is slow enough to run with any wait state and voltage
+    /// setting, this keeps the transition glitch-free in both directions. Unlike `setup`, this
+    /// doesn't reset SYSCFG, so EXTI line mappings are kept. On success, `self` is updated to `new`.
+    /// Afterwards, run `Reclock::reclock` on peripherals that derive settings from the clock
+    /// speeds, eg `Usart`, `I2c`, and `Timer`.
+    ///
+    /// On L4 and L5, this exits low power run mode if active; re-enter it if required.
+    pub fn change_speed(&mut self, new: Self) -> Result<(), SpeedError> {
+        new.validate_speeds()?;
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // The HSI can't be selected in low power run mode, where SYSCLK is limited to 2Mhz.
+        #[cfg(any(feature = "l4", feature = "l5"))]
+        {
+            let pwr = unsafe { &(*pac::PWR::ptr()) };
+            if pwr.cr1.read().lpr().bit_is_set() {
+                crate::low_power::return_from_low_power_run();
+            }
+        }
+
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.sw().bits(InputSrc::Hsi.bits()) });
+        while rcc.cfgr.read().sws().bits() != InputSrc::Hsi.bits() {}
+
+        // `setup` only reconfigures the PLL if it's used; otherwise, turn it off to save power.
+        if !matches!(new.input_src, InputSrc::Pll(_)) {
+            rcc.cr.modify(|_, w| w.pllon().clear_bit());
+            while rcc.cr.read().pllrdy().bit_is_set() {}
+        }
+
+        // `setup` handles entering Range 1 boost mode, but not leaving it. G4 RM:
+        // "The sequence to switch from Range1 boost mode to Range1 normal mode is:
+        // 1. Configure and switch to new system frequency. 2. Set the R1MODE bit in the PWR_CR5 register."
+        // Since we're on the HSI, this is safe to do now.
+        #[cfg(feature = "g4")]
+        if !new.boost_mode {
+            let pwr = unsafe { &(*pac::PWR::ptr()) };
+            pwr.cr5.modify(|_, w| w.r1mode().set_bit());
+        }
+
+        new.setup_rcc()?;
+
+        while rcc.cfgr.read().sws().bits() != new.input_src.bits() {}
+
+        // `setup` leaves the HSI on for L4 and L5.
+        if !matches!(new.input_src, InputSrc::Hsi | InputSrc::Pll(PllSrc::Hsi))
+            && !new.hsi_kernel_used()
+        {
+            rcc.cr.modify(|_, w| w.hsion().clear_bit());
+        }
+
+        *self = new;
+
+        Ok(())
+    }
+
     /// Re-select input source; used after Stop and Standby modes, where the system reverts
     /// to MSI or HSI after wake.
     pub fn reselect_input(&self) {
@@ -1290,20 +1707,35 @@ impl Clocks {
             }
         } else if #[cfg(feature = "g4")] {
             pub fn usb(&self) -> u32 {
-                48_000_000 // Uses hsi48.
+                match self.clk48_src {
+                    Clk48Src::Hsi48 => 48_000_000,
+                    Clk48Src::Pllq => self.pllq(),
+                }
             }
         } else { // L4 and L5
             pub fn usb(&self) -> u32 {
                 match self.clk48_src {
                     Clk48Src::Hsi48 => 48_000_000,
                     Clk48Src::PllSai1 => unimplemented!(),
-                    Clk48Src::Pllq => unimplemented!(),
+                    Clk48Src::Pllq => self.pllq(),
                     Clk48Src::Msi => unimplemented!(),
                 }
             }
         }
     }
 
+    #[cfg(not(any(feature = "g0", feature = "wl")))]
+    /// Get the main PLL's Q output frequency, in Hz.
+    fn pllq(&self) -> u32 {
+        match self.input_src {
+            InputSrc::Pll(pll_src) => {
+                pll_src.freq() / self.pll.divm.value() as u32 * self.pll.divn as u32
+                    / self.pll.divq.value() as u32
+            }
+            _ => 0,
+        }
+    }
+
     /// Get the APB1 frequency, in hz
     pub fn apb1(&self) -> u32 {
         self.hclk() / self.apb1_prescaler.value() as u32
@@ -1384,28 +1816,7 @@ impl Clocks {
     }
 
     pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        #[cfg(feature = "l4")]
-        let max_clock = 80_000_000;
-
-        #[cfg(feature = "l5")]
-        let max_clock = 110_000_000;
-
-        #[cfg(feature = "g0")]
-        let max_clock = 64_000_000;
-
-        #[cfg(feature = "g4")]
-        let max_clock = 170_000_000;
-
-        #[cfg(feature = "wb")]
-        let max_clock = 64_000_000;
-
-        #[cfg(feature = "wl")]
-        let max_clock = 48_000_000;
-
-        // todo: Check valid PLL output range as well. You can use Cube, mousing over the PLL
-        // todo speed to find these.
-
-        // todo: L4+ (ie R, S, P, Q) can go up to 120_000.
+        let max_clock = MAX_SYSCLK;
 
         #[cfg(any(feature = "l4", feature = "l5", feature = "wb"))]
         if self.pll.divn < 7
@@ -1440,9 +1851,17 @@ impl Clocks {
             return Err(SpeedError::new("A PLL divider is out of limits"));
         }
 
-        // todo: on WB, input src / PlLM * plln Must be between 96 and 344 Mhz.
-        // todo; Cube will validate this. Others probably have a similar restriction.
-        // todo: Put this check here.
+        if let InputSrc::Pll(pll_src) = self.input_src {
+            let vco_in = pll_src.freq() / self.pll.divm.value() as u32;
+            if vco_in < VCO_IN_RANGE.0 || vco_in > VCO_IN_RANGE.1 {
+                return Err(SpeedError::new("PLL input (after PLLM) out of limits"));
+            }
+
+            let vco_out = vco_in * self.pll.divn as u32;
+            if vco_out < VCO_OUT_RANGE.0 || vco_out > VCO_OUT_RANGE.1 {
+                return Err(SpeedError::new("PLL VCO output out of limits"));
+            }
+        }
 
         // todo: QC these limits
         // todo: Note that this involves repeatedly calculating sysclk.
@@ -1470,6 +1889,312 @@ impl Clocks {
     }
 }
 
+impl Clocks {
+    /// Returns true if any peripheral kernel clock is set to the HSI16.
+    fn hsi_kernel_used(&self) -> bool {
+        let mut result = self.usart1_src == UsartSrc::Hsi16 || self.i2c1_src == I2cSrc::Hsi16;
+
+        #[cfg(not(any(feature = "wb", feature = "g030", feature = "g031", feature = "g041")))]
+        {
+            result |= self.usart2_src == UsartSrc::Hsi16;
+        }
+        #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+        {
+            result |= self.usart3_src == UsartSrc::Hsi16;
+        }
+        #[cfg(any(
+            feature = "l4",
+            feature = "l5",
+            feature = "g4",
+            feature = "wb",
+            feature = "wl",
+            feature = "g031",
+            feature = "g041",
+            feature = "g071",
+            feature = "g081",
+            feature = "g0b1",
+            feature = "g0c1",
+        ))]
+        {
+            result |= self.lpuart1_src == UsartSrc::Hsi16 || self.lptim1_src == LptimSrc::Hsi16;
+        }
+        #[cfg(any(
+            feature = "l4",
+            feature = "l5",
+            feature = "wb",
+            feature = "wl",
+            feature = "g031",
+            feature = "g041",
+            feature = "g071",
+            feature = "g081",
+            feature = "g0b1",
+            feature = "g0c1",
+        ))]
+        {
+            result |= self.lptim2_src == LptimSrc::Hsi16;
+        }
+        #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wl"))]
+        {
+            result |= self.i2c2_src == I2cSrc::Hsi16;
+        }
+        #[cfg(not(feature = "g0"))]
+        {
+            result |= self.i2c3_src == I2cSrc::Hsi16;
+        }
+        #[cfg(any(feature = "g0", feature = "wl"))]
+        {
+            result |= self.adc_src == AdcSrc::Hsi16;
+        }
+
+        result
+    }
+
+    /// Returns true if any peripheral kernel clock is set to the LSE.
+    fn lse_kernel_used(&self) -> bool {
+        #[allow(unused_mut)] // On G030, only USART1 can use the LSE.
+        let mut result = self.usart1_src == UsartSrc::Lse;
+
+        #[cfg(not(any(feature = "wb", feature = "g030", feature = "g031", feature = "g041")))]
+        {
+            result |= self.usart2_src == UsartSrc::Lse;
+        }
+        #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+        {
+            result |= self.usart3_src == UsartSrc::Lse;
+        }
+        #[cfg(any(
+            feature = "l4",
+            feature = "l5",
+            feature = "g4",
+            feature = "wb",
+            feature = "wl",
+            feature = "g031",
+            feature = "g041",
+            feature = "g071",
+            feature = "g081",
+            feature = "g0b1",
+            feature = "g0c1",
+        ))]
+        {
+            result |= self.lpuart1_src == UsartSrc::Lse || self.lptim1_src == LptimSrc::Lse;
+        }
+        #[cfg(any(
+            feature = "l4",
+            feature = "l5",
+            feature = "wb",
+            feature = "wl",
+            feature = "g031",
+            feature = "g041",
+            feature = "g071",
+            feature = "g081",
+            feature = "g0b1",
+            feature = "g0c1",
+        ))]
+        {
+            result |= self.lptim2_src == LptimSrc::Lse;
+        }
+
+        result
+    }
+
+    #[cfg(any(feature = "l5", feature = "g4"))]
+    /// The HSE frequency, if it's used as the input source or PLL source. Otherwise, 0.
+    fn hse_freq(&self) -> u32 {
+        match self.input_src {
+            InputSrc::Hse(freq) | InputSrc::Pll(PllSrc::Hse(freq)) => freq,
+            _ => 0,
+        }
+    }
+
+    /// The frequency input to the PLLs, after the PLLM divider. This is shared by PLLSAI1 and
+    /// PLLSAI2. 0 if the PLL isn't used.
+    fn pll_ref_freq(&self) -> u32 {
+        match self.input_src {
+            InputSrc::Pll(pll_src) => pll_src.freq() / self.pll.divm.value() as u32,
+            _ => 0,
+        }
+    }
+
+    #[cfg(not(any(feature = "l4", feature = "l5")))]
+    /// Get the main PLL's P output frequency, in Hz.
+    fn pllp(&self) -> u32 {
+        self.pll_ref_freq() * self.pll.divn as u32 / self.pll.pvalue() as u32
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "g4", feature = "wl")))]
+    /// Get PLLSAI1's R output frequency, in Hz.
+    fn pllsai1r(&self) -> u32 {
+        self.pll_ref_freq() * self.pllsai1.divn as u32 / self.pllsai1.divr.value() as u32
+    }
+}
+
+impl ClockTree for Clocks {
+    fn sysclk(&self) -> u32 {
+        Clocks::sysclk(self)
+    }
+
+    fn hclk(&self) -> u32 {
+        Clocks::hclk(self)
+    }
+
+    fn apb1(&self) -> u32 {
+        Clocks::apb1(self)
+    }
+
+    fn apb2(&self) -> u32 {
+        Clocks::apb2(self)
+    }
+
+    fn apb1_timer(&self) -> u32 {
+        Clocks::apb1_timer(self)
+    }
+
+    fn apb2_timer(&self) -> u32 {
+        Clocks::apb2_timer(self)
+    }
+
+    fn kernel_clock(&self, periph: KernelClockPeriph) -> u32 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        #[cfg(feature = "l5")]
+        let ccipr = rcc.ccipr1.read().bits();
+        #[cfg(not(feature = "l5"))]
+        let ccipr = rcc.ccipr.read().bits();
+
+        // The USART, LPUART and I2C selections have the same positions and encodings on all of
+        // these families. L4 RM, section 6.4.28: "00: PCLK selected as USART1 clock,
+        // 01: System clock (SYSCLK) selected as USART1 clock, 10: HSI16 clock selected as USART1
+        // clock, 11: LSE clock selected as USART1 clock". "00: PCLK selected as I2C1 clock,
+        // 01: System clock (SYSCLK) selected as I2C1 clock, 10: HSI16 clock selected as I2C1 clock"
+        let usart = |pclk: u32, pos: u8| match (ccipr >> pos) & 0b11 {
+            0b00 => pclk,
+            0b01 => self.sysclk(),
+            0b10 => 16_000_000,
+            _ => 32_768,
+        };
+
+        let i2c = |pos: u8| match (ccipr >> pos) & 0b11 {
+            0b00 => self.apb1(),
+            0b01 => self.sysclk(),
+            _ => 16_000_000,
+        };
+
+        // "00: PCLK selected as LPTIM1 clock, 01: LSI clock selected as LPTIM1 clock, 10: HSI16 clock
+        // selected as LPTIM1 clock, 11: LSE clock selected as LPTIM1 clock"
+        let lptim = |pos: u8| match (ccipr >> pos) & 0b11 {
+            0b00 => self.apb1(),
+            0b01 => 32_000,
+            0b10 => 16_000_000,
+            _ => 32_768,
+        };
+
+        match periph {
+            KernelClockPeriph::Usart1 => usart(self.apb2(), 0),
+            KernelClockPeriph::Usart2 => usart(self.apb1(), 2),
+            KernelClockPeriph::Usart3 => usart(self.apb1(), 4),
+            // On G0, these positions are used by CEC and LPUART2; on WL, by I2S2.
+            #[cfg(not(any(feature = "g0", feature = "wl")))]
+            KernelClockPeriph::Uart4 => usart(self.apb1(), 6),
+            #[cfg(not(any(feature = "g0", feature = "wl")))]
+            KernelClockPeriph::Uart5 => usart(self.apb1(), 8),
+            KernelClockPeriph::Lpuart1 => usart(self.apb1(), 10),
+            KernelClockPeriph::I2c1 => i2c(12),
+            // On G0, I2C2 and I2C3 are always clocked by PCLK.
+            #[cfg(not(feature = "g0"))]
+            KernelClockPeriph::I2c2 => i2c(14),
+            #[cfg(not(feature = "g0"))]
+            KernelClockPeriph::I2c3 => i2c(16),
+            // I2C4SEL is in CCIPR2, with the same encoding as the other I2C selections. On L4, the
+            // PAC doesn't include CCIPR2, so I2C4 falls through to PCLK1, its reset selection.
+            #[cfg(any(
+                feature = "l5",
+                feature = "g471",
+                feature = "g473",
+                feature = "g474",
+                feature = "g483",
+                feature = "g484",
+            ))]
+            KernelClockPeriph::I2c4 => match rcc.ccipr2.read().i2c4sel().bits() {
+                0b00 => self.apb1(),
+                0b01 => self.sysclk(),
+                _ => 16_000_000,
+            },
+            KernelClockPeriph::Spi1 => self.apb2(),
+            KernelClockPeriph::Lptim1 => lptim(18),
+            // On G4, this position is used by SAI1.
+            #[cfg(not(feature = "g4"))]
+            KernelClockPeriph::Lptim2 => lptim(20),
+            KernelClockPeriph::Adc => {
+                cfg_if! {
+                    // L4 RM: "00: No clock selected, 01: PLLSAI1 “R” clock (PLLADC1CLK) selected as
+                    // ADCs clock, 10: PLLSAI2 “R” clock (PLLADC2CLK) selected as ADCs clock,
+                    // 11: System clock selected as ADCs clock"
+                    if #[cfg(any(feature = "l4", feature = "l5", feature = "wb"))] {
+                        match (ccipr >> 28) & 0b11 {
+                            0b01 => self.pllsai1r(),
+                            #[cfg(any(feature = "l4x5", feature = "l4x6"))]
+                            0b10 => {
+                                self.pll_ref_freq() * self.pllsai2.divn as u32
+                                    / self.pllsai2.divr.value() as u32
+                            }
+                            // On WB, this selects PLL "P".
+                            #[cfg(feature = "wb")]
+                            0b10 => self.pllp(),
+                            0b11 => self.sysclk(),
+                            _ => 0,
+                        }
+                    } else if #[cfg(feature = "g4")] {
+                        // ADC12SEL. G4 RM: "00: No clock selected, 01: PLL “P” clock selected as
+                        // ADC1/2 clock, 10: System clock selected as ADC1/2 clock"
+                        match (ccipr >> 28) & 0b11 {
+                            0b01 => self.pllp(),
+                            0b10 => self.sysclk(),
+                            _ => 0,
+                        }
+                    } else if #[cfg(feature = "g0")] {
+                        // G0 RM: "00: System clock, 01: PLLPCLK, 10: HSI16"
+                        match (ccipr >> 30) & 0b11 {
+                            0b00 => self.sysclk(),
+                            0b01 => self.pllp(),
+                            0b10 => 16_000_000,
+                            _ => 0,
+                        }
+                    } else {
+                        // WL RM: "00: No clock selected, 01: HSI16, 10: PLLPCLK, 11: SYSCLK"
+                        match (ccipr >> 28) & 0b11 {
+                            0b01 => 16_000_000,
+                            0b10 => self.pllp(),
+                            0b11 => self.sysclk(),
+                            _ => 0,
+                        }
+                    }
+                }
+            }
+            #[cfg(any(feature = "l5", feature = "g4"))]
+            KernelClockPeriph::Fdcan => {
+                // G4 RM: "00: HSE clock selected as FDCAN clock, 01: PLL “Q” clock selected as
+                // FDCAN clock, 10: PCLK clock selected as FDCAN clock". On L5, 10 selects PLLSAI1 "P".
+                match (ccipr >> 24) & 0b11 {
+                    0b00 => self.hse_freq(),
+                    0b01 => self.pllq(),
+                    #[cfg(feature = "g4")]
+                    0b10 => self.apb1(),
+                    #[cfg(feature = "l5")]
+                    0b10 => {
+                        self.pll_ref_freq() * self.pllsai1.divn as u32
+                            / self.pllsai1.pvalue() as u32
+                    }
+                    _ => 0,
+                }
+            }
+            // QUADSPI and OCTOSPI are on AHB.
+            KernelClockPeriph::Qspi => self.hclk(),
+            // Everything else, including SPI2 and SPI3, is on APB1.
+            _ => self.apb1(),
+        }
+    }
+}
+
 impl Default for Clocks {
     /// This default configures clocks with a HSI, with system and peripheral clocks at full rated speed.
     /// All peripheral. Speeds -> L4: 80Mhz. L5: 110Mhz. G0: 64Mhz. G4: 170Mhz. WB: 64Mhz.
@@ -1505,47 +2230,398 @@ impl Default for Clocks {
             sai1_src: SaiSrc::Pllp,
             #[cfg(feature = "g4")]
             boost_mode: true,
+            usart1_src: UsartSrc::Pclk,
+            #[cfg(not(any(
+                feature = "wb",
+                feature = "g030",
+                feature = "g031",
+                feature = "g041"
+            )))]
+            usart2_src: UsartSrc::Pclk,
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+            usart3_src: UsartSrc::Pclk,
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            lpuart1_src: UsartSrc::Pclk,
+            i2c1_src: I2cSrc::Pclk,
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wl"))]
+            i2c2_src: I2cSrc::Pclk,
+            #[cfg(not(feature = "g0"))]
+            i2c3_src: I2cSrc::Pclk,
+            #[cfg(not(feature = "g0"))]
+            adc_src: AdcSrc::None,
+            #[cfg(feature = "g0")]
+            adc_src: AdcSrc::Sysclk,
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "g4",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            lptim1_src: LptimSrc::Pclk,
+            #[cfg(any(
+                feature = "l4",
+                feature = "l5",
+                feature = "wb",
+                feature = "wl",
+                feature = "g031",
+                feature = "g041",
+                feature = "g071",
+                feature = "g081",
+                feature = "g0b1",
+                feature = "g0c1",
+            ))]
+            lptim2_src: LptimSrc::Pclk,
         }
     }
 }
 
-#[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb"))]
-/// Enable the Clock Recovery System. L443 User manual:
-/// "The STM32L443xx devices embed a special block which allows automatic trimming of the
-/// internal 48 MHz oscillator to guarantee its optimal accuracy over the whole device
-/// operational range. This automatic trimming is based on the external synchronization signal,
-/// which could be either derived from USB SOF signalization, from LSE oscillator, from an
-/// external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
-/// startup it is also possible to combine automatic trimming with manual trimming action."
-/// Note: This is for HSI48 only. Note that the HSI will turn off after entering Stop or Standby.
-pub fn enable_crs(sync_src: CrsSyncSrc) {
-    let crs = unsafe { &(*CRS::ptr()) };
-    let rcc = unsafe { &(*RCC::ptr()) };
+/// PLL settings found by `Clocks::with_sysclk`.
+struct PllSolution {
+    divm: Pllm,
+    divn: u8,
+    divr: Pllr,
+    divq: Option<Pllr>,
+    sysclk: u32,
+}
 
-    // todo: CRSEN missing on l4x5 pac: https://github.com/stm32-rs/stm32-rs/issues/572
-    cfg_if! {
-        if #[cfg(feature = "l4x5")] {
-            let val = rcc.apb1enr1.read().bits();
-            rcc.apb1enr1.write(|w| unsafe { w.bits(val | (1 << 24)) });
-        } else {
-            rcc.apb1enr1.modify(|_, w| w.crsen().set_bit());
+/// Search PLLM, PLLN, and PLLR for the SYSCLK closest to `target` without exceeding it, within
+/// the VCO limits. If `usb` is true, only accept settings where a PLLQ divider produces exactly
+/// 48Mhz. Earlier (lower PLLM and PLLN) settings are preferred on ties; these have lower VCO
+/// speeds, which reduces power use. Only dividers that divide their input frequency exactly are
+/// used, so the speeds calculated from the config (eg by `Clocks::sysclk`) are exact.
+fn solve_pll(input_freq: u32, target: u32, usb: bool) -> Option<PllSolution> {
+    let mut best: Option<PllSolution> = None;
+
+    for divm in PLLM_DIVS {
+        if input_freq % divm.value() as u32 != 0 {
+            continue;
+        }
+
+        let vco_in = input_freq / divm.value() as u32;
+        if vco_in < VCO_IN_RANGE.0 || vco_in > VCO_IN_RANGE.1 {
+            continue;
+        }
+
+        for divn in PLLN_RANGE.0..=PLLN_RANGE.1 {
+            let vco_out = vco_in * divn as u32;
+            if vco_out < VCO_OUT_RANGE.0 || vco_out > VCO_OUT_RANGE.1 {
+                continue;
+            }
+
+            let divq = if usb {
+                match PLLR_DIVS
+                    .iter()
+                    .find(|q| vco_out == 48_000_000 * q.value() as u32)
+                {
+                    Some(q) => Some(*q),
+                    None => continue,
+                }
+            } else {
+                None
+            };
+
+            for divr in PLLR_DIVS {
+                let sysclk = vco_out / divr.value() as u32;
+                if sysclk > target || vco_out % divr.value() as u32 != 0 {
+                    continue;
+                }
+
+                if best.as_ref().map(|b| sysclk > b.sysclk).unwrap_or(true) {
+                    best = Some(PllSolution {
+                        divm,
+                        divn,
+                        divr,
+                        divq,
+                        sysclk,
+                    });
+                }
+            }
         }
     }
 
-    crs.cfgr
-        .modify(|_, w| unsafe { w.syncsrc().bits(sync_src as u8) });
+    best
+}
+
+impl Clocks {
+    /// Create a config that runs SYSCLK from the PLL, fed by `pll_src`, as close as possible to
+    /// `sysclk` (in Hz) without exceeding it. PLLM, PLLN, and PLLR are chosen within this
+    /// family's VCO input and output limits; other settings are as in `Default`. Returns an error
+    /// if `sysclk` is above the maximum, or no valid PLL settings exist.
+    pub fn with_sysclk(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, false)
+    }
+
+    #[cfg(not(any(feature = "g0", feature = "wl")))]
+    /// Similar to `with_sysclk`, but also sets PLLQ to exactly 48Mhz, and selects it as the
+    /// 48Mhz (USB) clock source. This lets USB run without the HSI48, eg from a crystal HSE.
+    pub fn with_sysclk_usb(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, true)
+    }
+
+    /// Create the config to switch to if the HSE fails, eg with `clocks::handle_hse_failure`:
+    /// This config, with the PLL fed by the HSI, and set to approximate this config's SYSCLK.
+    /// If the PLL can't do this within its limits, SYSCLK runs from the HSI directly. If the 48Mhz
+    /// clock is from PLLQ, and the HSI can't also produce 48Mhz there, it's switched to the HSI48.
+    /// Note that PLLSAI speeds will change, since they share the PLL source.
+    pub fn hse_fallback(&self) -> Self {
+        let mut result = self.clone();
+        result.security_system = false;
+        result.hse_bypass = false;
+
+        let target = self.sysclk();
+
+        cfg_if! {
+            if #[cfg(not(any(feature = "g0", feature = "wl")))] {
+                let usb = self.clk48_src == Clk48Src::Pllq;
+                let soln = if usb {
+                    solve_pll(PllSrc::Hsi.freq(), target, true)
+                } else {
+                    None
+                };
+
+                if usb && soln.is_none() {
+                    result.clk48_src = Clk48Src::Hsi48;
+                    result.hsi48_on = true;
+                }
+
+                let soln = soln.or_else(|| solve_pll(PllSrc::Hsi.freq(), target, false));
+            } else {
+                let soln = solve_pll(PllSrc::Hsi.freq(), target, false);
+            }
+        }
 
-    crs.cr.modify(|_, w| {
-        // Set autotrim enabled.
-        w.autotrimen().set_bit();
-        // Enable CRS
-        w.cen().set_bit()
+        match soln {
+            Some(s) => {
+                result.input_src = InputSrc::Pll(PllSrc::Hsi);
+                result.pll.divm = s.divm;
+                result.pll.divn = s.divn;
+                result.pll.divr = s.divr;
+                if let Some(divq) = s.divq {
+                    result.pll.divq = divq;
+                    result.pll.pllq_en = true;
+                }
+            }
+            None => result.input_src = InputSrc::Hsi,
+        }
+
+        result
+    }
+
+    fn from_pll_search(sysclk: u32, pll_src: PllSrc, usb: bool) -> Result<Self, SpeedError> {
+        if sysclk > MAX_SYSCLK {
+            return Err(SpeedError::new("Requested sysclk is above the maximum"));
+        }
+
+        let input_freq = pll_src.freq();
+        if input_freq == 0 {
+            return Err(SpeedError::new("A PLL source must be selected"));
+        }
+
+        let soln = match solve_pll(input_freq, sysclk, usb) {
+            Some(s) => s,
+            None if usb => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce both this sysclk and a 48Mhz PLLQ",
+                ))
+            }
+            None => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce this sysclk within the VCO limits",
+                ))
+            }
+        };
+
+        let mut result = Self {
+            input_src: InputSrc::Pll(pll_src),
+            pll: PllCfg {
+                divm: soln.divm,
+                divn: soln.divn,
+                divr: soln.divr,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        if let Some(divq) = soln.divq {
+            result.pll.divq = divq;
+            result.pll.pllq_en = true;
+            #[cfg(not(any(feature = "g0", feature = "wl")))]
+            {
+                result.clk48_src = Clk48Src::Pllq;
+            }
+        }
+
+        result.validate_speeds()?;
+        Ok(result)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO pin. Sets RCC_CFGR register, MCOSEL field.
+pub enum McoSrc {
+    Disabled = 0b0000,
+    Sysclk = 0b0001,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "wb", feature = "wl"))]
+    Msi = 0b0010,
+    #[cfg(any(feature = "g0b1", feature = "g0c1"))]
+    Hsi48 = 0b0010,
+    Hsi16 = 0b0011,
+    Hse = 0b0100,
+    /// The main PLL's system clock output. (PLLCLK or PLLRCLK)
+    Pll = 0b0101,
+    #[cfg(not(feature = "wb"))]
+    Lsi = 0b0110,
+    #[cfg(feature = "wb")]
+    Lsi1 = 0b0110,
+    #[cfg(feature = "wb")]
+    Lsi2 = 0b0111,
+    #[cfg(not(any(feature = "wb", feature = "wl")))]
+    Lse = 0b0111,
+    #[cfg(any(feature = "wb", feature = "wl"))]
+    Lse = 0b1000,
+    #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+    Hsi48 = 0b1000,
+    #[cfg(feature = "wb")]
+    Hsi48 = 0b1001,
+    #[cfg(feature = "wl")]
+    Pllp = 0b1101,
+    #[cfg(feature = "wl")]
+    Pllq = 0b1110,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The division applied to the MCO output. Sets RCC_CFGR register, MCOPRE field.
+pub enum McoDiv {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+}
+
+/// Output a clock on the MCO pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. Set the MCO pin (eg PA8) to alternate function 0 to use it.
+pub fn setup_mco(src: McoSrc, div: McoDiv) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    // We use raw bits, since the MCOPRE field is missing from some PACs.
+    rcc.cfgr.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b111_1111 << 24) | (div as u32) << 28 | (src as u32) << 24)
     });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "l4", feature = "g4"))]
+    /// Check the PLL dividers `with_sysclk` picked, and the resulting SYSCLK.
+    fn assert_pll(clocks: &Clocks, divm: u8, divn: u8, divr: u8, sysclk: u32) {
+        assert_eq!(clocks.pll.divm.value(), divm);
+        assert_eq!(clocks.pll.divn, divn);
+        assert_eq!(clocks.pll.divr.value(), divr);
+        assert_eq!(clocks.sysclk(), sysclk);
+    }
+
+    #[cfg(feature = "l4")]
+    #[test]
+    fn solve_pll_l4() {
+        // 8Mhz HSE / 1 * 20 / 2 = 80Mhz.
+        let clocks = Clocks::with_sysclk(80_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_pll(&clocks, 1, 20, 2, 80_000_000);
+
+        // 16Mhz HSI / 1 * 10 / 2 = 80Mhz.
+        let clocks = Clocks::with_sysclk(80_000_000, PllSrc::Hsi).unwrap();
+        assert_pll(&clocks, 1, 10, 2, 80_000_000);
+
+        // 25Mhz / 3 isn't an integer, and no PLLN multiplies the 12.5 or 6.25Mhz from PLLM 2 and 4
+        // to a multiple of 80Mhz, so PLLM is 5: 25Mhz / 5 * 32 / 2 = 80Mhz.
+        let clocks = Clocks::with_sysclk(80_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 5, 32, 2, 80_000_000);
+
+        // The VCO must be a multiple of 48Mhz for PLLQ; the fastest such SYSCLK is
+        // 288Mhz / 4 = 72Mhz, with PLLQ = 288Mhz / 6.
+        let clocks = Clocks::with_sysclk_usb(80_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_pll(&clocks, 1, 36, 4, 72_000_000);
+        assert_eq!(clocks.pll.divq.value(), 6);
+        assert!(clocks.pll.pllq_en);
+    }
+
+    #[cfg(feature = "g4")]
+    #[test]
+    fn solve_pll_g4() {
+        // 170Mhz needs a 340Mhz VCO, which 8Mhz doesn't divide: 8Mhz HSE / 2 * 85 / 2.
+        let clocks = Clocks::with_sysclk(170_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_pll(&clocks, 2, 85, 2, 170_000_000);
+
+        // 16Mhz HSI / 4 * 85 / 2 = 170Mhz.
+        let clocks = Clocks::with_sysclk(170_000_000, PllSrc::Hsi).unwrap();
+        assert_pll(&clocks, 4, 85, 2, 170_000_000);
+
+        // 24Mhz / 5 isn't an integer, and 12, 8 and 6Mhz don't divide 340Mhz: 24Mhz / 6 * 85 / 2.
+        let clocks = Clocks::with_sysclk(170_000_000, PllSrc::Hse(24_000_000)).unwrap();
+        assert_pll(&clocks, 6, 85, 2, 170_000_000);
+
+        // 25Mhz is above the VCO input limit, 25Mhz / 3 isn't an integer, and 340Mhz isn't a
+        // multiple of the 12.5 or 6.25Mhz from PLLM 2 and 4.
+        let clocks = Clocks::with_sysclk(170_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 5, 68, 2, 170_000_000);
+
+        // Below the maximum: 16Mhz HSI / 1 * 9 / 2 = 72Mhz. A 144Mhz VCO is the lowest with
+        // PLLM = 1 that produces 72Mhz.
+        let clocks = Clocks::with_sysclk(72_000_000, PllSrc::Hsi).unwrap();
+        assert_pll(&clocks, 1, 9, 2, 72_000_000);
+    }
+
+    #[test]
+    fn with_sysclk() {
+        let clocks = Clocks::with_sysclk(MAX_SYSCLK, PllSrc::Hsi).unwrap();
+        assert_eq!(clocks.sysclk(), MAX_SYSCLK);
+
+        assert!(Clocks::with_sysclk(MAX_SYSCLK + 1, PllSrc::Hsi).is_err());
+        assert!(Clocks::with_sysclk(MAX_SYSCLK, PllSrc::None).is_err());
+    }
+
+    #[test]
+    fn hse_fallback() {
+        for hse in [8_000_000, 24_000_000, 32_000_000] {
+            let clocks = Clocks::with_sysclk(MAX_SYSCLK, PllSrc::Hse(hse)).unwrap();
+            let fallback = clocks.hse_fallback();
+            assert!(fallback.validate_speeds().is_ok());
+            assert!(matches!(fallback.input_src, InputSrc::Pll(PllSrc::Hsi)));
+            assert_eq!(fallback.sysclk(), clocks.sysclk());
+        }
 
-    // "The internal 48 MHz RC oscillator is mainly dedicated to provide a high precision clock to
-    // the USB peripheral by means of a special Clock Recovery System (CRS) circuitry. The CRS
-    // can use the USB SOF signal, the LSE or an external signal to automatically and quickly
-    // adjust the oscillator frequency on-fly. It is disabled as soon as the system enters Stop or
-    // Standby mode. When the CRS is not used, the HSI48 RC oscillator runs on its default
-    // frequency which is subject to manufacturing process variations
+        // USB stays on PLLQ, since the HSI can produce both 48Mhz and this SYSCLK.
+        #[cfg(not(any(feature = "g0", feature = "wl")))]
+        {
+            let clocks = Clocks::with_sysclk_usb(MAX_SYSCLK, PllSrc::Hse(8_000_000)).unwrap();
+            let fallback = clocks.hse_fallback();
+            assert!(fallback.validate_speeds().is_ok());
+            assert!(fallback.clk48_src == Clk48Src::Pllq);
+            assert_eq!(fallback.usb(), 48_000_000);
+            assert_eq!(fallback.sysclk(), clocks.sysclk());
+        }
+    }
 }
@@ -0,0 +1,356 @@
+//! Clock Recovery System (CRS) for the HSI48. L443 RM:
+//! "The STM32L443xx devices embed a special block which allows automatic trimming of the
+//! internal 48 MHz oscillator to guarantee its optimal accuracy over the whole device
+//! operational range. This automatic trimming is based on the external synchronization signal,
+//! which could be either derived from USB SOF signalization, from LSE oscillator, from an
+//! external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
+//! startup it is also possible to combine automatic trimming with manual trimming action."
+//!
+//! The HSI48 must be enabled separately, eg with the `Clocks` struct. Note that the HSI48 will
+//! turn off after entering Stop or Standby.
+
+use crate::{
+    clocks::SpeedError,
+    pac::{CRS, RCC},
+};
+
+use cfg_if::cfg_if;
+
+/// The HSI48 frequency the CRS trims towards, in Hz.
+const HSI48_FREQ: u32 = 48_000_000;
+
+/// The HSI48 trimming step, in hundredths of a percent. L4 RM: "The trimming step is around 0.14%".
+const TRIM_STEP: u32 = 14;
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Select the SYNC signal source. Sets the CRS_CFGR register, SYNCSRC field.
+pub enum CrsSyncSrc {
+    /// CRS_SYNC pin
+    Gpio = 0b00,
+    Lse = 0b01,
+    /// USB SOF. On H743, this is USB2 OTG FS. On H723-735, it's USB1 OTG HS.
+    Usb = 0b10,
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// Division factor applied to the SYNC signal. Sets the CRS_CFGR register, SYNCDIV field.
+pub enum CrsSyncDiv {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    Div32 = 0b101,
+    Div64 = 0b110,
+    Div128 = 0b111,
+}
+
+impl CrsSyncDiv {
+    pub fn value(&self) -> u32 {
+        1 << (*self as u8)
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(u8)]
+/// The SYNC signal edge that triggers synchronization. Sets the CRS_CFGR register, SYNCPOL field.
+pub enum CrsSyncPolarity {
+    Rising = 0,
+    Falling = 1,
+}
+
+#[derive(Clone, Copy)]
+/// CRS interrupts. Sets the CRS_CR register, xxxIE fields.
+pub enum CrsInterrupt {
+    /// SYNC event OK
+    SyncOk,
+    /// SYNC warning
+    SyncWarn,
+    /// Synchronization or trimming error: SYNCERR, SYNCMISS or TRIMOVF.
+    Error,
+    /// Expected SYNC
+    ExpectedSync,
+}
+
+/// CRS configuration. Apply with `setup_crs`.
+#[derive(Clone)]
+pub struct CrsConfig {
+    pub sync_src: CrsSyncSrc,
+    pub sync_div: CrsSyncDiv,
+    pub sync_polarity: CrsSyncPolarity,
+    /// The counter reload value, for the frequency error counter: (HSI48 freq / SYNC freq) - 1.
+    /// Sets the CRS_CFGR register, RELOAD field. 47,999 for 1kHz USB SOF.
+    pub reload: u16,
+    /// The frequency error limit, used to evaluate the frequency error. Sets the CRS_CFGR
+    /// register, FELIM field. 34 for 1kHz USB SOF.
+    pub felim: u8,
+    /// Automatically adjust the HSI48 trim from the frequency error. If `false`, trim manually
+    /// with `crs_set_trim`, eg using `crs_status` readings.
+    pub autotrim: bool,
+}
+
+impl CrsConfig {
+    /// Create a config for a 1kHz SYNC signal, eg USB SOF. These are the reset values, other
+    /// than the sync source.
+    pub fn new(sync_src: CrsSyncSrc) -> Self {
+        Self {
+            sync_src,
+            sync_div: CrsSyncDiv::Div1,
+            sync_polarity: CrsSyncPolarity::Rising,
+            reload: 47_999,
+            felim: 34,
+            autotrim: true,
+        }
+    }
+
+    /// Create a config for a SYNC signal of `sync_freq` Hz, before `sync_div`; eg a 1kHz signal on
+    /// the CRS_SYNC pin, or a divided LSE. Sets `reload` and `felim` with `crs_reload_felim`.
+    pub fn with_sync_freq(
+        sync_src: CrsSyncSrc,
+        sync_freq: u32,
+        sync_div: CrsSyncDiv,
+    ) -> Result<Self, SpeedError> {
+        let (reload, felim) = crs_reload_felim(sync_freq / sync_div.value())?;
+
+        Ok(Self {
+            sync_div,
+            reload,
+            felim,
+            ..Self::new(sync_src)
+        })
+    }
+}
+
+/// Calculate the RELOAD and FELIM values for a SYNC frequency, after the SYNC divider, in Hz.
+/// This doesn't access any registers. L4 RM, section 7.2: "RELOAD = (fTARGET / fSYNC) - 1",
+/// and "FELIM = (fTARGET / fSYNC) * STEP[%] / 100% / 2. The result should always be rounded up
+/// to the nearest integer value to obtain the best trimming response." Returns an error if the
+/// SYNC frequency is too low for RELOAD to fit in 16 bits (about 733Hz), or too high to trim.
+pub fn crs_reload_felim(sync_freq: u32) -> Result<(u16, u8), SpeedError> {
+    if sync_freq == 0 {
+        return Err(SpeedError::new("CRS SYNC frequency can't be 0"));
+    }
+
+    // Round to the nearest whole number of HSI48 cycles per SYNC period.
+    let cycles = (HSI48_FREQ + sync_freq / 2) / sync_freq;
+    if cycles > u16::MAX as u32 + 1 {
+        return Err(SpeedError::new("CRS SYNC frequency is too low"));
+    }
+
+    let felim = (cycles * TRIM_STEP + 19_999) / 20_000;
+    if felim == 0 {
+        return Err(SpeedError::new("CRS SYNC frequency is too high"));
+    }
+
+    Ok(((cycles - 1) as u16, felim as u8))
+}
+
+/// Enable the CRS clock. Sets RCC_APB1ENR1 register, CRSEN field (RCC_APB1HENR on H7).
+fn enable_crs_clock() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    // todo: CRSEN missing on l4x5 pac: https://github.com/stm32-rs/stm32-rs/issues/572
+    cfg_if! {
+        if #[cfg(feature = "h7")] {
+            rcc.apb1henr.modify(|_, w| w.crsen().set_bit());
+        } else if #[cfg(feature = "l4x5")] {
+            let val = rcc.apb1enr1.read().bits();
+            rcc.apb1enr1.write(|w| unsafe { w.bits(val | (1 << 24)) });
+        } else {
+            rcc.apb1enr1.modify(|_, w| w.crsen().set_bit());
+        }
+    }
+}
+
+/// Configure and enable the CRS. The CRS is stopped while applying the configuration, since
+/// CRS_CFGR can't be written while it's enabled.
+pub fn setup_crs(cfg: &CrsConfig) {
+    let crs = unsafe { &(*CRS::ptr()) };
+
+    enable_crs_clock();
+
+    crs.cr.modify(|_, w| w.cen().clear_bit());
+
+    crs.cfgr.modify(|_, w| unsafe {
+        w.syncsrc().bits(cfg.sync_src as u8);
+        w.syncdiv().bits(cfg.sync_div as u8);
+        w.syncpol().bit(cfg.sync_polarity as u8 != 0);
+        w.reload().bits(cfg.reload);
+        w.felim().bits(cfg.felim)
+    });
+
+    crs.cr.modify(|_, w| {
+        w.autotrimen().bit(cfg.autotrim);
+        w.cen().set_bit()
+    });
+
+    // "The internal 48 MHz RC oscillator is mainly dedicated to provide a high precision clock to
+    // the USB peripheral by means of a special Clock Recovery System (CRS) circuitry. The CRS
+    // can use the USB SOF signal, the LSE or an external signal to automatically and quickly
+    // adjust the oscillator frequency on-fly. It is disabled as soon as the system enters Stop or
+    // Standby mode. When the CRS is not used, the HSI48 RC oscillator runs on its default
+    // frequency which is subject to manufacturing process variations
+}
+
+/// Enable the Clock Recovery System with autotrim, for a 1kHz SYNC signal such as USB SOF.
+/// For other SYNC frequencies, or manual trimming, use `setup_crs`.
+pub fn enable_crs(sync_src: CrsSyncSrc) {
+    setup_crs(&CrsConfig::new(sync_src));
+}
+
+/// Disable the CRS. The HSI48 keeps its current trim value.
+pub fn disable_crs() {
+    let crs = unsafe { &(*CRS::ptr()) };
+    crs.cr.modify(|_, w| w.cen().clear_bit());
+}
+
+/// Generate a SYNC event from software. Sets the CRS_CR register, SWSYNC field.
+pub fn crs_sync_software() {
+    let crs = unsafe { &(*CRS::ptr()) };
+
+    // SWSYNC is missing from the H7 PAC.
+    #[cfg(feature = "h7")]
+    crs.cr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 7)) });
+    #[cfg(not(feature = "h7"))]
+    crs.cr.modify(|_, w| w.swsync().set_bit());
+}
+
+/// Read the HSI48 trim value, from the CRS_CR register, TRIM field.
+pub fn crs_trim() -> u8 {
+    let crs = unsafe { &(*CRS::ptr()) };
+    crs.cr.read().trim().bits()
+}
+
+/// Set the HSI48 trim value manually. This is 0 to 63, or 0 to 127 on L5 and G4; the middle of
+/// the range is the default. If autotrim is enabled, this is overwritten by the CRS on the next
+/// SYNC event.
+pub fn crs_set_trim(trim: u8) {
+    let crs = unsafe { &(*CRS::ptr()) };
+    crs.cr.modify(|_, w| unsafe { w.trim().bits(trim) });
+}
+
+/// CRS synchronization status, read from the CRS_ISR register.
+#[derive(Clone, Copy, Debug)]
+pub struct CrsStatus {
+    /// SYNCOKF: The frequency error is below FELIM at the SYNC event.
+    pub sync_ok: bool,
+    /// SYNCWARNF: The frequency error is between FELIM and 3 x FELIM, and a trimming step
+    /// was made.
+    pub sync_warn: bool,
+    /// SYNCERR: The frequency error exceeded 128 x FELIM. Autotrim doesn't correct this.
+    pub sync_err: bool,
+    /// SYNCMISS: The SYNC signal is missing, or the frequency error exceeded 128 x FELIM before
+    /// it arrived.
+    pub sync_miss: bool,
+    /// TRIMOVF: Autotrim reached the trim limit.
+    pub trim_overflow: bool,
+    /// ESYNCF: The frequency error counter reached 0.
+    pub expected_sync: bool,
+    /// FEDIR: `true` if the HSI48 is below the target frequency, ie it needs to be trimmed up.
+    pub slow: bool,
+    /// FECAP: The frequency error counter value captured at the last SYNC event.
+    pub error_capture: u16,
+}
+
+impl CrsStatus {
+    /// Returns true if a SYNCERR, SYNCMISS or TRIMOVF error is flagged.
+    pub fn is_error(&self) -> bool {
+        self.sync_err || self.sync_miss || self.trim_overflow
+    }
+}
+
+/// Read the CRS synchronization status.
+pub fn crs_status() -> CrsStatus {
+    let crs = unsafe { &(*CRS::ptr()) };
+    let isr = crs.isr.read();
+
+    CrsStatus {
+        sync_ok: isr.syncokf().bit_is_set(),
+        sync_warn: isr.syncwarnf().bit_is_set(),
+        sync_err: isr.syncerr().bit_is_set(),
+        sync_miss: isr.syncmiss().bit_is_set(),
+        trim_overflow: isr.trimovf().bit_is_set(),
+        expected_sync: isr.esyncf().bit_is_set(),
+        slow: isr.fedir().bit_is_set(),
+        error_capture: isr.fecap().bits(),
+    }
+}
+
+/// Clear all CRS status flags. Clearing the error flag clears SYNCERR, SYNCMISS and TRIMOVF.
+pub fn crs_clear_status() {
+    let crs = unsafe { &(*CRS::ptr()) };
+
+    crs.icr.write(|w| {
+        w.syncokc().set_bit();
+        w.syncwarnc().set_bit();
+        w.errc().set_bit();
+        w.esyncc().set_bit()
+    });
+}
+
+/// Enable a CRS interrupt.
+pub fn crs_enable_interrupt(interrupt: CrsInterrupt) {
+    let crs = unsafe { &(*CRS::ptr()) };
+
+    crs.cr.modify(|_, w| match interrupt {
+        CrsInterrupt::SyncOk => w.syncokie().set_bit(),
+        CrsInterrupt::SyncWarn => w.syncwarnie().set_bit(),
+        CrsInterrupt::Error => w.errie().set_bit(),
+        CrsInterrupt::ExpectedSync => w.esyncie().set_bit(),
+    });
+}
+
+/// Clear a CRS interrupt flag.
+pub fn crs_clear_interrupt(interrupt: CrsInterrupt) {
+    let crs = unsafe { &(*CRS::ptr()) };
+
+    crs.icr.write(|w| match interrupt {
+        CrsInterrupt::SyncOk => w.syncokc().set_bit(),
+        CrsInterrupt::SyncWarn => w.syncwarnc().set_bit(),
+        CrsInterrupt::Error => w.errc().set_bit(),
+        CrsInterrupt::ExpectedSync => w.esyncc().set_bit(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_sof() {
+        // 1kHz USB SOF: The RM's example values.
+        assert_eq!(crs_reload_felim(1_000).unwrap(), (47_999, 34));
+
+        let cfg = CrsConfig::new(CrsSyncSrc::Usb);
+        assert_eq!((cfg.reload, cfg.felim), (47_999, 34));
+    }
+
+    #[test]
+    fn lse() {
+        // 48Mhz / 32.768kHz = 1464.8 cycles, rounded to 1465.
+        assert_eq!(crs_reload_felim(32_768).unwrap(), (1_464, 2));
+
+        let cfg = CrsConfig::with_sync_freq(CrsSyncSrc::Lse, 32_768, CrsSyncDiv::Div1).unwrap();
+        assert_eq!((cfg.reload, cfg.felim), (1_464, 2));
+
+        // Divided by 32: 1.024kHz.
+        let cfg = CrsConfig::with_sync_freq(CrsSyncSrc::Lse, 32_768, CrsSyncDiv::Div32).unwrap();
+        assert_eq!((cfg.reload, cfg.felim), (46_874, 33));
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(crs_reload_felim(0).is_err());
+
+        // RELOAD must fit in 16 bits.
+        assert!(crs_reload_felim(732).is_err());
+        assert_eq!(crs_reload_felim(733).unwrap(), (65_483, 46));
+        assert!(CrsConfig::with_sync_freq(CrsSyncSrc::Lse, 32_768, CrsSyncDiv::Div64).is_err());
+
+        // At least one HSI48 cycle per SYNC period.
+        assert_eq!(crs_reload_felim(48_000_000).unwrap(), (0, 1));
+        assert!(crs_reload_felim(96_000_001).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! Clock security system (CSS) handling. When the CSS is enabled with `Clocks::security_system`,
+//! a failure of the HSE switches SYSCLK to the HSI (or MSI on some families) in hardware, and
+//! triggers a non-maskable interrupt (NMI). Call `handle_hse_failure` from the NMI handler to
+//! rebuild the clock tree from the HSI, approximating the original SYSCLK.
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use crate::{
+    clocks::{Clocks, SpeedError},
+    pac::RCC,
+};
+
+static HSE_FAILURE_CALLBACK: Mutex<Cell<Option<fn(&Clocks)>>> = Mutex::new(Cell::new(None));
+
+/// Set a function to run when `handle_hse_failure` has applied the fallback configuration. It's
+/// passed the new (degraded) clock config, eg to re-calculate peripheral baud rates.
+pub fn set_hse_failure_callback(callback: fn(&Clocks)) {
+    free(|cs| HSE_FAILURE_CALLBACK.borrow(cs).set(Some(callback)));
+}
+
+/// Check if the CSS has detected a HSE failure. Use this in the NMI handler to determine its cause.
+pub fn hse_failure_detected() -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "f3", feature = "f4"))] {
+            rcc.cir.read().cssf().bit_is_set()
+        } else if #[cfg(any(feature = "wb", feature = "h7"))] {
+            rcc.cifr.read().hsecssf().bit_is_set()
+        } else {
+            rcc.cifr.read().cssf().bit_is_set()
+        }
+    }
+}
+
+/// Clear the HSE CSS flag. This must be done in the NMI handler, or the NMI will re-trigger.
+pub fn clear_hse_failure() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(feature = "f3", feature = "f4"))] {
+            rcc.cir.modify(|_, w| w.cssc().set_bit());
+        } else if #[cfg(any(feature = "wb", feature = "h7"))] {
+            rcc.cicr.write(|w| w.hsecssc().set_bit());
+        } else {
+            rcc.cicr.write(|w| w.cssc().set_bit());
+        }
+    }
+}
+
+/// Handle a HSE failure detected by the CSS: Clear the CSS flag, set up the clock tree from
+/// the HSI using `Clocks::hse_fallback`, and run the callback set with `set_hse_failure_callback`.
+/// Returns the new clock config, which reflects the degraded state; use it in place of the
+/// original for further clock queries. Call this from the NMI handler. The CSS is left disabled;
+/// it can only be re-enabled after a reset.
+///
+/// Example:
+/// ```rust
+/// #[exception]
+/// fn NonMaskableInt() {
+///     if clocks::hse_failure_detected() {
+///         let degraded = clocks::handle_hse_failure(&CLOCK_CFG).unwrap();
+///     }
+/// }
+/// ```
+pub fn handle_hse_failure(clocks: &Clocks) -> Result<Clocks, SpeedError> {
+    clear_hse_failure();
+
+    let fallback = clocks.hse_fallback();
+    // Not `setup`, which would reset SYSCFG, and lose the EXTI line mappings.
+    fallback.setup_rcc()?;
+
+    if let Some(callback) = free(|cs| HSE_FAILURE_CALLBACK.borrow(cs).get()) {
+        callback(&fallback);
+    }
+
+    Ok(fallback)
+}
@@ -1,9 +1,12 @@
 use crate::{
-    clocks::SpeedError,
+    clocks::{wait_ready, ClockTree, KernelClockPeriph, SpeedError, HSE_READY_POLLS},
     pac::{self, FLASH, RCC},
     util::rcc_en_reset,
 };
 
+#[cfg(feature = "f3")]
+use crate::clocks::{lse_ready, setup_lse, LseConfig};
+
 use cfg_if::cfg_if;
 
 cfg_if! {
@@ -71,6 +74,43 @@ cfg_if! {
    }
 }
 
+impl PllSrc {
+    /// The frequency of this source, in Hz. On F3, this includes the HSI's fixed /2 divider.
+    fn freq(&self) -> u32 {
+        match self {
+            #[cfg(feature = "f3")]
+            Self::HsiDiv2 => 4_000_000,
+            #[cfg(feature = "f4")]
+            Self::Hsi => 16_000_000,
+            Self::Hse(freq) => *freq,
+        }
+    }
+}
+
+// Maximum SYSCLK, and PLL limits: The PLL (VCO on F4) input and output frequency ranges, in
+// Hz. From the datasheets' PLL characteristics tables.
+cfg_if! {
+    if #[cfg(feature = "f3")] {
+        const MAX_SYSCLK: u32 = 72_000_000;
+        const PLL_IN_RANGE: (u32, u32) = (1_000_000, 24_000_000);
+        const PLL_OUT_RANGE: (u32, u32) = (16_000_000, 72_000_000);
+    } else {
+        cfg_if! {
+            if #[cfg(feature = "f401")] {
+                const MAX_SYSCLK: u32 = 84_000_000;
+            } else if #[cfg(any(feature = "f410", feature = "f411", feature = "f412", feature = "f413"))] {
+                const MAX_SYSCLK: u32 = 110_000_000;
+            } else if #[cfg(any(feature = "f405", feature = "f407"))] {
+                const MAX_SYSCLK: u32 = 168_000_000;
+            } else {
+                const MAX_SYSCLK: u32 = 180_000_000;
+            }
+        }
+        const PLL_IN_RANGE: (u32, u32) = (1_000_000, 2_000_000);
+        const PLL_OUT_RANGE: (u32, u32) = (100_000_000, 432_000_000);
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum InputSrc {
     Hsi,
@@ -350,9 +390,31 @@ impl Pllq {
     }
 }
 
+#[cfg(feature = "f3")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// USART kernel clock source. Sets RCC_CFGR3 register, USARTxSW fields. `Hsi` and `Lse` keep
+/// the baud rate independent of SYSCLK changes.
+pub enum UsartSrc {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Lse = 0b10,
+    Hsi = 0b11,
+}
+
+#[cfg(feature = "f3")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2C kernel clock source. Sets RCC_CFGR3 register, I2CxSW fields.
+pub enum I2cSrc {
+    Hsi = 0,
+    Sysclk = 1,
+}
+
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The input source for the system and peripheral clocks. Eg HSE, HSI, PLL etc
     pub input_src: InputSrc,
@@ -384,6 +446,21 @@ pub struct Clocks {
     /// frees up the pin for use as GPIO.
     pub hse_bypass: bool,
     pub security_system: bool,
+    #[cfg(feature = "f3")]
+    /// USART1 kernel clock source
+    pub usart1_src: UsartSrc,
+    #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+    /// USART2 kernel clock source
+    pub usart2_src: UsartSrc,
+    #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+    /// USART3 kernel clock source
+    pub usart3_src: UsartSrc,
+    #[cfg(feature = "f3")]
+    /// I2C1 kernel clock source
+    pub i2c1_src: I2cSrc,
+    #[cfg(all(feature = "f3", not(feature = "f3x4")))]
+    /// I2C2 kernel clock source
+    pub i2c2_src: I2cSrc,
 }
 
 impl Clocks {
@@ -391,7 +468,24 @@ impl Clocks {
     /// `Invalid`, and don't setup if not.
     /// https://docs.rs/stm32f3xx-hal/0.5.0/stm32f3xx_hal/rcc/struct.CFGR.html
     /// Use the STM32CubeIDE Clock Configuration tab to help.
+    /// Returns an error if the HSE is used, and doesn't become ready; eg if the crystal is missing.
+    /// This also enables and resets SYSCFG, which clears its EXTI line mappings; run it once, at
+    /// startup, and use `change_speed` to change clock speeds afterwards.
     pub fn setup(&self) -> Result<(), SpeedError> {
+        self.setup_rcc()?;
+
+        // Enable and reset System Configuration Controller, ie for interrupts.
+        // todo: Is this the right module to do this in?
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc_en_reset!(apb2, syscfg, rcc);
+
+        Ok(())
+    }
+
+    /// Validate the config, and apply it: Oscillators, PLL, prescalers, and flash wait states. This
+    /// doesn't touch SYSCFG, so is safe to run again at runtime; `change_speed`, and
+    /// `handle_hse_failure` use it.
+    pub(crate) fn setup_rcc(&self) -> Result<(), SpeedError> {
         if let Err(e) = self.validate_speeds() {
             return Err(e);
         }
@@ -454,8 +548,11 @@ impl Clocks {
         match self.input_src {
             InputSrc::Hse(_) => {
                 rcc.cr.modify(|_, w| w.hseon().bit(true));
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().is_not_ready() {}
+                wait_ready(
+                    || rcc.cr.read().hserdy().is_ready(),
+                    HSE_READY_POLLS,
+                    "HSE failed to start",
+                )?;
             }
             InputSrc::Hsi => {
                 rcc.cr.modify(|_, w| w.hsion().bit(true));
@@ -466,7 +563,11 @@ impl Clocks {
                     PllSrc::Hse(_) => {
                         // DRY
                         rcc.cr.modify(|_, w| w.hseon().bit(true));
-                        while rcc.cr.read().hserdy().is_not_ready() {}
+                        wait_ready(
+                            || rcc.cr.read().hserdy().is_ready(),
+                            HSE_READY_POLLS,
+                            "HSE failed to start",
+                        )?;
                     }
                     _ => {
                         // Hsi or HsiDiv2: In both cases, set up the HSI.
@@ -531,9 +632,36 @@ impl Clocks {
 
         rcc.cr.modify(|_, w| w.csson().bit(self.security_system));
 
+        #[cfg(feature = "f3")]
+        {
+            // The HSI and LSE must be running for the peripherals that use them as kernel clocks.
+            if self.hsi_kernel_used() {
+                rcc.cr.modify(|_, w| w.hsion().bit(true));
+                while rcc.cr.read().hsirdy().is_not_ready() {}
+            }
+
+            if self.lse_kernel_used() && !lse_ready() {
+                setup_lse(&LseConfig::default())?;
+            }
+
+            rcc.cfgr3.modify(|_, w| unsafe {
+                w.usart1sw().bits(self.usart1_src as u8);
+                #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+                w.usart2sw().bits(self.usart2_src as u8);
+                #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+                w.usart3sw().bits(self.usart3_src as u8);
+                #[cfg(not(feature = "f3x4"))]
+                w.i2c2sw().bit(self.i2c2_src as u8 != 0);
+                w.i2c1sw().bit(self.i2c1_src as u8 != 0)
+            });
+        }
+
         // If we're not using the default clock source as input source or for PLL, turn it off.
         match self.input_src {
             InputSrc::Hsi => (),
+            // Peripherals with the HSI selected as their kernel clock need it on.
+            #[cfg(feature = "f3")]
+            _ if self.hsi_kernel_used() => (),
             InputSrc::Pll(pll_src) => match pll_src {
                 #[cfg(feature = "f3")]
                 PllSrc::HsiDiv2 => (),
@@ -548,9 +676,39 @@ impl Clocks {
             }
         }
 
-        // Enable and reset System Configuration Controller, ie for interrupts.
-        // todo: Is this the right module to do this in?
-        rcc_en_reset!(apb2, syscfg, rcc);
+        Ok(())
+    }
+
+    /// Change clock speeds at runtime, eg to scale SYSCLK down when idle. Validates `new`, then
+    /// switches SYSCLK to the HSI while the oscillators, PLL, and flash wait states are
+    /// reconfigured. Since the HSI is slow enough to run with any wait state setting, this keeps the
+    /// transition glitch-free in both directions. Unlike `setup`, this doesn't reset SYSCFG, so EXTI
+    /// line mappings are kept. On success, `self` is updated to `new`. Afterwards, run
+    /// `Reclock::reclock` on peripherals that derive settings from the clock speeds, eg `Usart`,
+    /// `I2c`, and `Timer`.
+    pub fn change_speed(&mut self, new: Self) -> Result<(), SpeedError> {
+        new.validate_speeds()?;
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        rcc.cr.modify(|_, w| w.hsion().bit(true));
+        while rcc.cr.read().hsirdy().is_not_ready() {}
+
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.sw().bits(InputSrc::Hsi.bits()) });
+        while rcc.cfgr.read().sws().bits() != InputSrc::Hsi.bits() {}
+
+        // `setup` only reconfigures the PLL if it's used; otherwise, turn it off to save power.
+        if !matches!(new.input_src, InputSrc::Pll(_)) {
+            rcc.cr.modify(|_, w| w.pllon().off());
+            while rcc.cr.read().pllrdy().is_ready() {}
+        }
+
+        new.setup_rcc()?;
+
+        while rcc.cfgr.read().sws().bits() != new.input_src.bits() {}
+
+        *self = new;
 
         Ok(())
     }
@@ -643,9 +801,14 @@ impl Clocks {
 
     pub fn usb(&self) -> u32 {
         #[cfg(feature = "f3")]
-        return self.sysclk() / self.usb_pre.value() as u32;
+        return (self.sysclk() as f32 / self.usb_pre.value()) as u32;
         #[cfg(feature = "f4")]
-        return 0; // todo
+        return match self.input_src {
+            InputSrc::Pll(pll_src) => {
+                pll_src.freq() / self.pllm as u32 * self.plln as u32 / self.pllq.value() as u32
+            }
+            _ => 0,
+        };
     }
 
     pub fn apb1(&self) -> u32 {
@@ -673,25 +836,26 @@ impl Clocks {
     }
 
     pub fn validate_speeds(&self) -> Result<(), SpeedError> {
-        cfg_if! {
-            if #[cfg(feature = "f3")] {
-                let max_clock = 72_000_000;
-            } else if #[cfg(feature = "f401")] {
-                let max_clock = 84_000_000;
-            } else if #[cfg(any(feature = "f410", feature = "f411", feature = "f412", feature = "f413"))] {
-                let max_clock = 110_000_000;
-            } else if #[cfg(any(feature = "f405", feature = "f407"))] {
-                let max_clock = 168_000_000;
-            } else {
-                let max_clock = 180_000_000;
-            }
-        }
+        let max_clock = MAX_SYSCLK;
 
         #[cfg(feature = "f4")]
         if self.plln < 50 || self.plln > 432 || self.pllm < 2 || self.pllm > 63 {
             return Err(SpeedError::new("A PLL divider is out of limits"));
         }
 
+        #[cfg(feature = "f4")]
+        if let InputSrc::Pll(pll_src) = self.input_src {
+            let vco_in = pll_src.freq() / self.pllm as u32;
+            if vco_in < PLL_IN_RANGE.0 || vco_in > PLL_IN_RANGE.1 {
+                return Err(SpeedError::new("PLL input (after PLLM) out of limits"));
+            }
+
+            let vco_out = vco_in * self.plln as u32;
+            if vco_out < PLL_OUT_RANGE.0 || vco_out > PLL_OUT_RANGE.1 {
+                return Err(SpeedError::new("PLL VCO output out of limits"));
+            }
+        }
+
         let max_hclk = max_clock;
 
         // todo: min clock? eg for apxb?
@@ -715,6 +879,125 @@ impl Clocks {
     }
 }
 
+#[cfg(feature = "f3")]
+impl Clocks {
+    /// Returns true if any peripheral kernel clock is set to the HSI.
+    fn hsi_kernel_used(&self) -> bool {
+        #[allow(unused_mut)] // On F3x4, only USART1 and I2C1 have a selectable kernel clock.
+        let mut result = self.usart1_src == UsartSrc::Hsi || self.i2c1_src == I2cSrc::Hsi;
+
+        #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+        {
+            result |= self.usart2_src == UsartSrc::Hsi || self.usart3_src == UsartSrc::Hsi;
+        }
+        #[cfg(not(feature = "f3x4"))]
+        {
+            result |= self.i2c2_src == I2cSrc::Hsi;
+        }
+
+        result
+    }
+
+    /// Returns true if any peripheral kernel clock is set to the LSE.
+    fn lse_kernel_used(&self) -> bool {
+        #[allow(unused_mut)] // On F301 and F3x4, only USART1 can use the LSE.
+        let mut result = self.usart1_src == UsartSrc::Lse;
+
+        #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+        {
+            result |= self.usart2_src == UsartSrc::Lse || self.usart3_src == UsartSrc::Lse;
+        }
+
+        result
+    }
+}
+
+impl ClockTree for Clocks {
+    fn sysclk(&self) -> u32 {
+        Clocks::sysclk(self)
+    }
+
+    fn hclk(&self) -> u32 {
+        Clocks::hclk(self)
+    }
+
+    fn apb1(&self) -> u32 {
+        Clocks::apb1(self)
+    }
+
+    fn apb2(&self) -> u32 {
+        Clocks::apb2(self)
+    }
+
+    fn apb1_timer(&self) -> u32 {
+        Clocks::apb1_timer(self)
+    }
+
+    fn apb2_timer(&self) -> u32 {
+        Clocks::apb2_timer(self)
+    }
+
+    fn kernel_clock(&self, periph: KernelClockPeriph) -> u32 {
+        cfg_if! {
+            if #[cfg(feature = "f3")] {
+                let rcc = unsafe { &(*RCC::ptr()) };
+                let cfgr3 = rcc.cfgr3.read().bits();
+
+                // F303 RM, section 9.4.13: "00: PCLK selected as USART1 clock source (default),
+                // 01: System clock (SYSCLK) selected as USART1 clock, 10: LSE clock selected as
+                // USART1 clock, 11: HSI clock selected as USART1 clock"
+                let usart = |pclk: u32, pos: u8| match (cfgr3 >> pos) & 0b11 {
+                    0b00 => pclk,
+                    0b01 => self.sysclk(),
+                    0b10 => 32_768,
+                    _ => 8_000_000,
+                };
+
+                // "0: HSI clock selected as I2C1 clock source (default), 1: System clock (SYSCLK)
+                // selected as I2C1 clock"
+                let i2c = |pos: u8| match (cfgr3 >> pos) & 0b1 {
+                    0 => 8_000_000,
+                    _ => self.sysclk(),
+                };
+
+                match periph {
+                    KernelClockPeriph::Usart1 => usart(self.apb2(), 0),
+                    KernelClockPeriph::Usart2 => usart(self.apb1(), 16),
+                    KernelClockPeriph::Usart3 => usart(self.apb1(), 18),
+                    KernelClockPeriph::Uart4 => usart(self.apb1(), 20),
+                    KernelClockPeriph::Uart5 => usart(self.apb1(), 22),
+                    KernelClockPeriph::I2c1 => i2c(4),
+                    KernelClockPeriph::I2c2 => i2c(5),
+                    // On F373, this position is used by CEC.
+                    #[cfg(not(feature = "f373"))]
+                    KernelClockPeriph::I2c3 => i2c(6),
+                    KernelClockPeriph::Spi1 | KernelClockPeriph::Spi4 => self.apb2(),
+                    // This assumes the ADC uses the synchronous AHB clock. On F373, the ADC is on APB2.
+                    #[cfg(feature = "f373")]
+                    KernelClockPeriph::Adc => self.apb2(),
+                    #[cfg(not(feature = "f373"))]
+                    KernelClockPeriph::Adc => self.hclk(),
+                    _ => self.apb1(),
+                }
+            } else {
+                // F4 doesn't have kernel clock selection for these peripherals; they use the bus
+                // clock.
+                match periph {
+                    KernelClockPeriph::Usart1
+                    | KernelClockPeriph::Usart6
+                    | KernelClockPeriph::Spi1
+                    | KernelClockPeriph::Spi4
+                    | KernelClockPeriph::Spi5
+                    | KernelClockPeriph::Spi6
+                    | KernelClockPeriph::Adc => self.apb2(),
+                    KernelClockPeriph::Qspi => self.hclk(),
+                    _ => self.apb1(),
+                }
+            }
+        }
+    }
+}
+
 impl Default for Clocks {
     #[cfg(feature = "f3")]
     /// This default configures common with a HSI, a 64Mhz sysclck. All peripheral common are at
@@ -730,6 +1013,14 @@ impl Default for Clocks {
             apb2_prescaler: ApbPrescaler::Div1,
             hse_bypass: false,
             security_system: false,
+            usart1_src: UsartSrc::Pclk,
+            #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+            usart2_src: UsartSrc::Pclk,
+            #[cfg(any(feature = "f302", feature = "f303", feature = "f373"))]
+            usart3_src: UsartSrc::Pclk,
+            i2c1_src: I2cSrc::Hsi,
+            #[cfg(not(feature = "f3x4"))]
+            i2c2_src: I2cSrc::Hsi,
         }
     }
 
@@ -773,3 +1064,506 @@ impl Default for Clocks {
         }
     }
 }
+
+// The divider and multiplier values searched by `Clocks::with_sysclk`.
+#[cfg(feature = "f3")]
+const PREDIVS: [Prediv; 16] = [
+    Prediv::Div1,
+    Prediv::Div2,
+    Prediv::Div3,
+    Prediv::Div4,
+    Prediv::Div5,
+    Prediv::Div6,
+    Prediv::Div7,
+    Prediv::Div8,
+    Prediv::Div9,
+    Prediv::Div10,
+    Prediv::Div11,
+    Prediv::Div12,
+    Prediv::Div13,
+    Prediv::Div14,
+    Prediv::Div15,
+    Prediv::Div16,
+];
+
+#[cfg(feature = "f3")]
+const PLL_MULS: [PllMul; 15] = [
+    PllMul::Mul2,
+    PllMul::Mul3,
+    PllMul::Mul4,
+    PllMul::Mul5,
+    PllMul::Mul6,
+    PllMul::Mul7,
+    PllMul::Mul8,
+    PllMul::Mul9,
+    PllMul::Mul10,
+    PllMul::Mul11,
+    PllMul::Mul12,
+    PllMul::Mul13,
+    PllMul::Mul14,
+    PllMul::Mul15,
+    PllMul::Mul16,
+];
+
+#[cfg(feature = "f4")]
+const PLLP_DIVS: [Pllp; 4] = [Pllp::Div2, Pllp::Div4, Pllp::Div6, Pllp::Div8];
+
+#[cfg(feature = "f4")]
+const PLLQ_DIVS: [Pllq; 14] = [
+    Pllq::Div2,
+    Pllq::Div3,
+    Pllq::Div4,
+    Pllq::Div5,
+    Pllq::Div6,
+    Pllq::Div7,
+    Pllq::Div8,
+    Pllq::Div9,
+    Pllq::Div10,
+    Pllq::Div11,
+    Pllq::Div12,
+    Pllq::Div13,
+    Pllq::Div14,
+    Pllq::Div15,
+];
+
+#[cfg(feature = "f3")]
+/// PLL settings found by `Clocks::with_sysclk`.
+struct PllSolution {
+    prediv: Prediv,
+    pll_mul: PllMul,
+    usb_pre: UsbPrescaler,
+    sysclk: u32,
+}
+
+#[cfg(feature = "f3")]
+/// Search PREDIV and PLLMUL for the SYSCLK closest to `target` without exceeding it, within the
+/// PLL limits. PREDIV only applies to the HSE. If `usb` is true, only accept SYSCLKs of 48 or
+/// 72Mhz, which the USB prescaler can divide to 48Mhz. Only PREDIV values that divide the input
+/// frequency exactly are used, so the speeds calculated from the config are exact.
+fn solve_pll(pll_src: PllSrc, target: u32, usb: bool) -> Option<PllSolution> {
+    let prediv_count = match pll_src {
+        PllSrc::HsiDiv2 => 1,
+        PllSrc::Hse(_) => PREDIVS.len(),
+    };
+
+    let mut best: Option<PllSolution> = None;
+
+    for prediv in &PREDIVS[..prediv_count] {
+        if pll_src.freq() % prediv.value() as u32 != 0 {
+            continue;
+        }
+
+        let pll_in = pll_src.freq() / prediv.value() as u32;
+        if pll_in < PLL_IN_RANGE.0 || pll_in > PLL_IN_RANGE.1 {
+            continue;
+        }
+
+        for pll_mul in PLL_MULS {
+            let sysclk = pll_in * pll_mul.value() as u32;
+            if sysclk < PLL_OUT_RANGE.0 || sysclk > PLL_OUT_RANGE.1 || sysclk > target {
+                continue;
+            }
+
+            let usb_pre = match sysclk {
+                72_000_000 => UsbPrescaler::Div1_5,
+                48_000_000 => UsbPrescaler::Div1,
+                _ if usb => continue,
+                _ => UsbPrescaler::Div1,
+            };
+
+            if best.as_ref().map(|b| sysclk > b.sysclk).unwrap_or(true) {
+                best = Some(PllSolution {
+                    prediv: *prediv,
+                    pll_mul,
+                    usb_pre,
+                    sysclk,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(feature = "f4")]
+/// PLL settings found by `Clocks::with_sysclk`.
+struct PllSolution {
+    pllm: u8,
+    plln: u16,
+    pllp: Pllp,
+    pllq: Option<Pllq>,
+    sysclk: u32,
+}
+
+#[cfg(feature = "f4")]
+/// Search PLLM, PLLN, and PLLP for the SYSCLK closest to `target` without exceeding it, within
+/// the VCO limits. If `usb` is true, only accept settings where a PLLQ divider produces exactly
+/// 48Mhz. Earlier (lower PLLM and PLLN) settings are preferred on ties; these have lower VCO
+/// speeds, which reduces power use. Only dividers that divide their input frequency exactly are
+/// used, so the speeds calculated from the config are exact.
+fn solve_pll(pll_src: PllSrc, target: u32, usb: bool) -> Option<PllSolution> {
+    let mut best: Option<PllSolution> = None;
+
+    for pllm in 2..=63_u8 {
+        let vco_in = pll_src.freq() / pllm as u32;
+        if vco_in < PLL_IN_RANGE.0 {
+            break;
+        }
+        if vco_in > PLL_IN_RANGE.1 || pll_src.freq() % pllm as u32 != 0 {
+            continue;
+        }
+
+        for plln in 50..=432_u16 {
+            let vco_out = vco_in * plln as u32;
+            if vco_out < PLL_OUT_RANGE.0 {
+                continue;
+            }
+            if vco_out > PLL_OUT_RANGE.1 {
+                break;
+            }
+
+            let pllq = if usb {
+                match PLLQ_DIVS
+                    .iter()
+                    .find(|q| vco_out == 48_000_000 * q.value() as u32)
+                {
+                    Some(q) => Some(*q),
+                    None => continue,
+                }
+            } else {
+                None
+            };
+
+            for pllp in PLLP_DIVS {
+                let sysclk = vco_out / pllp.value() as u32;
+                if sysclk > target || vco_out % pllp.value() as u32 != 0 {
+                    continue;
+                }
+
+                if best.as_ref().map(|b| sysclk > b.sysclk).unwrap_or(true) {
+                    best = Some(PllSolution {
+                        pllm,
+                        plln,
+                        pllp,
+                        pllq,
+                        sysclk,
+                    });
+                }
+            }
+        }
+    }
+
+    best
+}
+
+impl Clocks {
+    /// Create a config that runs SYSCLK from the PLL, fed by `pll_src`, as close as possible to
+    /// `sysclk` (in Hz) without exceeding it. The PLL dividers and multipliers are chosen within
+    /// this family's PLL input and output limits; other settings are as in `Default`. Returns an
+    /// error if `sysclk` is above the maximum, or no valid PLL settings exist.
+    pub fn with_sysclk(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, false)
+    }
+
+    /// Similar to `with_sysclk`, but also requires a 48Mhz USB clock: On F3, SYSCLK must be 48 or
+    /// 72Mhz, and the USB prescaler is set accordingly. On F4, PLLQ is set to produce exactly
+    /// 48Mhz.
+    pub fn with_sysclk_usb(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, true)
+    }
+
+    /// Create the config to switch to if the HSE fails, eg with `clocks::handle_hse_failure`:
+    /// This config, with the PLL fed by the HSI, and set to approximate this config's SYSCLK. If
+    /// the PLL can't do this within its limits, SYSCLK runs from the HSI directly. The USB clock
+    /// is kept at 48Mhz if possible.
+    pub fn hse_fallback(&self) -> Self {
+        let mut result = self.clone();
+        result.security_system = false;
+        result.hse_bypass = false;
+
+        #[cfg(feature = "f3")]
+        let hsi = PllSrc::HsiDiv2;
+        #[cfg(feature = "f4")]
+        let hsi = PllSrc::Hsi;
+
+        let target = self.sysclk();
+        let soln = if self.usb() == 48_000_000 {
+            solve_pll(hsi, target, true)
+        } else {
+            None
+        };
+
+        match soln.or_else(|| solve_pll(hsi, target, false)) {
+            Some(s) => {
+                result.input_src = InputSrc::Pll(hsi);
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        result.prediv = s.prediv;
+                        result.pll_mul = s.pll_mul;
+                        result.usb_pre = s.usb_pre;
+                    } else {
+                        result.pllm = s.pllm;
+                        result.plln = s.plln;
+                        result.pllp = s.pllp;
+                        if let Some(pllq) = s.pllq {
+                            result.pllq = pllq;
+                        }
+                    }
+                }
+            }
+            None => result.input_src = InputSrc::Hsi,
+        }
+
+        result
+    }
+
+    fn from_pll_search(sysclk: u32, pll_src: PllSrc, usb: bool) -> Result<Self, SpeedError> {
+        if sysclk > MAX_SYSCLK {
+            return Err(SpeedError::new("Requested sysclk is above the maximum"));
+        }
+
+        let soln = match solve_pll(pll_src, sysclk, usb) {
+            Some(s) => s,
+            None if usb => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce both this sysclk and a 48Mhz USB clock",
+                ))
+            }
+            None => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce this sysclk within the PLL limits",
+                ))
+            }
+        };
+
+        cfg_if! {
+            if #[cfg(feature = "f3")] {
+                let result = Self {
+                    input_src: InputSrc::Pll(pll_src),
+                    prediv: soln.prediv,
+                    pll_mul: soln.pll_mul,
+                    usb_pre: soln.usb_pre,
+                    ..Default::default()
+                };
+            } else {
+                let mut result = Self {
+                    input_src: InputSrc::Pll(pll_src),
+                    pllm: soln.pllm,
+                    plln: soln.plln,
+                    pllp: soln.pllp,
+                    ..Default::default()
+                };
+
+                if let Some(pllq) = soln.pllq {
+                    result.pllq = pllq;
+                }
+            }
+        }
+
+        result.validate_speeds()?;
+        Ok(result)
+    }
+}
+
+#[cfg(feature = "f3")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO pin. Sets RCC_CFGR register, MCO field.
+pub enum McoSrc {
+    Disabled = 0b000,
+    Lsi = 0b010,
+    Lse = 0b011,
+    Sysclk = 0b100,
+    Hsi = 0b101,
+    Hse = 0b110,
+    /// The PLL output, divided by 2.
+    PllDiv2 = 0b111,
+}
+
+#[cfg(feature = "f3")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The division applied to the MCO output. Sets RCC_CFGR register, MCOPRE field.
+pub enum McoDiv {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+    Div32 = 0b101,
+    Div64 = 0b110,
+    Div128 = 0b111,
+}
+
+#[cfg(feature = "f3")]
+/// Output a clock on the MCO pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. Set the MCO pin (PA8) to alternate function 0 to use it. F373 has no MCO
+/// prescaler; `div` is ignored on it.
+#[allow(unused_variables)] // div on F373.
+pub fn setup_mco(src: McoSrc, div: McoDiv) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.cfgr.modify(|_, w| unsafe {
+        #[cfg(not(feature = "f373"))]
+        w.mcopre().bits(div as u8);
+        w.mco().bits(src as u8)
+    });
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO1 pin. Sets RCC_CFGR register, MCO1 field.
+pub enum Mco1Src {
+    Hsi = 0b00,
+    Lse = 0b01,
+    Hse = 0b10,
+    Pll = 0b11,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO2 pin. Sets RCC_CFGR register, MCO2 field.
+pub enum Mco2Src {
+    Sysclk = 0b00,
+    PllI2s = 0b01,
+    Hse = 0b10,
+    Pll = 0b11,
+}
+
+#[cfg(feature = "f4")]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The division applied to the MCO outputs. Sets RCC_CFGR register, MCO1PRE and MCO2PRE fields.
+pub enum McoDiv {
+    Div1 = 0b000,
+    Div2 = 0b100,
+    Div3 = 0b101,
+    Div4 = 0b110,
+    Div5 = 0b111,
+}
+
+#[cfg(feature = "f4")]
+/// Output a clock on the MCO1 pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. Set the MCO1 pin (PA8) to alternate function 0 to use it.
+pub fn setup_mco1(src: Mco1Src, div: McoDiv) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.cfgr.modify(|_, w| unsafe {
+        #[cfg(feature = "f410")]
+        w.mco1en().set_bit();
+        w.mco1pre().bits(div as u8);
+        w.mco1().bits(src as u8)
+    });
+}
+
+#[cfg(feature = "f4")]
+/// Output a clock on the MCO2 pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. Set the MCO2 pin (PC9) to alternate function 0 to use it.
+pub fn setup_mco2(src: Mco2Src, div: McoDiv) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.cfgr.modify(|_, w| unsafe {
+        #[cfg(feature = "f410")]
+        w.mco2en().set_bit();
+        w.mco2pre().bits(div as u8);
+        w.mco2().bits(src as u8)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "f3")]
+    #[test]
+    fn solve_pll_f3() {
+        // 8Mhz HSE / 1 * 9 = 72Mhz, with USB at 72Mhz / 1.5.
+        let clocks = Clocks::with_sysclk(72_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_eq!(clocks.prediv.value(), 1);
+        assert_eq!(clocks.pll_mul.value(), 9);
+        assert!(matches!(clocks.usb_pre, UsbPrescaler::Div1_5));
+        assert_eq!(clocks.sysclk(), 72_000_000);
+
+        // The HSI / 2 is 4Mhz, and PLLMUL tops out at 16: 64Mhz.
+        let clocks = Clocks::with_sysclk(72_000_000, PllSrc::HsiDiv2).unwrap();
+        assert_eq!(clocks.pll_mul.value(), 16);
+        assert_eq!(clocks.sysclk(), 64_000_000);
+
+        // USB needs 48 or 72Mhz; 64Mhz isn't reachable with it, so 48Mhz is used.
+        let clocks = Clocks::with_sysclk_usb(64_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_eq!(clocks.pll_mul.value(), 6);
+        assert!(matches!(clocks.usb_pre, UsbPrescaler::Div1));
+        assert_eq!(clocks.sysclk(), 48_000_000);
+    }
+
+    #[cfg(feature = "f4")]
+    /// Check the PLL dividers `with_sysclk` picked, and the resulting SYSCLK.
+    fn assert_pll(clocks: &Clocks, pllm: u8, plln: u16, pllp: u8, sysclk: u32) {
+        assert_eq!(clocks.pllm, pllm);
+        assert_eq!(clocks.plln, plln);
+        assert_eq!(clocks.pllp.value(), pllp);
+        assert_eq!(clocks.sysclk(), sysclk);
+    }
+
+    #[cfg(feature = "f4")]
+    #[test]
+    fn solve_pll_f4() {
+        // 8Mhz HSE / 4 * 84 / 2 = 84Mhz. PLLM = 4 is the lowest that brings the VCO input
+        // down to 2Mhz.
+        let clocks = Clocks::with_sysclk(84_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_pll(&clocks, 4, 84, 2, 84_000_000);
+
+        // 16Mhz HSI / 8 * 84 / 2 = 84Mhz.
+        let clocks = Clocks::with_sysclk(84_000_000, PllSrc::Hsi).unwrap();
+        assert_pll(&clocks, 8, 84, 2, 84_000_000);
+
+        // 84Mhz needs a 168 or 336Mhz VCO. Of the VCO inputs 25Mhz divides to exactly (1.5625,
+        // 1.25 and 1Mhz), only 1Mhz, from PLLM = 25, multiplies to either.
+        let clocks = Clocks::with_sysclk(84_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 25, 168, 2, 84_000_000);
+
+        // The VCO must be a multiple of 48Mhz for PLLQ: 336Mhz / 4 = 84Mhz, and 336Mhz / 7 =
+        // 48Mhz.
+        let clocks = Clocks::with_sysclk_usb(84_000_000, PllSrc::Hse(8_000_000)).unwrap();
+        assert_pll(&clocks, 4, 168, 4, 84_000_000);
+        assert_eq!(clocks.pllq.value(), 7);
+    }
+
+    #[test]
+    fn with_sysclk() {
+        let clocks = Clocks::with_sysclk(MAX_SYSCLK, PllSrc::Hse(8_000_000)).unwrap();
+        assert_eq!(clocks.sysclk(), MAX_SYSCLK);
+
+        let clocks = Clocks::with_sysclk_usb(MAX_SYSCLK, PllSrc::Hse(8_000_000)).unwrap();
+        assert_eq!(clocks.usb(), 48_000_000);
+
+        assert!(Clocks::with_sysclk(MAX_SYSCLK + 1, PllSrc::Hse(8_000_000)).is_err());
+    }
+
+    #[test]
+    fn hse_fallback() {
+        let clocks = Clocks::with_sysclk(MAX_SYSCLK, PllSrc::Hse(8_000_000)).unwrap();
+        let fallback = clocks.hse_fallback();
+        assert!(fallback.validate_speeds().is_ok());
+        // `with_sysclk` runs USB from 72Mhz / 1.5 on F3. The HSI / 2 can't produce 72Mhz, so
+        // the fallback keeps USB at 48Mhz with a 48Mhz SYSCLK.
+        #[cfg(feature = "f3")]
+        {
+            assert!(matches!(fallback.input_src, InputSrc::Pll(PllSrc::HsiDiv2)));
+            assert_eq!(fallback.sysclk(), 48_000_000);
+            assert_eq!(fallback.usb(), 48_000_000);
+        }
+        #[cfg(feature = "f4")]
+        {
+            assert!(matches!(fallback.input_src, InputSrc::Pll(PllSrc::Hsi)));
+            assert_eq!(fallback.sysclk(), clocks.sysclk());
+        }
+
+        let clocks = Clocks::with_sysclk_usb(MAX_SYSCLK, PllSrc::Hse(8_000_000)).unwrap();
+        let fallback = clocks.hse_fallback();
+        assert!(fallback.validate_speeds().is_ok());
+        assert_eq!(fallback.usb(), 48_000_000);
+    }
+}
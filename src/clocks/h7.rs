@@ -5,8 +5,11 @@
 // Similar in from to the `baseline` clocks module, but includes notable differendes.
 
 use crate::{
-    clocks::SpeedError,
-    pac::{CRS, FLASH, PWR, RCC, SYSCFG},
+    clocks::{
+        lse_ready, setup_lse, wait_ready, ClockTree, KernelClockPeriph, LseConfig, SpeedError,
+        HSE_READY_POLLS,
+    },
+    pac::{FLASH, PWR, RCC, SYSCFG},
 };
 
 use cfg_if::cfg_if;
@@ -51,21 +54,10 @@ pub enum UsbSrc {
     Hsi48 = 0b11,
 }
 
-#[derive(Clone, Copy)]
-#[repr(u8)]
-/// Select the SYNC signal source. Sets the CRS_CFGR register, SYNCSRC field.
-pub enum CrsSyncSrc {
-    #[cfg(feature = "h735")] // todo: 735 and 743 are opposites here. QC which use which.
-    /// CRS_SYNC pin selected as SYNC signal source
-    CrsSync = 0b00,
-    #[cfg(not(feature = "h735"))]
-    /// USB2 SOF selected as SYNC signal source
-    Usb2 = 0b00,
-    /// LSE selected as SYNC signal source
-    Lse = 0b01,
-    /// OTG HS1 SOF selected as SYNC signal source
-    OtgHs = 0b10,
-}
+// The wide VCO range (VCOH) output frequency limits, in Hz.
+const VCO_WIDE_RANGE: (u32, u32) = (192_000_000, 960_000_000);
+// The medium VCO range (VCOL) output frequency limits, in Hz.
+const VCO_MEDIUM_RANGE: (u32, u32) = (150_000_000, 420_000_000);
 
 #[derive(Clone, Copy, PartialEq)]
 /// Clock input source, also known as system clock switch. Sets RCC_CFGR register, SW field.
@@ -92,9 +84,13 @@ impl InputSrc {
 
 /// Configures the speeds, and enable status of an individual PLL. Note that the `enable`
 /// field has no effect for PLL1.
+#[derive(Clone, PartialEq)]
 pub struct PllCfg {
     pub enabled: bool,
-    // pub fractional: bool,
+    /// Fractional part of the VCO multiplication factor, from 0 to 8191. If `Some`, the PLL runs
+    /// in fractional mode, with VCO = ref_ck x (DIVN + FRACN / 8192). Use this to produce exact
+    /// audio clocks, eg multiples of 44.1kHz for SAI. Sets RCC_PLLxFRACR register, FRACNx field.
+    pub fracn: Option<u16>,
     pub pllp_en: bool,
     pub pllq_en: bool,
     pub pllr_en: bool,
@@ -110,7 +106,7 @@ impl Default for PllCfg {
     fn default() -> Self {
         Self {
             enabled: true,
-            // fractional: false,
+            fracn: None,
             pllp_en: true,
             pllq_en: false,
             pllr_en: false,
@@ -137,6 +133,91 @@ impl PllCfg {
             ..Default::default()
         }
     }
+
+    /// Calculate the VCO output frequency, in Hz, from the PLL source frequency (before DIVM):
+    /// ref_ck x (DIVN + FRACN / 8192).
+    pub fn vco_freq(&self, src_freq: u32) -> u32 {
+        let ref_ck = src_freq / self.divm as u32;
+        let fracn = self.fracn.unwrap_or(0) as u64;
+
+        ref_ck * self.divn as u32 + (ref_ck as u64 * fracn / 8_192) as u32
+    }
+
+    /// Check the dividers, and that the reference clock (after DIVM) and VCO output are in range,
+    /// given the PLL source frequency (before DIVM). This doesn't access any registers.
+    pub fn validate(&self, src_freq: u32) -> Result<(), SpeedError> {
+        if self.divm == 0
+            || self.divm > 63
+            || self.divn < 4
+            || self.divn > 512
+            || self.divp == 0
+            || self.divp > 128
+            || self.divq == 0
+            || self.divq > 128
+            || self.divr == 0
+            || self.divr > 128
+        {
+            return Err(SpeedError::new("A PLL divider is out of limits"));
+        }
+
+        if let Some(fracn) = self.fracn {
+            if fracn > 8_191 {
+                return Err(SpeedError::new("PLL FRACN must be 8191 or lower"));
+            }
+        }
+
+        let ref_ck = src_freq / self.divm as u32;
+        if !(1_000_000..=16_000_000).contains(&ref_ck) {
+            return Err(SpeedError::new("Invalid PLL input speed"));
+        }
+
+        // Wide VCO range (VCOH), for reference clocks of 2Mhz and above. Note: The RM appears out
+        // of date: Revision "V" allegedly supports 960Mhz VCO speed, to allow a max core speed of
+        // 480Mhz. Medium VCO range (VCOL): 150 to 420 MHz, for reference clocks below 2Mhz.
+        let vco = self.vco_freq(src_freq);
+        if ref_ck >= 2_000_000 && (vco < VCO_WIDE_RANGE.0 || vco > VCO_WIDE_RANGE.1) {
+            return Err(SpeedError::new("Invalid wide VCO speed"));
+        }
+        if ref_ck < 2_000_000 && (vco < VCO_MEDIUM_RANGE.0 || vco > VCO_MEDIUM_RANGE.1) {
+            return Err(SpeedError::new("Invalid medium VCO speed"));
+        }
+
+        Ok(())
+    }
+
+    /// Find DIVM, DIVN and FRACN that keep this PLL's VCO speed, and so its output speeds, when
+    /// its source changes from `src_freq` to `new_src_freq`. Integer settings are preferred over
+    /// fractional ones. Returns `None` if no valid settings exist.
+    fn refit(&self, src_freq: u32, new_src_freq: u32) -> Option<Self> {
+        let vco = self.vco_freq(src_freq);
+        let mut fractional = None;
+
+        for divm in 1..=63 {
+            if new_src_freq % divm != 0 {
+                continue;
+            }
+            let ref_ck = new_src_freq / divm;
+            let fracn = ((vco % ref_ck) as u64 * 8_192 / ref_ck as u64) as u16;
+
+            let cfg = Self {
+                divm: divm as u8,
+                divn: (vco / ref_ck) as u16,
+                fracn: if fracn == 0 { None } else { Some(fracn) },
+                ..self.clone()
+            };
+            if cfg.validate(new_src_freq).is_err() {
+                continue;
+            }
+            if cfg.fracn.is_none() {
+                return Some(cfg);
+            }
+            if fractional.is_none() {
+                fractional = Some(cfg);
+            }
+        }
+
+        fractional
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -214,6 +295,112 @@ pub enum DfsdmSrc {
     Sysclk = 1,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// USART and LPUART kernel clock source. Sets RCC_D2CCIP2R register, USART16SEL and
+/// USART234578SEL fields, and RCC_D3CCIPR register, LPUART1SEL field. `Pclk` is `rcc_pclk2` for
+/// USART1 and 6, `rcc_pclk1` for USART2, 3, 4, 5, 7 and 8, and `rcc_pclk4` for LPUART1. `HsiKer`,
+/// `CsiKer` and `Lse` keep the baud rate independent of SYSCLK changes.
+pub enum UsartSrc {
+    Pclk = 0b000,
+    Pll2Q = 0b001,
+    Pll3Q = 0b010,
+    HsiKer = 0b011,
+    CsiKer = 0b100,
+    Lse = 0b101,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// I2C kernel clock source. Sets RCC_D2CCIP2R register, I2C123SEL field, and RCC_D3CCIPR
+/// register, I2C4SEL field. `Pclk` is `rcc_pclk1` for I2C1, 2 and 3, and `rcc_pclk4` for I2C4.
+pub enum I2cSrc {
+    Pclk = 0b00,
+    Pll3R = 0b01,
+    HsiKer = 0b10,
+    CsiKer = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SPI1, 2 and 3 kernel clock source. Sets RCC_D2CCIP1R register, SPI123SEL field.
+pub enum Spi123Src {
+    Pll1Q = 0b000,
+    Pll2P = 0b001,
+    Pll3P = 0b010,
+    I2sCkin = 0b011,
+    PerCk = 0b100,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// SPI4, 5 and 6 kernel clock source. Sets RCC_D2CCIP1R register, SPI45SEL field, and
+/// RCC_D3CCIPR register, SPI6SEL field. `Pclk` is `rcc_pclk2` for SPI4 and 5, and `rcc_pclk4`
+/// for SPI6.
+pub enum Spi456Src {
+    Pclk = 0b000,
+    Pll2Q = 0b001,
+    Pll3Q = 0b010,
+    HsiKer = 0b011,
+    CsiKer = 0b100,
+    Hse = 0b101,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// ADC kernel clock source. Sets RCC_D3CCIPR register, ADCSEL field. Only used if the ADC is
+/// configured to use its asynchronous clock.
+pub enum AdcSrc {
+    Pll2P = 0b00,
+    Pll3R = 0b01,
+    PerCk = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// FDCAN kernel clock source. Sets RCC_D2CCIP1R register, FDCANSEL field.
+pub enum FdcanSrc {
+    Hse = 0b00,
+    Pll1Q = 0b01,
+    Pll2Q = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// QUADSPI kernel clock source. Sets RCC_D1CCIPR register, QSPISEL field. On H723-735, this
+/// selects the OCTOSPI kernel clock, with the OCTOSPISEL field.
+pub enum QspiSrc {
+    Hclk = 0b00,
+    Pll1Q = 0b01,
+    Pll2R = 0b10,
+    PerCk = 0b11,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Source for `per_ck`, which can be selected as a kernel clock by SAI, SPI1-3, ADC, QUADSPI
+/// and others. Sets RCC_D1CCIPR register, CKPERSEL field.
+pub enum PerCkSrc {
+    HsiKer = 0b00,
+    CsiKer = 0b01,
+    Hse = 0b10,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LPTIM kernel clock source. Sets RCC_D2CCIP2R register, LPTIM1SEL field, and RCC_D3CCIPR
+/// register, LPTIM2SEL and LPTIM345SEL fields. `Pclk` is `rcc_pclk1` for LPTIM1, and `rcc_pclk4`
+/// for the others.
+/// `setup` enables the LSE if selected, but not the LSI; it can be enabled by RTC setup.
+pub enum LptimSrc {
+    Pclk = 0b000,
+    Pll2P = 0b001,
+    Pll3R = 0b010,
+    Lse = 0b011,
+    Lsi = 0b100,
+    PerCk = 0b101,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 #[repr(u8)]
 /// Clock divider for the HSI. See RCC_CR register, HSIDIV field.
@@ -331,6 +518,7 @@ impl VosRange {
 /// Settings used to configure clocks. Create this struct by using its `Default::default()`
 /// implementation, then modify as required, referencing your RM's clock tree,
 /// or Stm32Cube IDE's interactive clock manager. Apply settings by running `.setup()`.
+#[derive(Clone)]
 pub struct Clocks {
     /// The main input source
     pub input_src: InputSrc,
@@ -373,6 +561,36 @@ pub struct Clocks {
     pub sai4b_src: SaiSrc,
     /// DFSDM1 kernel clock source selection
     pub dfsdm1_src: DfsdmSrc,
+    /// USART1 and USART6 kernel clock source selection. (Also UART9 and USART10 on H735)
+    pub usart16_src: UsartSrc,
+    /// USART2, 3, 4, 5, 7 and 8 kernel clock source selection
+    pub usart234578_src: UsartSrc,
+    /// LPUART1 kernel clock source selection
+    pub lpuart1_src: UsartSrc,
+    /// I2C1, 2 and 3 kernel clock source selection. (Also I2C5 on H735)
+    pub i2c123_src: I2cSrc,
+    /// I2C4 kernel clock source selection
+    pub i2c4_src: I2cSrc,
+    /// SPI1, 2 and 3 kernel clock source selection
+    pub spi123_src: Spi123Src,
+    /// SPI4 and 5 kernel clock source selection
+    pub spi45_src: Spi456Src,
+    /// SPI6 kernel clock source selection
+    pub spi6_src: Spi456Src,
+    /// ADC kernel clock source selection
+    pub adc_src: AdcSrc,
+    /// LPTIM1 kernel clock source selection
+    pub lptim1_src: LptimSrc,
+    /// LPTIM2 kernel clock source selection
+    pub lptim2_src: LptimSrc,
+    /// LPTIM3, 4 and 5 kernel clock source selection
+    pub lptim345_src: LptimSrc,
+    /// FDCAN kernel clock source selection
+    pub fdcan_src: FdcanSrc,
+    /// QUADSPI (OCTOSPI on H723-735) kernel clock source selection
+    pub qspi_src: QspiSrc,
+    /// `per_ck` source selection
+    pub per_ck_src: PerCkSrc,
 }
 
 impl Clocks {
@@ -382,21 +600,48 @@ impl Clocks {
     /// Use the `default()` implementation as a safe baseline.
     /// This method also configures the PWR VOS setting, and can be used to enable VOS boost,
     /// if `vos_range` is set to `VosRange::VOS0`.
+    /// Returns an error if the HSE is used, and doesn't become ready; eg if the crystal is missing.
+    /// This also enables and resets SYSCFG, which clears its EXTI line mappings; run it once, at
+    /// startup, and use `change_speed` to change clock speeds afterwards.
     pub fn setup(&self) -> Result<(), SpeedError> {
-        if let Err(e) = self.validate_speeds() {
-            return Err(e);
-        }
-
-        let rcc = unsafe { &(*RCC::ptr()) };
-        let flash = unsafe { &(*FLASH::ptr()) };
-        let pwr = unsafe { &(*PWR::ptr()) };
+        self.validate_speeds()?;
 
-        // Enable and reset System Configuration Controller, ie for interrupts.
+        // Enable and reset System Configuration Controller, ie for interrupts. This is done before
+        // configuring the clocks, since activating VOS0 uses SYSCFG.
         // todo: Is this the right module to do this in?
+        let rcc = unsafe { &(*RCC::ptr()) };
         rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().set_bit());
         rcc.apb4rstr.modify(|_, w| w.syscfgrst().clear_bit());
 
+        self.setup_keeping(false, false)
+    }
+
+    /// Validate the config, and apply it: Oscillators, PLLs, voltage scaling, prescalers, kernel
+    /// clock sources, and flash wait states. This doesn't reset SYSCFG, so is safe to run again at
+    /// runtime; `handle_hse_failure` uses it.
+    pub(crate) fn setup_rcc(&self) -> Result<(), SpeedError> {
+        self.validate_speeds()?;
+        self.setup_keeping(false, false)
+    }
+
+    /// Apply the config, without validating it, or resetting SYSCFG. If `keep_pll2` or
+    /// `keep_pll3` is true, leave that PLL running as-is, instead of stopping and reconfiguring
+    /// it. Used by `setup`, `setup_rcc` and `change_speed`, which validate first.
+    fn setup_keeping(&self, keep_pll2: bool, keep_pll3: bool) -> Result<(), SpeedError> {
+        // RM0468: "The CPU frequency boost can be enabled through the CPUFREQ_BOOST option byte in
+        // FLASH_OPTSR2_PRG register." Without it, SYSCLK is limited to 520Mhz.
+        #[cfg(feature = "h735")]
+        if self.sysclk() > 520_000_000 && !crate::flash::cpu_freq_boost_enabled() {
+            return Err(SpeedError::new(
+                "Sysclk above 520Mhz requires CPUFREQ_BOOST. Set it with `Flash::set_cpu_freq_boost`.",
+            ));
+        }
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let flash = unsafe { &(*FLASH::ptr()) };
+        let pwr = unsafe { &(*PWR::ptr()) };
+
         // H743 RM, sefction 6.8.6, and section 6.6.2: Voltage Scaling
         //  Voltage scaling selection according to performance
         // These bits control the VCORE voltage level and allow to obtains the best trade-off between
@@ -423,7 +668,7 @@ impl Clocks {
 
                 // 2. Enable the SYSCFG clock in the RCC by setting the SYSCFGEN bit in the
                 // RCC_APB4ENR register.
-                // (Handled above)
+                rcc.apb4enr.modify(|_, w| w.syscfgen().set_bit());
 
                 // 3. Enable the ODEN bit in the SYSCFG_PWRCR register.
                 syscfg.pwrcr.modify(|_, w| w.oden().set_bit());
@@ -462,8 +707,11 @@ impl Clocks {
             }
             InputSrc::Hse(_) => {
                 rcc.cr.modify(|_, w| w.hseon().bit(true));
-                // Wait for the HSE to be ready.
-                while rcc.cr.read().hserdy().bit_is_clear() {}
+                wait_ready(
+                    || rcc.cr.read().hserdy().bit_is_set(),
+                    HSE_READY_POLLS,
+                    "HSE failed to start",
+                )?;
             }
             InputSrc::Hsi(div) => {
                 rcc.cr.modify(|_, w| {
@@ -481,7 +729,11 @@ impl Clocks {
                     }
                     PllSrc::Hse(_) => {
                         rcc.cr.modify(|_, w| w.hseon().bit(true));
-                        while rcc.cr.read().hserdy().bit_is_clear() {}
+                        wait_ready(
+                            || rcc.cr.read().hserdy().bit_is_set(),
+                            HSE_READY_POLLS,
+                            "HSE failed to start",
+                        )?;
                     }
                     PllSrc::Hsi(div) => {
                         rcc.cr.modify(|_, w| {
@@ -519,34 +771,70 @@ impl Clocks {
         rcc.d3cfgr
             .modify(|_, w| unsafe { w.d3ppre().bits(self.d3_prescaler as u8) });
 
+        // The CSI and LSE must be running for the peripherals that use them as kernel clocks.
+        if self.csi_kernel_used() {
+            rcc.cr.modify(|_, w| w.csion().set_bit());
+            while rcc.cr.read().csirdy().bit_is_clear() {}
+        }
+
+        if self.lse_kernel_used() && !lse_ready() {
+            setup_lse(&LseConfig::default())?;
+        }
+
         #[cfg(not(feature = "h7b3"))]
         rcc.d2ccip1r.modify(|_, w| unsafe {
             w.sai1sel().bits(self.sai1_src as u8);
             #[cfg(not(feature = "h735"))]
             w.sai23sel().bits(self.sai23_src as u8);
+            w.spi123sel().bits(self.spi123_src as u8);
+            w.spi45sel().bits(self.spi45_src as u8);
+            w.fdcansel().bits(self.fdcan_src as u8);
             w.dfsdm1sel().bit(self.dfsdm1_src as u8 != 0)
         });
 
-        // Set USART2 to HSI, and USB to HSI48. Temp hardcoded.
-        // todo: Add config enums for these, and add them as Clocks fields.
+        #[cfg(not(feature = "h7b3"))]
+        rcc.d1ccipr.modify(|_, w| unsafe {
+            #[cfg(feature = "h735")]
+            w.octospisel().bits(self.qspi_src as u8);
+            #[cfg(not(feature = "h735"))]
+            w.qspisel().bits(self.qspi_src as u8);
+            w.ckpersel().bits(self.per_ck_src as u8)
+        });
+
         #[cfg(not(feature = "h7b3"))]
         rcc.d2ccip2r.modify(|_, w| unsafe {
-            w.usart234578sel().bits(0b111);
+            w.usart234578sel().bits(self.usart234578_src as u8);
+            #[cfg(feature = "h735")]
+            w.usart16910sel().bits(self.usart16_src as u8);
+            #[cfg(not(feature = "h735"))]
+            w.usart16sel().bits(self.usart16_src as u8);
+            #[cfg(feature = "h735")]
+            w.i2c1235sel().bits(self.i2c123_src as u8);
+            #[cfg(not(feature = "h735"))]
+            w.i2c123sel().bits(self.i2c123_src as u8);
+            w.lptim1sel().bits(self.lptim1_src as u8);
             w.usbsel().bits(self.usb_src as u8)
         });
 
         #[cfg(not(feature = "h7b3"))]
         rcc.d3ccipr.modify(|_, w| unsafe {
             w.sai4asel().bits(self.sai4a_src as u8);
-            w.sai4bsel().bits(self.sai4b_src as u8)
+            w.sai4bsel().bits(self.sai4b_src as u8);
+            w.lpuart1sel().bits(self.lpuart1_src as u8);
+            w.i2c4sel().bits(self.i2c4_src as u8);
+            w.spi6sel().bits(self.spi6_src as u8);
+            w.adcsel().bits(self.adc_src as u8);
+            w.lptim2sel().bits(self.lptim2_src as u8);
+            w.lptim345sel().bits(self.lptim345_src as u8)
         });
 
         rcc.cr.modify(|_, w| w.hsecsson().bit(self.security_system));
 
-        // todo: Allow configuring the PLL in fractional mode.
-
-        rcc.pllckselr
-            .modify(|_, w| w.pllsrc().bits(self.pll_src.bits()));
+        // PLLSRC can only be written with all PLLs off; it's unchanged if any are kept running.
+        if !keep_pll2 && !keep_pll3 {
+            rcc.pllckselr
+                .modify(|_, w| w.pllsrc().bits(self.pll_src.bits()));
+        }
 
         // Note that with this code setup, PLL2 and PLL3 won't work properly unless using
         // the input source is PLL1.
@@ -575,8 +863,8 @@ impl Clocks {
             // 0: Wide VCO range: 192 to 836 MHz (default after reset)
             // 1: Medium VCO range: 150 to 420 MHz
             let pll1_vco = match self.pll_input_speed(self.pll_src, 1) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             // The user application can then configure the proper VCO: if the frequency of the reference
@@ -611,13 +899,22 @@ impl Clocks {
                 w.divr1().bits(self.pll1.divr - 1)
             });
 
+            // The FRACN value is latched when PLLxFRACEN changes from 0 to 1. With PLLxFRACEN
+            // cleared, the PLL runs in integer mode.
+            rcc.pllcfgr.modify(|_, w| w.pll1fracen().clear_bit());
+            if let Some(fracn) = self.pll1.fracn {
+                rcc.pll1fracr
+                    .modify(|_, w| unsafe { w.fracn1().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll1fracen().set_bit());
+            }
+
             // Now turn PLL back on, once we're configured things that can only be set with it off.
             rcc.cr.modify(|_, w| w.pll1on().set_bit());
             while rcc.cr.read().pll1rdy().bit_is_clear() {}
         }
 
         // todo DRY
-        if self.pll2.enabled {
+        if self.pll2.enabled && !keep_pll2 {
             rcc.cr.modify(|_, w| w.pll2on().clear_bit());
             while rcc.cr.read().pll2rdy().bit_is_set() {}
 
@@ -630,8 +927,8 @@ impl Clocks {
             };
 
             let pll2_vco = match self.pll_input_speed(self.pll_src, 2) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             rcc.pllckselr.modify(|_, w| w.divm2().bits(self.pll2.divm));
@@ -651,11 +948,18 @@ impl Clocks {
                 w.divr2().bits(self.pll2.divr - 1)
             });
 
+            rcc.pllcfgr.modify(|_, w| w.pll2fracen().clear_bit());
+            if let Some(fracn) = self.pll2.fracn {
+                rcc.pll2fracr
+                    .modify(|_, w| unsafe { w.fracn2().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll2fracen().set_bit());
+            }
+
             rcc.cr.modify(|_, w| w.pll2on().set_bit());
             while rcc.cr.read().pll2rdy().bit_is_clear() {}
         }
 
-        if self.pll3.enabled {
+        if self.pll3.enabled && !keep_pll3 {
             rcc.cr.modify(|_, w| w.pll3on().clear_bit());
             while rcc.cr.read().pll3rdy().bit_is_set() {}
 
@@ -668,8 +972,8 @@ impl Clocks {
             };
 
             let pll3_vco = match self.pll_input_speed(self.pll_src, 3) {
-                0..=2_000_000 => 0,
-                _ => 1,
+                0..=1_999_999 => 1,
+                _ => 0,
             };
 
             rcc.pllckselr.modify(|_, w| w.divm3().bits(self.pll3.divm));
@@ -689,6 +993,13 @@ impl Clocks {
                 w.divr3().bits(self.pll3.divr - 1)
             });
 
+            rcc.pllcfgr.modify(|_, w| w.pll3fracen().clear_bit());
+            if let Some(fracn) = self.pll3.fracn {
+                rcc.pll3fracr
+                    .modify(|_, w| unsafe { w.fracn3().bits(fracn) });
+                rcc.pllcfgr.modify(|_, w| w.pll3fracen().set_bit());
+            }
+
             rcc.cr.modify(|_, w| w.pll3on().set_bit());
             while rcc.cr.read().pll3rdy().bit_is_clear() {}
         }
@@ -701,6 +1012,72 @@ impl Clocks {
         Ok(())
     }
 
+    /// Change clock speeds at runtime, eg to scale SYSCLK down when idle. Validates `new`, then
+    /// switches SYSCLK to the CSI while the oscillators, PLLs, voltage scaling, and flash wait states
+    /// are reconfigured. Since the CSI is slow enough to run with any wait state and voltage
+    /// setting, this keeps the transition glitch-free in both directions. PLL2 and PLL3 are left
+    /// running if neither their settings nor the PLL source change. Unlike `setup`, this doesn't
+    /// reset SYSCFG, so EXTI line mappings are kept. On success, `self` is updated to `new`.
+    /// Afterwards, run `Reclock::reclock` on peripherals that derive settings from the clock
+    /// speeds, eg `Usart`, `I2c`, and `Timer`.
+    pub fn change_speed(&mut self, new: Self) -> Result<(), SpeedError> {
+        new.validate_speeds()?;
+
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        rcc.cr.modify(|_, w| w.csion().set_bit());
+        while rcc.cr.read().csirdy().bit_is_clear() {}
+
+        rcc.cfgr
+            .modify(|_, w| unsafe { w.sw().bits(InputSrc::Csi.bits()) });
+        while rcc.cfgr.read().sws().bits() != InputSrc::Csi.bits() {}
+
+        // PLL2 and PLL3 keep running if their settings, and the PLL source they share with PLL1,
+        // are unchanged; eg so peripherals clocked from them aren't interrupted. Otherwise, they're
+        // stopped here, since the PLL source can only be changed with all PLLs off. `setup`
+        // re-enables the ones in use.
+        let src_changed = new.pll_src != self.pll_src;
+        let keep_pll2 = !src_changed && new.pll2 == self.pll2 && self.pll2.enabled;
+        let keep_pll3 = !src_changed && new.pll3 == self.pll3 && self.pll3.enabled;
+
+        rcc.cr.modify(|_, w| {
+            w.pll1on().clear_bit();
+            if !keep_pll2 {
+                w.pll2on().clear_bit();
+            }
+            if !keep_pll3 {
+                w.pll3on().clear_bit();
+            }
+            w
+        });
+        while rcc.cr.read().pll1rdy().bit_is_set()
+            || (!keep_pll2 && rcc.cr.read().pll2rdy().bit_is_set())
+            || (!keep_pll3 && rcc.cr.read().pll3rdy().bit_is_set())
+        {}
+
+        // `setup` handles activating VOS0, but not deactivating it. H743 RM, section 6.6.2:
+        // "The sequence to deactivate the VOS0 is the following: 1. Ensure that the system frequency
+        // was decreased. 2. Ensure that the SYSCFG clock is enabled in the RCC [...] 3. Reset the
+        // ODEN bit in the SYSCFG_PWRCR register to disable VOS0."
+        #[cfg(not(any(feature = "h7b3", feature = "h735")))]
+        if self.vos_range == VosRange::VOS0 && new.vos_range != VosRange::VOS0 {
+            let syscfg = unsafe { &(*SYSCFG::ptr()) };
+            syscfg.pwrcr.modify(|_, w| w.oden().clear_bit());
+        }
+
+        new.setup_keeping(keep_pll2, keep_pll3)?;
+
+        while rcc.cfgr.read().sws().bits() != new.input_src.bits() {}
+
+        if new.input_src != InputSrc::Csi && new.pll_src != PllSrc::Csi && !new.csi_kernel_used() {
+            rcc.cr.modify(|_, w| w.csion().clear_bit());
+        }
+
+        *self = new;
+
+        Ok(())
+    }
+
     /// Re-select input source; used on Stop and Standby modes, where the system reverts
     /// to HSI after wake.
     pub fn reselect_input(&self) {
@@ -783,13 +1160,12 @@ impl Clocks {
         }
     }
 
-    /// Calculate VCO output frequency: = Fref1_ck x DIVN1
+    /// Calculate VCO output frequency: = Fref1_ck x (DIVN1 + FRACN1 / 8192)
     pub fn vco_output_freq(&self, pll_src: PllSrc, pll_num: u8) -> u32 {
-        let input_speed = self.pll_input_speed(pll_src, pll_num);
         match pll_num {
-            1 => input_speed * self.pll1.divn as u32,
-            2 => input_speed * self.pll2.divn as u32,
-            3 => input_speed * self.pll3.divn as u32,
+            1 => self.pll1.vco_freq(pll_src.freq()),
+            2 => self.pll2.vco_freq(pll_src.freq()),
+            3 => self.pll3.vco_freq(pll_src.freq()),
             _ => panic!("Pll num must be between 1 and 3."),
         }
     }
@@ -811,11 +1187,7 @@ impl Clocks {
     /// CPU2 syclock is equal to the HCLK, so use the `hclk()` method.
     pub fn sysclk(&self) -> u32 {
         match self.input_src {
-            InputSrc::Pll1 => {
-                // divm1 is included in `pll_input_speed`.
-                self.pll_input_speed(self.pll_src, 1) * self.pll1.divn as u32
-                    / self.pll1.divp as u32
-            }
+            InputSrc::Pll1 => self.vco_output_freq(self.pll_src, 1) / self.pll1.divp as u32,
             InputSrc::Csi => 4_000_000,
             InputSrc::Hsi(div) => 64_000_000 / (div.value() as u32),
             InputSrc::Hse(freq) => freq,
@@ -841,9 +1213,12 @@ impl Clocks {
 
     /// Get the USB clock frequency, in hz
     pub fn usb(&self) -> u32 {
-        // let (input_freq, _) = sysclock(self.input_src, self.divm1, self.divn1, self.divp1);
-        // (input_freq * 1_000_000) as u32 / self.divm1 as u32 * self.pll_sai1_mul as u32 / 2
-        0 // todo
+        match self.usb_src {
+            UsbSrc::Disabled => 0,
+            UsbSrc::Pll1Q => self.vco_output_freq(self.pll_src, 1) / self.pll1.divq as u32,
+            UsbSrc::Pll3Q => self.vco_output_freq(self.pll_src, 3) / self.pll3.divq as u32,
+            UsbSrc::Hsi48 => 48_000_000,
+        }
     }
 
     pub fn apb1(&self) -> u32 {
@@ -874,24 +1249,14 @@ impl Clocks {
 
     /// Get the SAI1 audio clock frequency, in hz
     pub fn sai1_speed(&self) -> u32 {
-        let pll_src = match self.input_src {
-            InputSrc::Pll1 => self.pll_src,
-            InputSrc::Csi => PllSrc::Csi,
-            InputSrc::Hsi(div) => PllSrc::Hsi(div),
-            InputSrc::Hse(freq) => PllSrc::Hse(freq),
-        };
-
         match self.sai1_src {
-            SaiSrc::Pll1Q => {
-                self.pll_input_speed(pll_src, 1) * self.pll1.divn as u32 / self.pll1.divq as u32
-            }
-            SaiSrc::Pll2P => {
-                self.pll_input_speed(pll_src, 1) * self.pll2.divn as u32 / self.pll2.divp as u32
-            }
-            SaiSrc::Pll3P => {
-                self.pll_input_speed(pll_src, 1) * self.pll3.divn as u32 / self.pll3.divp as u32
-            }
+            SaiSrc::Pll1Q => self.pll_output(1, self.pll1.divq),
+            SaiSrc::Pll2P => self.pll_output(2, self.pll2.divp),
+            SaiSrc::Pll3P => self.pll_output(3, self.pll3.divp),
             SaiSrc::I2sCkin => unimplemented!(),
+            #[cfg(not(feature = "h7b3"))]
+            SaiSrc::PerClk => self.per_ck(),
+            #[cfg(feature = "h7b3")]
             SaiSrc::PerClk => unimplemented!(),
         }
     }
@@ -899,59 +1264,42 @@ impl Clocks {
     pub fn validate_speeds(&self) -> Result<(), SpeedError> {
         cfg_if! {
             if #[cfg(feature = "h735")] {
-                let max_sysclk = 480_000_000;
-            } else {
+                // 550Mhz requires the CPUFREQ_BOOST option byte; `setup` checks this.
                 let max_sysclk = 550_000_000;
+                let max_hclk = 275_000_000;
+                let max_apb = 137_500_000;
+            } else if #[cfg(feature = "h7b3")] {
+                let max_sysclk = 280_000_000;
+                let max_hclk = 280_000_000;
+                let max_apb = 140_000_000;
+            } else {
+                let max_sysclk = 480_000_000;
+                let max_hclk = 240_000_000;
+                let max_apb = 120_000_000; // todo: Different depending on apb
             }
         }
-        // #[cfg(feature = "h743")]
-        let max_hclk = 240_000_000;
-        // #[cfg(feature = "h743")]
-        let max_apb = 120_000_000; // todo: Different depending on apb
-
-        // todo: PLL input (Post DIVM1, pre DIVN1) must be between 1Mhz and 16 Mhz
-
-        // todo after pool today: FIgure out what other bit needs to be set to reflect this.
-
-        // todo: Are these valid for all H7 configs?
-        if self.pll1.divm > 63
-            || self.pll1.divm > 63
-            || self.pll3.divm > 63
-            || self.pll1.divn > 512
-            || self.pll2.divn > 512
-            || self.pll3.divn > 512
-            || self.pll1.divp > 128
-            || self.pll2.divp > 128
-            || self.pll3.divp > 128
-            || self.pll1.divp < 2
-            || self.pll2.divp < 2
-            || self.pll3.divp < 2
-        // todo: R and Q
-        {
-            return Err(SpeedError::new("A PLL divider is out of limits"));
-        }
 
+        // PLL1 is validated if it's used for SYSCLK; PLL2 and 3 if they're enabled.
+        let src_freq = self.pll_src.freq();
         if let InputSrc::Pll1 = self.input_src {
-            let pll_input_speed = self.pll_input_speed(self.pll_src, 1);
-            if pll_input_speed < 1_000_000 || pll_input_speed > 16_000_000 {
-                return Err(SpeedError::new("Invalid PLL input speed"));
-            }
-            // VCO0: Wide VCO range: 192 to 836 MHz (default after reset) (VCOH)
-            // Note: The RM appears out of date: Revision "V" allgedly supports 960_000_000
-            // VCO speed, to allow a max core speed of 480Mhz.
-            let vco_speed = self.vco_output_freq(self.pll_src, 1);
-            if pll_input_speed <= 2_000_000 && (vco_speed < 192_000_000 || vco_speed > 960_000_000)
-            {
-                return Err(SpeedError::new("Invalid wide VCO speed"));
-            }
-            // 1: Medium VCO range: 150 to 420 MHz. (VCOL)
-            // Note: You may get power savings
-            if pll_input_speed > 2_000_000 && (vco_speed < 150_000_000 || vco_speed > 420_000_000) {
-                return Err(SpeedError::new("Invalid medium VCO speed"));
+            self.pll1.validate(src_freq)?;
+
+            // H743 RM, RCC_PLL1DIVR register: DIVP1 "odd division factors are not allowed".
+            // H723 RM allows a division factor of 1, for 550Mhz operation.
+            #[cfg(feature = "h735")]
+            let divp_valid = self.pll1.divp == 1 || self.pll1.divp % 2 == 0;
+            #[cfg(not(feature = "h735"))]
+            let divp_valid = self.pll1.divp % 2 == 0;
+            if !divp_valid {
+                return Err(SpeedError::new("PLL1 DIVP must be even"));
             }
         }
-
-        // todo: Validate PLL2 and PLL3 speeds.
+        if self.pll2.enabled {
+            self.pll2.validate(src_freq)?;
+        }
+        if self.pll3.enabled {
+            self.pll3.validate(src_freq)?;
+        }
 
         // todo: More work on this, including feature gates
 
@@ -982,6 +1330,242 @@ impl Clocks {
 
 // todo: support default for 280Mhz variants.
 
+impl Clocks {
+    /// Returns true if any peripheral kernel clock is set to `csi_ker_ck`.
+    fn csi_kernel_used(&self) -> bool {
+        [self.usart16_src, self.usart234578_src, self.lpuart1_src].contains(&UsartSrc::CsiKer)
+            || [self.i2c123_src, self.i2c4_src].contains(&I2cSrc::CsiKer)
+            || [self.spi45_src, self.spi6_src].contains(&Spi456Src::CsiKer)
+            || (self.per_ck_src == PerCkSrc::CsiKer && self.per_ck_used())
+    }
+
+    /// Returns true if any peripheral kernel clock is set to `per_ck`.
+    fn per_ck_used(&self) -> bool {
+        #[cfg(not(feature = "h735"))]
+        let sai23_per_ck = self.sai23_src == SaiSrc::PerClk;
+        #[cfg(feature = "h735")]
+        let sai23_per_ck = false;
+
+        [self.sai1_src, self.sai4a_src, self.sai4b_src].contains(&SaiSrc::PerClk)
+            || sai23_per_ck
+            || self.spi123_src == Spi123Src::PerCk
+            || self.adc_src == AdcSrc::PerCk
+            || self.qspi_src == QspiSrc::PerCk
+            || [self.lptim1_src, self.lptim2_src, self.lptim345_src].contains(&LptimSrc::PerCk)
+    }
+
+    /// Returns true if any peripheral kernel clock is set to the LSE.
+    fn lse_kernel_used(&self) -> bool {
+        [self.usart16_src, self.usart234578_src, self.lpuart1_src].contains(&UsartSrc::Lse)
+            || [self.lptim1_src, self.lptim2_src, self.lptim345_src].contains(&LptimSrc::Lse)
+    }
+
+    /// The HSE frequency, if it's used as the input source or PLL source. Otherwise, 0.
+    fn hse_freq(&self) -> u32 {
+        match (self.input_src, self.pll_src) {
+            (InputSrc::Hse(freq), _) | (_, PllSrc::Hse(freq)) => freq,
+            _ => 0,
+        }
+    }
+
+    /// The `hsi_ker_ck` frequency, in Hz. This is affected by HSIDIV.
+    fn hsi_ker(&self) -> u32 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        64_000_000 >> rcc.cr.read().hsidiv().bits()
+    }
+
+    #[cfg(not(feature = "h7b3"))]
+    /// The `per_ck` frequency, in Hz. H743 RM: "00: hsi_ker_ck clock selected as per_ck clock,
+    /// 01: csi_ker_ck clock selected as per_ck clock, 10: hse_ck clock selected as per_ck clock"
+    fn per_ck(&self) -> u32 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        match rcc.d1ccipr.read().ckpersel().bits() {
+            0b00 => self.hsi_ker(),
+            0b01 => 4_000_000,
+            0b10 => self.hse_freq(),
+            _ => 0,
+        }
+    }
+
+    /// Get a PLL output frequency, in Hz, from its VCO output, and the output's divider.
+    fn pll_output(&self, pll_num: u8, div: u8) -> u32 {
+        self.vco_output_freq(self.pll_src, pll_num) / div as u32
+    }
+
+    /// The APB4 frequency, in Hz. Used by LPUART1, I2C4, and SPI6 as `rcc_pclk4`.
+    fn apb4(&self) -> u32 {
+        self.hclk() / self.d3_prescaler.value() as u32
+    }
+}
+
+impl ClockTree for Clocks {
+    fn sysclk(&self) -> u32 {
+        Clocks::sysclk(self)
+    }
+
+    fn hclk(&self) -> u32 {
+        Clocks::hclk(self)
+    }
+
+    fn apb1(&self) -> u32 {
+        Clocks::apb1(self)
+    }
+
+    fn apb2(&self) -> u32 {
+        Clocks::apb2(self)
+    }
+
+    fn apb1_timer(&self) -> u32 {
+        Clocks::apb1_timer(self)
+    }
+
+    fn apb2_timer(&self) -> u32 {
+        Clocks::apb2_timer(self)
+    }
+
+    #[cfg(feature = "h7b3")]
+    fn kernel_clock(&self, periph: KernelClockPeriph) -> u32 {
+        // todo: H7B3 uses different kernel clock registers (CDCCIP1R etc).
+        match periph {
+            KernelClockPeriph::Usart1
+            | KernelClockPeriph::Usart6
+            | KernelClockPeriph::Spi4
+            | KernelClockPeriph::Spi5 => self.apb2(),
+            KernelClockPeriph::Lpuart1 | KernelClockPeriph::I2c4 | KernelClockPeriph::Spi6 => {
+                self.apb4()
+            }
+            KernelClockPeriph::Spi1 | KernelClockPeriph::Spi2 | KernelClockPeriph::Spi3 => {
+                self.pll_output(1, self.pll1.divq)
+            }
+            KernelClockPeriph::Adc => self.pll_output(2, self.pll2.divp),
+            KernelClockPeriph::Fdcan => self.hse_freq(),
+            KernelClockPeriph::Qspi => self.hclk(),
+            _ => self.apb1(),
+        }
+    }
+
+    #[cfg(not(feature = "h7b3"))]
+    fn kernel_clock(&self, periph: KernelClockPeriph) -> u32 {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        let d1ccipr = rcc.d1ccipr.read().bits();
+        let d2ccip1r = rcc.d2ccip1r.read().bits();
+        let d2ccip2r = rcc.d2ccip2r.read().bits();
+        let d3ccipr = rcc.d3ccipr.read().bits();
+
+        // H743 RM, RCC_D2CCIP2R register: "000: rcc_pclk2 clock is selected as kernel clock (default),
+        // 001: pll2_q_ck clock is selected as kernel clock, 010: pll3_q_ck clock is selected as
+        // kernel clock, 011: hsi_ker_ck clock is selected as kernel clock, 100: csi_ker_ck clock
+        // is selected as kernel clock, 101: lse_ck clock is selected as kernel clock"
+        // SPI4, 5, and 6 use the same encoding, but with `hse_ck` instead of `lse_ck`.
+        let usart_spi = |pclk: u32, sel: u32, last: u32| match sel & 0b111 {
+            0b000 => pclk,
+            0b001 => self.pll_output(2, self.pll2.divq),
+            0b010 => self.pll_output(3, self.pll3.divq),
+            0b011 => self.hsi_ker(),
+            0b100 => 4_000_000,
+            0b101 => last,
+            _ => 0,
+        };
+
+        // "00: rcc_pclk1 clock is selected as kernel clock (default), 01: pll3_r_ck clock is
+        // selected as kernel clock, 10: hsi_ker_ck clock is selected as kernel clock,
+        // 11: csi_ker_ck clock is selected as kernel clock"
+        let i2c = |pclk: u32, sel: u32| match sel & 0b11 {
+            0b00 => pclk,
+            0b01 => self.pll_output(3, self.pll3.divr),
+            0b10 => self.hsi_ker(),
+            _ => 4_000_000,
+        };
+
+        // "000: rcc_pclk1 clock is selected as kernel peripheral clock (default), 001: pll2_p_ck
+        // clock is selected as kernel peripheral clock, 010: pll3_r_ck clock is selected as kernel
+        // peripheral clock, 011: lse_ck clock is selected as kernel peripheral clock, 100: lsi_ck
+        // clock is selected as kernel peripheral clock, 101: per_ck clock is selected as kernel
+        // peripheral clock"
+        let lptim = |pclk: u32, sel: u32| match sel & 0b111 {
+            0b000 => pclk,
+            0b001 => self.pll_output(2, self.pll2.divp),
+            0b010 => self.pll_output(3, self.pll3.divr),
+            0b011 => 32_768,
+            0b100 => 32_000,
+            0b101 => self.per_ck(),
+            _ => 0,
+        };
+
+        match periph {
+            KernelClockPeriph::Usart1
+            | KernelClockPeriph::Usart6
+            | KernelClockPeriph::Uart9
+            | KernelClockPeriph::Usart10 => usart_spi(self.apb2(), d2ccip2r >> 3, 32_768),
+            KernelClockPeriph::Usart2
+            | KernelClockPeriph::Usart3
+            | KernelClockPeriph::Uart4
+            | KernelClockPeriph::Uart5
+            | KernelClockPeriph::Uart7
+            | KernelClockPeriph::Uart8 => usart_spi(self.apb1(), d2ccip2r, 32_768),
+            KernelClockPeriph::Lpuart1 => usart_spi(self.apb4(), d3ccipr, 32_768),
+            KernelClockPeriph::I2c1
+            | KernelClockPeriph::I2c2
+            | KernelClockPeriph::I2c3
+            | KernelClockPeriph::I2c5 => i2c(self.apb1(), d2ccip2r >> 12),
+            KernelClockPeriph::I2c4 => i2c(self.apb4(), d3ccipr >> 8),
+            KernelClockPeriph::Spi1 | KernelClockPeriph::Spi2 | KernelClockPeriph::Spi3 => {
+                // "000: pll1_q_ck clock selected as SPI/I2S1,2 and 3 kernel clock (default),
+                // 001: pll2_p_ck clock selected as SPI/I2S1,2 and 3 kernel clock, 010: pll3_p_ck
+                // clock selected as SPI/I2S1,2 and 3 kernel clock, 011: I2S_CKIN clock selected as
+                // SPI/I2S1,2 and 3 kernel clock, 100: per_ck clock selected as SPI/I2S1,2 and 3
+                // kernel clock"
+                match (d2ccip1r >> 12) & 0b111 {
+                    0b000 => self.pll_output(1, self.pll1.divq),
+                    0b001 => self.pll_output(2, self.pll2.divp),
+                    0b010 => self.pll_output(3, self.pll3.divp),
+                    0b100 => self.per_ck(),
+                    _ => 0, // I2S_CKIN is external; we don't know its speed.
+                }
+            }
+            KernelClockPeriph::Spi4 | KernelClockPeriph::Spi5 => {
+                usart_spi(self.apb2(), d2ccip1r >> 16, self.hse_freq())
+            }
+            KernelClockPeriph::Spi6 => usart_spi(self.apb4(), d3ccipr >> 28, self.hse_freq()),
+            KernelClockPeriph::Lptim1 => lptim(self.apb1(), d2ccip2r >> 28),
+            KernelClockPeriph::Lptim2 => lptim(self.apb4(), d3ccipr >> 10),
+            KernelClockPeriph::Adc => {
+                // "00: pll2_p_ck clock selected as kernel peripheral clock (default), 01: pll3_r_ck
+                // clock selected as kernel peripheral clock, 10: per_ck clock selected as kernel
+                // peripheral clock"
+                match (d3ccipr >> 16) & 0b11 {
+                    0b00 => self.pll_output(2, self.pll2.divp),
+                    0b01 => self.pll_output(3, self.pll3.divr),
+                    0b10 => self.per_ck(),
+                    _ => 0,
+                }
+            }
+            KernelClockPeriph::Fdcan => {
+                // "00: hse_ck clock is selected as kernel clock (default), 01: pll1_q_ck clock is
+                // selected as kernel clock, 10: pll2_q_ck clock is selected as kernel clock"
+                match (d2ccip1r >> 28) & 0b11 {
+                    0b00 => self.hse_freq(),
+                    0b01 => self.pll_output(1, self.pll1.divq),
+                    0b10 => self.pll_output(2, self.pll2.divq),
+                    _ => 0,
+                }
+            }
+            KernelClockPeriph::Qspi => {
+                // "00: rcc_hclk3 clock selected as kernel peripheral clock (default), 01: pll1_q_ck
+                // clock selected as kernel peripheral clock, 10: pll2_r_ck clock selected as kernel
+                // peripheral clock, 11: per_ck clock selected as kernel peripheral clock"
+                match (d1ccipr >> 4) & 0b11 {
+                    0b00 => self.hclk(),
+                    0b01 => self.pll_output(1, self.pll1.divq),
+                    0b10 => self.pll_output(2, self.pll2.divr),
+                    _ => self.per_ck(),
+                }
+            }
+        }
+    }
+}
+
 impl Default for Clocks {
     /// This default configures clocks with the HSI, and a 400Mhz sysclock speed. (280Mhz sysclock
     /// on variants that only go that high). Note that H723-745 still use this default speed
@@ -1019,39 +1603,279 @@ impl Default for Clocks {
             sai4a_src: SaiSrc::Pll1Q,
             sai4b_src: SaiSrc::Pll1Q,
             dfsdm1_src: DfsdmSrc::Pclk2,
+            usart16_src: UsartSrc::Pclk,
+            usart234578_src: UsartSrc::Pclk,
+            lpuart1_src: UsartSrc::Pclk,
+            i2c123_src: I2cSrc::Pclk,
+            i2c4_src: I2cSrc::Pclk,
+            spi123_src: Spi123Src::Pll1Q,
+            spi45_src: Spi456Src::Pclk,
+            spi6_src: Spi456Src::Pclk,
+            adc_src: AdcSrc::Pll2P,
+            lptim1_src: LptimSrc::Pclk,
+            lptim2_src: LptimSrc::Pclk,
+            lptim345_src: LptimSrc::Pclk,
+            fdcan_src: FdcanSrc::Hse,
+            qspi_src: QspiSrc::Hclk,
+            per_ck_src: PerCkSrc::HsiKer,
+        }
+    }
+}
+
+impl PllSrc {
+    /// The frequency of this source, in Hz. 0 for `None`.
+    fn freq(&self) -> u32 {
+        match self {
+            Self::Csi => 4_000_000,
+            Self::Hsi(div) => 64_000_000 / (div.value() as u32),
+            Self::Hse(freq) => *freq,
+            Self::None => 0,
+        }
+    }
+}
+
+/// PLL1 settings found by `Clocks::with_sysclk`.
+struct PllSolution {
+    divm: u8,
+    divn: u16,
+    divp: u8,
+    divq: Option<u8>,
+    sysclk: u32,
+}
+
+/// Search DIVM1, DIVN1, and DIVP1 for the SYSCLK closest to `target` without exceeding it. The
+/// reference clock (after DIVM) is kept between 2 and 16Mhz, so the wide VCO range is used.
+/// If `usb` is true, only accept settings where a DIVQ1 produces exactly 48Mhz. Earlier (lower
+/// DIVM and DIVN) settings are preferred on ties; these have lower VCO speeds, which reduces power
+/// use. Only dividers that divide their input frequency exactly are used, so the speeds
+/// calculated from the config are exact.
+fn solve_pll(input_freq: u32, target: u32, usb: bool) -> Option<PllSolution> {
+    if target == 0 {
+        return None;
+    }
+
+    let mut best: Option<PllSolution> = None;
+
+    for divm in 1..=63_u8 {
+        let ref_freq = input_freq / divm as u32;
+        if ref_freq < 2_000_000 {
+            break;
+        }
+        if ref_freq > 16_000_000 || input_freq % divm as u32 != 0 {
+            continue;
+        }
+
+        for divn in 4..=512_u16 {
+            let vco_out = ref_freq * divn as u32;
+            if vco_out < VCO_WIDE_RANGE.0 {
+                continue;
+            }
+            if vco_out > VCO_WIDE_RANGE.1 {
+                break;
+            }
+
+            let divq = if usb {
+                if vco_out % 48_000_000 != 0 || vco_out / 48_000_000 > 128 {
+                    continue;
+                }
+                Some((vco_out / 48_000_000) as u8)
+            } else {
+                None
+            };
+
+            // DIVP1 must be even, except that H723-35 also allow 1. Use the smallest that doesn't
+            // exceed the target, and divides the VCO output exactly.
+            let mut divp = (vco_out + target - 1) / target;
+            #[cfg(feature = "h735")]
+            let divp_1_allowed = divp == 1;
+            #[cfg(not(feature = "h735"))]
+            let divp_1_allowed = false;
+            if !divp_1_allowed {
+                divp += divp % 2;
+                if divp < 2 {
+                    divp = 2;
+                }
+            }
+            while divp <= 128 && vco_out % divp != 0 {
+                divp += 2;
+            }
+            if divp > 128 {
+                continue;
+            }
+
+            let sysclk = vco_out / divp;
+            if best.as_ref().map(|b| sysclk > b.sysclk).unwrap_or(true) {
+                best = Some(PllSolution {
+                    divm,
+                    divn,
+                    divp: divp as u8,
+                    divq,
+                    sysclk,
+                });
+            }
+        }
+    }
+
+    best
+}
+
+impl Clocks {
+    /// Create a config that runs SYSCLK from PLL1 (P output), fed by `pll_src`, as close as
+    /// possible to `sysclk` (in Hz) without exceeding it. DIVM1, DIVN1, and DIVP1 are chosen
+    /// within the reference clock and VCO limits. `vos_range` is set to VOS0 for speeds above
+    /// 400Mhz; other settings are as in `Default`. Returns an error if no valid PLL settings exist,
+    /// or the result is out of limits.
+    pub fn with_sysclk(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, false)
+    }
+
+    /// Similar to `with_sysclk`, but also sets DIVQ1 to produce exactly 48Mhz, and selects PLL1Q
+    /// as the USB clock source.
+    pub fn with_sysclk_usb(sysclk: u32, pll_src: PllSrc) -> Result<Self, SpeedError> {
+        Self::from_pll_search(sysclk, pll_src, true)
+    }
+
+    /// Create the config to switch to if the HSE fails, eg with `clocks::handle_hse_failure`:
+    /// This config, with the PLLs fed by the HSI, and PLL1 set to approximate this config's
+    /// SYSCLK. If PLL1 can't do this within its limits, SYSCLK runs from the HSI directly. If USB
+    /// is clocked from PLL1Q, and the HSI can't also produce 48Mhz there, it's switched to the
+    /// HSI48. PLL2 and PLL3 are retuned to keep their speeds from the HSI, or disabled if they
+    /// can't. Kernel clocks set to the HSE are moved to `hsi_ker_ck`; FDCAN, which has no HSI
+    /// option, is moved to PLL1Q, so its bit timing needs to be set up again.
+    pub fn hse_fallback(&self) -> Self {
+        let mut result = self.clone();
+        result.security_system = false;
+        result.hse_bypass = false;
+
+        let hsi = PllSrc::Hsi(HsiDiv::Div1);
+        let target = self.sysclk();
+
+        let usb = self.usb_src == UsbSrc::Pll1Q;
+        let soln = if usb {
+            solve_pll(hsi.freq(), target, true)
+        } else {
+            None
+        };
+
+        if usb && soln.is_none() {
+            result.usb_src = UsbSrc::Hsi48;
+            result.hsi48_on = true;
+        }
+
+        match soln.or_else(|| solve_pll(hsi.freq(), target, false)) {
+            Some(s) => {
+                result.input_src = InputSrc::Pll1;
+                result.pll1.divm = s.divm;
+                result.pll1.divn = s.divn;
+                result.pll1.divp = s.divp;
+                result.pll1.fracn = None;
+                if let Some(divq) = s.divq {
+                    result.pll1.divq = divq;
+                    result.pll1.pllq_en = true;
+                }
+            }
+            None => result.input_src = InputSrc::Hsi(HsiDiv::Div1),
+        }
+        result.pll_src = hsi;
+
+        // `setup` only configures PLL2 and PLL3 when SYSCLK is from PLL1.
+        let pll1_used = matches!(result.input_src, InputSrc::Pll1);
+        for pll in [&mut result.pll2, &mut result.pll3] {
+            if !pll.enabled {
+                continue;
+            }
+            match pll.refit(self.pll_src.freq(), hsi.freq()) {
+                Some(cfg) if pll1_used => *pll = cfg,
+                _ => *pll = PllCfg::disabled(),
+            }
+        }
+
+        if result.per_ck_src == PerCkSrc::Hse {
+            result.per_ck_src = PerCkSrc::HsiKer;
+        }
+        if result.spi45_src == Spi456Src::Hse {
+            result.spi45_src = Spi456Src::HsiKer;
+        }
+        if result.spi6_src == Spi456Src::Hse {
+            result.spi6_src = Spi456Src::HsiKer;
+        }
+        if result.fdcan_src == FdcanSrc::Hse {
+            result.fdcan_src = FdcanSrc::Pll1Q;
+            // Keep the FDCAN kernel clock at or below the HSE speed, unless PLL1Q is already in
+            // use for something else.
+            if !result.pll1.pllq_en {
+                let vco = result.pll1.vco_freq(hsi.freq());
+                let hse = self.hse_freq().max(1);
+                result.pll1.divq = ((vco + hse - 1) / hse).clamp(1, 128) as u8;
+                result.pll1.pllq_en = true;
+            }
+        }
+
+        result
+    }
+
+    fn from_pll_search(sysclk: u32, pll_src: PllSrc, usb: bool) -> Result<Self, SpeedError> {
+        let input_freq = pll_src.freq();
+        if input_freq == 0 {
+            return Err(SpeedError::new("A PLL source must be selected"));
+        }
+
+        let soln = match solve_pll(input_freq, sysclk, usb) {
+            Some(s) => s,
+            None if usb => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce both this sysclk and a 48Mhz PLL1Q",
+                ))
+            }
+            None => {
+                return Err(SpeedError::new(
+                    "No PLL settings produce this sysclk within the VCO limits",
+                ))
+            }
+        };
+
+        #[cfg(not(feature = "h7b3"))]
+        let vos_range = if soln.sysclk > 400_000_000 {
+            VosRange::VOS0
+        } else {
+            VosRange::VOS1
+        };
+        #[cfg(feature = "h7b3")]
+        let vos_range = VosRange::VOS1;
+
+        let mut result = Self {
+            input_src: InputSrc::Pll1,
+            pll_src,
+            pll1: PllCfg {
+                divm: soln.divm,
+                divn: soln.divn,
+                divp: soln.divp,
+                ..Default::default()
+            },
+            vos_range,
+            ..Default::default()
+        };
+
+        if let Some(divq) = soln.divq {
+            result.pll1.divq = divq;
+            result.pll1.pllq_en = true;
+            result.usb_src = UsbSrc::Pll1Q;
         }
+
+        result.validate_speeds()?;
+        Ok(result)
     }
 }
 
 #[cfg(not(feature = "h7b3"))] // todo
 impl Clocks {
     /// Full speed of 480Mhz, with VC0 range 0. Correspondingly higher periph clock speeds as well.
-    /// (520Mhz core speed on H723-35) Note that special consideration needs to be taken
-    /// when using low power modes (ie anything with wfe or wfi) in this mode; may need to manually
-    /// disable and re-enable it.
+    /// (520Mhz core speed on H723-35. See `full_speed_boost` for 550Mhz) Note that special
+    /// consideration needs to be taken when using low power modes (ie anything with wfe or wfi)
+    /// in this mode; may need to manually disable and re-enable it.
     pub fn full_speed() -> Self {
-        // todo: 550Mhz on on H723 etc instead of 520Mhz. Need to set CPU_FREQ_BOOST as well for that.
-        // todo: "The CPU frequency boost can be enabled through the CPUFREQ_BOOST option byte in
-        // todo FLASH_OPTSR2_PRG register."
-        // todo: The change is
-        //
         cfg_if! {
             if #[cfg(feature = "h735")] {
-                // let flash_regs = unsafe { &(*pac::FLASH::ptr()) };
-
-                // To modify user option bytes, follow the sequence below:
-                // unsafe {
-                // 1.Unlock FLASH_OPTCR register as described in Section 4.5.1: FLASH configuration
-                // protection, unless the register is already unlocked.
-                // 2. Write the desired new option byte values in the corresponding option registers
-                // (FLASH_XXX_PRG).
-                // flash_regs.optsr2_prg.modify(|_, w| w.cpufreq_boost().set_bit());
-                // 3. Set the option byte start change OPTSTART bit to 1 in the FLASH_OPTCR register.
-                // flash_regs.optcr.modify(|_, w| w.optstart().set_bit());
-                // 4. Wait until OPT_BUSY bit is cleared.
-                // while flash_regs.optcr.read().opt_busy().bit_is_set() { }
-
-                // let divn 275; // for 550Mhz
                 let divn = 260;
                 let divp = 1;
             } else {
@@ -1069,37 +1893,305 @@ impl Clocks {
             ..Default::default()
         }
     }
+
+    #[cfg(feature = "h735")]
+    /// Similar to `full_speed`, but with a 550Mhz core speed. This requires the CPUFREQ_BOOST
+    /// option byte, which can be set once with `Flash::set_cpu_freq_boost`; `setup` returns an
+    /// error if it's not set.
+    pub fn full_speed_boost() -> Self {
+        Self {
+            pll1: PllCfg {
+                divn: 275,
+                divp: 1,
+                ..Default::default()
+            },
+            vos_range: VosRange::VOS0,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO1 pin. Sets RCC_CFGR register, MCO1 field.
+pub enum Mco1Src {
+    Hsi = 0b000,
+    Lse = 0b001,
+    Hse = 0b010,
+    Pll1Q = 0b011,
+    Hsi48 = 0b100,
 }
 
-// todo
-/// Enable the Clock Recovery System.
-/// "The STM32L443xx devices embed a special block which allows automatic trimming of the
-/// internal 48 MHz oscillator to guarantee its optimal accuracy over the whole device
-/// operational range. This automatic trimming is based on the external synchronization signal,
-/// which could be either derived from USB SOF signalization, from LSE oscillator, from an
-/// external signal on CRS_SYNC pin or generated by user software. For faster lock-in during
-/// startup it is also possible to combine automatic trimming with manual trimming action."
-/// Note: This is for HSI48 only. Note that the HSI will turn off after entering Stop or Standby.
-pub fn enable_crs(sync_src: CrsSyncSrc) {
-    let crs = unsafe { &(*CRS::ptr()) };
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// The clock output on the MCO2 pin. Sets RCC_CFGR register, MCO2 field.
+pub enum Mco2Src {
+    Sysclk = 0b000,
+    Pll2P = 0b001,
+    Hse = 0b010,
+    Pll1P = 0b011,
+    Csi = 0b100,
+    Lsi = 0b101,
+}
+
+/// Output a clock on the MCO1 pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. `div` is the division applied to the output, from 1 to 15. Set the MCO1 pin
+/// (PA8) to alternate function 0 to use it.
+pub fn setup_mco1(src: Mco1Src, div: u8) {
     let rcc = unsafe { &(*RCC::ptr()) };
 
-    rcc.apb1henr.modify(|_, w| w.crsen().set_bit());
+    // MCO1PRE: "0000: prescaler disabled (default), 0001: division by 1 (bypass), 0010: division
+    // by 2, 0011: division by 3, ..., 1111: division by 15"
+    assert!((1..=15).contains(&div), "Invalid MCO divider");
 
-    crs.cfgr
-        .modify(|_, w| unsafe { w.syncsrc().bits(sync_src as u8) });
+    rcc.cfgr.modify(|_, w| unsafe {
+        w.mco1pre().bits(div);
+        w.mco1().bits(src as u8)
+    });
+}
+
+/// Output a clock on the MCO2 pin, eg to clock an external device, or to check clock settings with
+/// an oscilloscope. `div` is the division applied to the output, from 1 to 15. Set the MCO2 pin
+/// (PC9) to alternate function 0 to use it.
+pub fn setup_mco2(src: Mco2Src, div: u8) {
+    let rcc = unsafe { &(*RCC::ptr()) };
 
-    crs.cr.modify(|_, w| {
-        // Set autotrim enabled.
-        w.autotrimen().set_bit();
-        // Enable CRS
-        w.cen().set_bit()
+    assert!((1..=15).contains(&div), "Invalid MCO divider");
+
+    rcc.cfgr.modify(|_, w| unsafe {
+        w.mco2pre().bits(div);
+        w.mco2().bits(src as u8)
     });
+}
 
-    // "The internal 48 MHz RC oscillator is mainly dedicated to provide a high precision clock to
-    // the USB peripheral by means of a special Clock Recovery System (CRS) circuitry. The CRS
-    // can use the USB SOF signal, the LSE or an external signal to automatically and quickly
-    // adjust the oscillator frequency on-fly. It is disabled as soon as the system enters Stop or
-    // Standby mode. When the CRS is not used, the HSI48 RC oscillator runs on its default
-    // frequency which is subject to manufacturing process variations
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the PLL1 dividers `with_sysclk` picked, and the resulting SYSCLK.
+    fn assert_pll(clocks: &Clocks, divm: u8, divn: u16, divp: u8, sysclk: u32) {
+        assert_eq!(clocks.pll1.divm, divm);
+        assert_eq!(clocks.pll1.divn, divn);
+        assert_eq!(clocks.pll1.divp, divp);
+        assert_eq!(clocks.sysclk(), sysclk);
+    }
+
+    #[test]
+    fn with_sysclk() {
+        // 25Mhz HSE / 2 * 64 / 2 = 400Mhz. H723-35 allow DIVP1 = 1, so use a lower VCO speed.
+        let clocks = Clocks::with_sysclk(400_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        #[cfg(not(feature = "h735"))]
+        assert_pll(&clocks, 2, 64, 2, 400_000_000);
+        #[cfg(feature = "h735")]
+        assert_pll(&clocks, 2, 32, 1, 400_000_000);
+        assert!(matches!(clocks.vos_range, VosRange::VOS1));
+
+        // 480Mhz isn't a multiple of the 12.5 or 6.25Mhz reference clocks from DIVM 2 and 4, and
+        // 25Mhz / 3 isn't an integer, so DIVM is 5.
+        let clocks = Clocks::with_sysclk(480_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        #[cfg(not(feature = "h735"))]
+        assert_pll(&clocks, 5, 192, 2, 480_000_000);
+        #[cfg(feature = "h735")]
+        assert_pll(&clocks, 5, 96, 1, 480_000_000);
+        assert!(matches!(clocks.vos_range, VosRange::VOS0));
+
+        // The VCO must be a multiple of 48Mhz for DIVQ1. 400Mhz would need 800Mhz, which isn't;
+        // the closest is 720Mhz / 2 = 360Mhz, with DIVQ1 = 15.
+        let clocks = Clocks::with_sysclk_usb(400_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 5, 144, 2, 360_000_000);
+        assert_eq!(clocks.pll1.divq, 15);
+        assert_eq!(clocks.usb(), 48_000_000);
+
+        assert!(Clocks::with_sysclk(400_000_000, PllSrc::None).is_err());
+    }
+
+    #[cfg(feature = "h735")]
+    #[test]
+    fn with_sysclk_h735() {
+        // These need DIVP1 = 1, since the VCO tops out at 960Mhz.
+        let clocks = Clocks::with_sysclk(520_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 5, 104, 1, 520_000_000);
+
+        let clocks = Clocks::with_sysclk(550_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        assert_pll(&clocks, 2, 44, 1, 550_000_000);
+    }
+
+    /// A PLL config with the given DIVM, DIVN and FRACN, and default output dividers.
+    fn pll(divm: u8, divn: u16, fracn: Option<u16>) -> PllCfg {
+        PllCfg {
+            divm,
+            divn,
+            fracn,
+            ..Default::default()
+        }
+    }
+
+    fn validate_err(cfg: &PllCfg, src_freq: u32) -> &'static str {
+        cfg.validate(src_freq).unwrap_err().error_msg
+    }
+
+    #[test]
+    fn pll_wide_vco() {
+        // 16Mhz reference clock; 192 to 960Mhz VCO.
+        assert!(pll(4, 12, None).validate(64_000_000).is_ok());
+        assert!(pll(4, 60, None).validate(64_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(4, 11, None), 64_000_000),
+            "Invalid wide VCO speed"
+        );
+        assert_eq!(
+            validate_err(&pll(4, 61, None), 64_000_000),
+            "Invalid wide VCO speed"
+        );
+
+        // The default: 2Mhz reference clock, which uses the wide range.
+        assert!(PllCfg::default().validate(64_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(32, 95, None), 64_000_000),
+            "Invalid wide VCO speed"
+        );
+    }
+
+    #[test]
+    fn pll_medium_vco() {
+        // 1.6Mhz reference clock; 150 to 420Mhz VCO.
+        assert!(pll(40, 100, None).validate(64_000_000).is_ok());
+        assert!(pll(40, 262, None).validate(64_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(40, 90, None), 64_000_000),
+            "Invalid medium VCO speed"
+        );
+        // Valid in the wide range, but not the medium one.
+        assert_eq!(
+            validate_err(&pll(40, 300, None), 64_000_000),
+            "Invalid medium VCO speed"
+        );
+    }
+
+    #[test]
+    fn pll_ref_clock() {
+        // 1 to 16Mhz.
+        assert!(pll(1, 30, None).validate(16_000_000).is_ok());
+        assert!(pll(4, 150, None).validate(4_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(1, 30, None), 25_000_000),
+            "Invalid PLL input speed"
+        );
+        assert_eq!(
+            validate_err(&pll(33, 200, None), 32_000_000),
+            "Invalid PLL input speed"
+        );
+        assert_eq!(
+            validate_err(&pll(32, 400, None), 0),
+            "Invalid PLL input speed"
+        );
+    }
+
+    #[test]
+    fn pll_dividers() {
+        for cfg in [
+            pll(0, 400, None),
+            pll(64, 400, None),
+            pll(32, 3, None),
+            pll(32, 513, None),
+            PllCfg {
+                divp: 0,
+                ..Default::default()
+            },
+            PllCfg {
+                divq: 129,
+                ..Default::default()
+            },
+            PllCfg {
+                divr: 0,
+                ..Default::default()
+            },
+        ] {
+            assert_eq!(
+                validate_err(&cfg, 64_000_000),
+                "A PLL divider is out of limits"
+            );
+        }
+    }
+
+    #[test]
+    fn pll_fracn() {
+        // VCO = ref_ck x (DIVN + FRACN / 8192)
+        assert_eq!(pll(32, 400, Some(4_096)).vco_freq(64_000_000), 801_000_000);
+        assert_eq!(pll(32, 400, Some(0)).vco_freq(64_000_000), 800_000_000);
+
+        assert!(pll(4, 59, Some(8_191)).validate(64_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(4, 59, Some(8_192)), 64_000_000),
+            "PLL FRACN must be 8191 or lower"
+        );
+        // The fractional part pushes the VCO over its limit.
+        assert!(pll(4, 60, Some(0)).validate(64_000_000).is_ok());
+        assert_eq!(
+            validate_err(&pll(4, 60, Some(1)), 64_000_000),
+            "Invalid wide VCO speed"
+        );
+    }
+
+    #[test]
+    fn hse_fallback() {
+        let mut clocks = Clocks::with_sysclk(400_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        // 25Mhz / 5 * 160 = 800Mhz. PLL3 uses the medium VCO range, in fractional mode.
+        clocks.pll2 = pll(5, 160, None);
+        clocks.pll3 = pll(25, 200, Some(1_000));
+        clocks.per_ck_src = PerCkSrc::Hse;
+        clocks.spi45_src = Spi456Src::Hse;
+        clocks.spi6_src = Spi456Src::Hse;
+        assert!(clocks.validate_speeds().is_ok());
+
+        let fallback = clocks.hse_fallback();
+        assert!(fallback.validate_speeds().is_ok());
+        assert!(matches!(fallback.pll_src, PllSrc::Hsi(HsiDiv::Div1)));
+        assert_eq!(fallback.sysclk(), 400_000_000);
+
+        // 64Mhz / 4 * 50 = 800Mhz.
+        assert!(fallback.pll2.enabled);
+        assert_eq!(fallback.pll2.divm, 4);
+        assert_eq!(fallback.pll2.divn, 50);
+        assert_eq!(fallback.pll2.fracn, None);
+        assert_eq!(fallback.pll2.vco_freq(64_000_000), 800_000_000);
+
+        let vco = clocks.pll3.vco_freq(25_000_000);
+        assert!(fallback.pll3.enabled);
+        assert!(fallback.pll3.vco_freq(64_000_000).abs_diff(vco) < 10_000);
+
+        assert!(fallback.per_ck_src == PerCkSrc::HsiKer);
+        assert!(fallback.spi45_src == Spi456Src::HsiKer);
+        assert!(fallback.spi6_src == Spi456Src::HsiKer);
+        // The default FDCAN source is the HSE. Its kernel clock stays at or under 25Mhz.
+        assert!(fallback.fdcan_src == FdcanSrc::Pll1Q);
+        assert!(fallback.pll1.pllq_en);
+        assert!(fallback.pll1.vco_freq(64_000_000) / fallback.pll1.divq as u32 <= 25_000_000);
+
+        // USB from PLL1Q stays there if the HSI can produce 48Mhz.
+        let clocks = Clocks::with_sysclk_usb(400_000_000, PllSrc::Hse(25_000_000)).unwrap();
+        let fallback = clocks.hse_fallback();
+        assert!(fallback.validate_speeds().is_ok());
+        assert!(fallback.usb_src == UsbSrc::Pll1Q);
+        assert_eq!(fallback.usb(), 48_000_000);
+        assert_eq!(fallback.sysclk(), clocks.sysclk());
+    }
+
+    #[test]
+    fn full_speed() {
+        let clocks = Clocks::full_speed();
+        assert!(clocks.validate_speeds().is_ok());
+
+        #[cfg(feature = "h735")]
+        {
+            // 550Mhz needs CPUFREQ_BOOST, so it's not the default.
+            assert_eq!(clocks.sysclk(), 520_000_000);
+
+            let clocks = Clocks::full_speed_boost();
+            assert_eq!(clocks.sysclk(), 550_000_000);
+            assert!(clocks.validate_speeds().is_ok());
+        }
+        #[cfg(not(feature = "h735"))]
+        assert_eq!(clocks.sysclk(), 480_000_000);
+    }
 }
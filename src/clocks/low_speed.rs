@@ -0,0 +1,196 @@
+//! Low speed oscillator (LSE and LSI) setup. These clock the RTC and IWDG, and can be selected
+//! as kernel clocks for LPUART, LPTIM and others, eg to keep them running in Stop mode. These
+//! functions don't wait indefinitely for the oscillator to become ready: They return a
+//! `SpeedError` if it doesn't, eg due to a missing or faulty crystal.
+
+use crate::{
+    clocks::SpeedError,
+    pac::{PWR, RCC},
+};
+
+use cfg_if::cfg_if;
+
+/// The number of times to poll the LSI ready flag before giving up. The LSI starts in well
+/// under a millisecond.
+const LSI_READY_POLLS: u32 = 1_000_000;
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LSE oscillator drive capability. Sets RCC_BDCR register, LSEDRV field. A higher drive helps
+/// crystals with a high load capacitance or ESR start reliably, at the cost of higher power use.
+/// Not used in bypass mode.
+pub enum LseDrive {
+    Low = 0b00,
+    #[cfg(feature = "f3")]
+    MediumLow = 0b10,
+    #[cfg(not(feature = "f3"))]
+    MediumLow = 0b01,
+    #[cfg(feature = "f3")]
+    MediumHigh = 0b01,
+    #[cfg(not(feature = "f3"))]
+    MediumHigh = 0b10,
+    High = 0b11,
+}
+
+/// LSE configuration. Apply with `setup_lse`.
+#[derive(Clone)]
+pub struct LseConfig {
+    /// Bypass the oscillator, for use with an external clock signal on OSC32_IN instead of a
+    /// crystal. Sets RCC_BDCR register, LSEBYP field. Defaults to `false`.
+    pub bypass: bool,
+    #[cfg(not(feature = "f4"))]
+    /// Oscillator drive capability. Defaults to `LseDrive::Low`.
+    pub drive: LseDrive,
+    /// The number of times to poll the LSE ready flag before giving up. Crystals can take up to
+    /// a few seconds to start, and each poll takes a few core clock cycles, so set this
+    /// according to your SYSCLK speed. Defaults to 100 million.
+    pub ready_polls: u32,
+}
+
+impl Default for LseConfig {
+    fn default() -> Self {
+        Self {
+            bypass: false,
+            #[cfg(not(feature = "f4"))]
+            drive: LseDrive::Low,
+            ready_polls: 100_000_000,
+        }
+    }
+}
+
+/// Enable write access to the backup domain, which includes the LSE configuration and RTC.
+/// Sets the PWR_CR1 register, DBP field.
+pub(crate) fn unlock_backup_domain() {
+    let pwr = unsafe { &(*PWR::ptr()) };
+
+    cfg_if! {
+        if #[cfg(any(feature = "f3", feature = "f4"))] {
+            let rcc = unsafe { &(*RCC::ptr()) };
+            rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+            pwr.cr.read(); // read to allow the pwr clock to enable
+            pwr.cr.modify(|_, w| w.dbp().set_bit());
+            while pwr.cr.read().dbp().bit_is_clear() {}
+        } else {
+            // The PWR clock is always enabled on WB, WL and H7.
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g0", feature = "g4"))]
+            let rcc = unsafe { &(*RCC::ptr()) };
+            #[cfg(feature = "g0")]
+            rcc.apbenr1.modify(|_, w| w.pwren().set_bit());
+            #[cfg(any(feature = "l4", feature = "l5", feature = "g4"))]
+            rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+            pwr.cr1.read(); // Read to allow the pwr clock to enable
+            pwr.cr1.modify(|_, w| w.dbp().set_bit());
+            while pwr.cr1.read().dbp().bit_is_clear() {}
+        }
+    }
+}
+
+/// Poll `ready` up to `polls` times, returning an error if it doesn't return true. Also used by
+/// `Clocks::setup` to wait for the HSE.
+pub(crate) fn wait_ready(
+    ready: impl Fn() -> bool,
+    polls: u32,
+    error_msg: &'static str,
+) -> Result<(), SpeedError> {
+    for _ in 0..polls {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(SpeedError::new(error_msg))
+}
+
+/// Returns true if the LSE is enabled, and stable.
+pub fn lse_ready() -> bool {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.bdcr.read().lserdy().bit_is_set()
+}
+
+/// Enable the LSE with the given configuration, and wait for it to become ready. If it's already
+/// running with this configuration, eg from a previous call, or RTC setup, this returns without
+/// changing anything. Otherwise, it's stopped first, since the bypass and drive settings can
+/// only be changed while it's off; this resets the RTC's time base if it uses the LSE.
+pub fn setup_lse(cfg: &LseConfig) -> Result<(), SpeedError> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    let bdcr = rcc.bdcr.read();
+    #[cfg(not(feature = "f4"))]
+    let drive_matches = bdcr.lsedrv().bits() == cfg.drive as u8;
+    #[cfg(feature = "f4")]
+    let drive_matches = true;
+
+    if bdcr.lserdy().bit_is_set() && bdcr.lsebyp().bit() == cfg.bypass && drive_matches {
+        return Ok(());
+    }
+
+    unlock_backup_domain();
+
+    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+    while rcc.bdcr.read().lserdy().bit_is_set() {}
+
+    rcc.bdcr.modify(|_, w| unsafe {
+        #[cfg(not(feature = "f4"))]
+        w.lsedrv().bits(cfg.drive as u8);
+        w.lsebyp().bit(cfg.bypass)
+    });
+
+    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+
+    wait_ready(lse_ready, cfg.ready_polls, "LSE failed to start")
+}
+
+/// Disable the LSE. Note that this stops the RTC, and any peripherals using it as a kernel clock.
+pub fn disable_lse() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    unlock_backup_domain();
+    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+}
+
+/// Enable the LSI, and wait for it to become ready. On WB, this is LSI1.
+pub fn setup_lsi() -> Result<(), SpeedError> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    cfg_if! {
+        if #[cfg(feature = "wb")] {
+            rcc.csr.modify(|_, w| w.lsi1on().set_bit());
+            wait_ready(|| rcc.csr.read().lsi1rdy().bit_is_set(), LSI_READY_POLLS, "LSI failed to start")
+        } else {
+            rcc.csr.modify(|_, w| w.lsion().set_bit());
+            wait_ready(|| rcc.csr.read().lsirdy().bit_is_set(), LSI_READY_POLLS, "LSI failed to start")
+        }
+    }
+}
+
+/// Disable the LSI. On WB, this is LSI1. Note that the LSI can't be disabled while the IWDG is
+/// running.
+pub fn disable_lsi() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    #[cfg(feature = "wb")]
+    rcc.csr.modify(|_, w| w.lsi1on().clear_bit());
+    #[cfg(not(feature = "wb"))]
+    rcc.csr.modify(|_, w| w.lsion().clear_bit());
+}
+
+#[cfg(feature = "wb")]
+/// Enable LSI2, and wait for it to become ready. LSI2 is a lower-power, more accurate alternative
+/// to LSI1, with a longer startup time. It can be output with MCO.
+pub fn setup_lsi2() -> Result<(), SpeedError> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+
+    rcc.csr.modify(|_, w| w.lsi2on().set_bit());
+    wait_ready(
+        || rcc.csr.read().lsi2rdy().bit_is_set(),
+        LSI_READY_POLLS,
+        "LSI2 failed to start",
+    )
+}
+
+#[cfg(feature = "wb")]
+/// Disable LSI2.
+pub fn disable_lsi2() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.csr.modify(|_, w| w.lsi2on().clear_bit());
+}
@@ -28,10 +28,38 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(not(feature = "u5"))]
+mod css;
+#[cfg(not(feature = "u5"))]
+pub use css::*;
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "h7"
+))]
+mod crs;
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "h7"
+))]
+pub use crs::*;
+
+#[cfg(not(feature = "u5"))]
+mod low_speed;
+#[cfg(not(feature = "u5"))]
+pub use low_speed::*;
+
 // todo: Consider merging the modules into a single file: There's more similar than different.
 // todo: You have a good deal of DRY atm between modules.
 
-// Dat structures and functions that are shared between clock modules go here.
+// Dat structures and functions that are shared between clock modules go here. Drivers should
+// query bus and kernel clock speeds through the `ClockTree` trait, which each module implements.
 
 // todo: Continue working through DRY between the clock modules.
 
@@ -47,6 +75,72 @@ impl SpeedError {
     }
 }
 
+/// The number of times `Clocks::setup` polls the HSE ready flag before returning an error. HSE
+/// crystals usually start within a few milliseconds; this allows around a second on the HSI.
+pub(crate) const HSE_READY_POLLS: u32 = 10_000_000;
+
+/// Peripherals whose kernel clock can be queried with `ClockTree::kernel_clock`. Not all of these
+/// are available on all MCUs. `Qspi` also refers to OctoSPI.
+#[derive(Clone, Copy, PartialEq)]
+pub enum KernelClockPeriph {
+    Usart1,
+    Usart2,
+    Usart3,
+    Uart4,
+    Uart5,
+    Usart6,
+    Uart7,
+    Uart8,
+    Uart9,
+    Usart10,
+    Lpuart1,
+    I2c1,
+    I2c2,
+    I2c3,
+    I2c4,
+    I2c5,
+    Spi1,
+    Spi2,
+    Spi3,
+    Spi4,
+    Spi5,
+    Spi6,
+    Adc,
+    Fdcan,
+    Qspi,
+    Lptim1,
+    Lptim2,
+}
+
+/// A common interface to the clock tree, across MCU families. Implemented by each family's
+/// `Clocks` struct.
+pub trait ClockTree {
+    /// Get the sysclock frequency, in Hz.
+    fn sysclk(&self) -> u32;
+    /// Get the HCLK frequency, in Hz.
+    fn hclk(&self) -> u32;
+    /// Get the APB1 frequency, in Hz.
+    fn apb1(&self) -> u32;
+    /// Get the APB2 frequency, in Hz.
+    fn apb2(&self) -> u32;
+    /// Get the frequency used by APB1 timers, in Hz.
+    fn apb1_timer(&self) -> u32;
+    /// Get the frequency used by APB2 timers, in Hz.
+    fn apb2_timer(&self) -> u32;
+    /// Get the frequency of a peripheral's kernel clock, in Hz. This is the clock its baud rate,
+    /// bit timing, or sampling rate is derived from. Where the kernel clock is selectable, this
+    /// reads the current selection from the RCC registers. Returns 0 if the clock is disabled.
+    fn kernel_clock(&self, periph: KernelClockPeriph) -> u32;
+}
+
+/// Implemented by peripheral drivers that derive settings, like baud rate or timer prescalers,
+/// from a clock speed. After changing clock speeds with `Clocks::change_speed`, run `reclock`
+/// on each of these to re-derive their settings from the new speeds.
+pub trait Reclock {
+    /// Re-derive clock-dependent settings from `clocks`.
+    fn reclock(&mut self, clocks: &Clocks);
+}
+
 // #[derive(Clone, Copy)]
 // #[repr(u8)]
 // pub enum ClocksValid {
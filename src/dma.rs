@@ -252,6 +252,7 @@ impl DmaInput {
             // Self::Dac1Ch2 => 7,
             // Self::Tim6Up => 8,
             // Self::Tim7Up => 9,
+            Self::Tim2Up => DmaChannel::C2,
             Self::Spi1Rx => DmaChannel::C2,
             Self::Spi1Tx => DmaChannel::C3,
             Self::Spi2Rx => DmaChannel::C4,
@@ -303,6 +304,7 @@ impl DmaInput {
             // Self::Dac1Ch2 => 7,
             // Self::Tim6Up => 8,
             // Self::Tim7Up => 9,
+            Self::Tim2Up => 0b100,
             Self::Spi1Rx => 0b001,
             Self::Spi1Tx => 0b001,
             Self::Spi2Rx => 0b001,
@@ -1277,6 +1279,94 @@ where
         }
     }
 
+    /// Read the number of data items remaining to be transferred on a channel. (CNDTR register)
+    /// This counts down from the `num_data` value passed to `cfg_channel`, and is reloaded
+    /// at the end of each pass in circular mode. It's useful for determining how much data was
+    /// received when a transfer is ended early; eg by a USART idle line.
+    #[cfg(not(feature = "h7"))]
+    pub fn num_data_remaining(&self, channel: DmaChannel) -> u16 {
+        // We read the whole register, since the `ndt` field is missing, or has inconsistent
+        // widths, on some PACs. Bits above 15 are reserved.
+        match channel {
+            DmaChannel::C1 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "f3", feature = "g0"))] {
+                        self.regs.ch1.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr1.read().bits() as u16
+                    }
+                }
+            }
+            DmaChannel::C2 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "f3", feature = "g0"))] {
+                        self.regs.ch2.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr2.read().bits() as u16
+                    }
+                }
+            }
+            DmaChannel::C3 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "f3", feature = "g0"))] {
+                        self.regs.ch3.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr3.read().bits() as u16
+                    }
+                }
+            }
+            DmaChannel::C4 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "f3", feature = "g0"))] {
+                        self.regs.ch4.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr4.read().bits() as u16
+                    }
+                }
+            }
+            DmaChannel::C5 => {
+                cfg_if! {
+                    if #[cfg(any(feature = "f3", feature = "g0"))] {
+                        self.regs.ch5.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr5.read().bits() as u16
+                    }
+                }
+            }
+            #[cfg(not(feature = "g0"))]
+            DmaChannel::C6 => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        self.regs.ch6.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr6.read().bits() as u16
+                    }
+                }
+            }
+            #[cfg(not(feature = "g0"))]
+            DmaChannel::C7 => {
+                cfg_if! {
+                    if #[cfg(feature = "f3")] {
+                        self.regs.ch7.ndtr.read().bits() as u16
+                    } else {
+                        self.regs.cndtr7.read().bits() as u16
+                    }
+                }
+            }
+            #[cfg(any(feature = "l5", feature = "g4"))]
+            DmaChannel::C8 => self.regs.cndtr8.read().bits() as u16,
+        }
+    }
+
+    /// Read the number of data items remaining to be transferred on a channel. (NDTR register)
+    /// This counts down from the `num_data` value passed to `cfg_channel`, and is reloaded
+    /// at the end of each pass in circular mode. It's useful for determining how much data was
+    /// received when a transfer is ended early; eg by a USART idle line.
+    #[cfg(feature = "h7")]
+    pub fn num_data_remaining(&self, channel: DmaChannel) -> u32 {
+        self.regs.st[channel as usize].ndtr.read().ndt().bits() as u32
+    }
+
     #[cfg(feature = "l4")] // Only required on L4
     /// Select which peripheral on a given channel we're using.
     /// See L44 RM, Table 41.
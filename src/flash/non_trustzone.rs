@@ -12,8 +12,20 @@ use cfg_if::cfg_if;
 
 const FLASH_KEY1: u32 = 0x4567_0123;
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
-// const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
-// const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+#[cfg(feature = "h7")]
+const FLASH_OPT_KEY1: u32 = 0x0819_2A3B;
+#[cfg(feature = "h7")]
+const FLASH_OPT_KEY2: u32 = 0x4C5D_6E7F;
+
+// FLASH_OPTSR2_CUR and FLASH_OPTSR2_PRG register offsets. These registers are missing from the PAC.
+#[cfg(feature = "h735")]
+const OPTSR2_CUR_OFFSET: usize = 0x70;
+#[cfg(feature = "h735")]
+const OPTSR2_PRG_OFFSET: usize = 0x74;
+// RM0468, FLASH_OPTSR2_PRG register: "CPUFREQ_BOOST: CPU frequency boost. This bit is used to
+// boost the CPU frequency by allowing the PLL to run at 550 MHz."
+#[cfg(feature = "h735")]
+const CPUFREQ_BOOST_BIT: u32 = 1 << 2;
 
 #[derive(Clone, Copy, PartialEq)]
 /// Set dual bank mode (DBANK option bit). Eg G4
@@ -206,26 +218,80 @@ impl Flash {
         }
     }
 
-    // /// Unlock the FLASH_OPTCR register, for writing option bits.
-    // pub fn unlock_options(&mut self) -> Result<(), Error> {
-    //     #[cfg(not(feature = "h7"))]
-    //     let regs = &self.regs;
-    //     #[cfg(feature = "h7")]
-    //     let regs = self.regs.bank1();
-    //
-    //     if regs.cr.read().lock().bit_is_clear() {
-    //         return Ok(());
-    //     }
-    //
-    //     regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY1) });
-    //     regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPT_KEY2) });
-    //
-    //     if regs.cr.read().lock().bit_is_clear() {
-    //         Ok(())
-    //     } else {
-    //         Err(Error::Failure)
-    //     }
-    // }
+    #[cfg(feature = "h7")]
+    /// Unlock the FLASH_OPTCR register, allowing option bytes to be changed. See H743 RM, section
+    /// 4.4.3: Option byte modification.
+    pub fn unlock_options(&mut self) -> Result<(), Error> {
+        let regs = &self.regs;
+
+        if regs.optcr().read().optlock().bit_is_clear() {
+            return Ok(());
+        }
+
+        // "1. Write OPTKEY1 = 0x0819 2A3B in the Flash option key register (FLASH_OPTKEYR).
+        // 2. Write OPTKEY2 = 0x4C5D 6E7F in the FLASH_OPTKEYR register."
+        regs.optkeyr().write(|w| unsafe { w.bits(FLASH_OPT_KEY1) });
+        regs.optkeyr().write(|w| unsafe { w.bits(FLASH_OPT_KEY2) });
+
+        if regs.optcr().read().optlock().bit_is_clear() {
+            Ok(())
+        } else {
+            Err(Error::Failure)
+        }
+    }
+
+    #[cfg(feature = "h7")]
+    /// Lock the FLASH_OPTCR register, preventing option bytes from being changed.
+    pub fn lock_options(&mut self) {
+        self.regs.optcr().modify(|_, w| w.optlock().set_bit());
+    }
+
+    #[cfg(feature = "h7")]
+    /// Apply option byte values written to the FLASH_XXX_PRG registers, and wait for the change
+    /// to complete. Options must be unlocked with `unlock_options` first.
+    pub fn commit_options(&mut self) -> Result<(), Error> {
+        let regs = &self.regs;
+
+        regs.optccr().write(|w| w.clr_optchangeerr().set_bit());
+
+        // "3. Set the option byte start change OPTSTART bit to 1 in the FLASH_OPTCR register.
+        // 4. Wait until OPT_BUSY bit is cleared."
+        regs.optcr().modify(|_, w| w.optstart().set_bit());
+        while regs.optsr_cur().read().opt_busy().bit_is_set() {}
+
+        if regs.optsr_cur().read().optchangeerr().bit_is_set() {
+            regs.optccr().write(|w| w.clr_optchangeerr().set_bit());
+            return Err(Error::Failure);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "h735")]
+    /// Set or clear the CPUFREQ_BOOST option byte, which allows SYSCLK to run at 550Mhz with
+    /// VOS0. Option bytes are stored in flash, so this only needs to be done once per device.
+    /// Returns without programming if the option byte is already set as requested.
+    pub fn set_cpu_freq_boost(&mut self, enabled: bool) -> Result<(), Error> {
+        if cpu_freq_boost_enabled() == enabled {
+            return Ok(());
+        }
+
+        self.unlock_options()?;
+
+        let prg = (FLASH::ptr() as usize + OPTSR2_PRG_OFFSET) as *mut u32;
+        unsafe {
+            let val = core::ptr::read_volatile(prg);
+            if enabled {
+                core::ptr::write_volatile(prg, val | CPUFREQ_BOOST_BIT);
+            } else {
+                core::ptr::write_volatile(prg, val & !CPUFREQ_BOOST_BIT);
+            }
+        }
+
+        let result = self.commit_options();
+        self.lock_options();
+        result
+    }
 
     pub fn lock(&mut self) {
         // The FLASH_CR register cannot be written when the BSY bit in the Flash status register
@@ -688,3 +754,10 @@ fn sector_to_address(bank: Bank, sector: usize) -> usize {
 
     starting_pt + sector * super::SECTOR_SIZE
 }
+
+#[cfg(feature = "h735")]
+/// Returns true if the CPUFREQ_BOOST option byte is set. Reads the FLASH_OPTSR2_CUR register.
+pub fn cpu_freq_boost_enabled() -> bool {
+    let cur = (FLASH::ptr() as usize + OPTSR2_CUR_OFFSET) as *const u32;
+    unsafe { core::ptr::read_volatile(cur) & CPUFREQ_BOOST_BIT != 0 }
+}
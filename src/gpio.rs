@@ -1,15 +1,16 @@
 //! This module provides functionality for General Purpose Input and Output (GPIO) pins,
-//! including all GPIOx register functions. It also configures GPIO interrupts using SYSCFG and EXTI
-//! registers as appropriate.
+//! including all GPIOx register functions. It also configures GPIO interrupts and events using
+//! SYSCFG and EXTI registers as appropriate.
 
 // todo: WL is missing port C here due to some pins being missing, and this being tough
 // todo to change with our current model. Note sure if PAC, or MCU limitation
-// todo: WL is also missing interrupt support.
 
 #[cfg(feature = "embedded-hal")]
 use core::convert::Infallible;
 
-use cortex_m::interrupt::free;
+use core::{cell::Cell, ops::RangeInclusive};
+
+use cortex_m::interrupt::{free, Mutex};
 
 use crate::pac::{self, EXTI, RCC};
 
@@ -19,13 +20,7 @@ use crate::util::rcc_en_reset;
 #[cfg(feature = "embedded-hal")]
 use embedded_hal::digital::v2::{InputPin, OutputPin, ToggleableOutputPin};
 
-#[cfg(not(any(
-    // feature = "g0",
-    feature = "f4",
-    // feature = "l5",
-    feature = "f3",
-    feature = "l4"
-)))]
+#[cfg(not(any(feature = "f4", feature = "l552")))]
 use core::ops::Deref;
 
 cfg_if! {
@@ -42,6 +37,8 @@ use crate::dma::{self, ChannelCfg, Dma, DmaChannel};
 use cfg_if::cfg_if;
 use paste::paste;
 
+pub mod typed;
+
 #[derive(Copy, Clone)]
 #[repr(u8)]
 /// Values for `GPIOx_MODER`. Sets pin to input, output, and other functionality.
@@ -326,7 +323,8 @@ macro_rules! set_state {
 
 // todo: Consolidate these exti macros
 
-// Reduce DRY for setting up interrupts.
+// Reduce DRY for setting up interrupts and events. Sets the edge triggers, and selects the port
+// in EXTICR. The interrupt and event masks are set separately, with `set_exti_bit!`.
 macro_rules! set_exti {
     ($pin:expr, $rising:expr, $falling:expr, $val:expr, [$(($num:expr, $crnum:expr)),+]) => {
         let exti = unsafe { &(*pac::EXTI::ptr()) };
@@ -336,19 +334,6 @@ macro_rules! set_exti {
             match $pin {
                 $(
                     $num => {
-                    // todo: Core 2 interrupts for wb. (?)
-                        cfg_if! {
-                            if #[cfg(all(feature = "h7", not(any(feature = "h747cm4", feature = "h747cm7"))))] {
-                                exti.cpuimr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            } else if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
-                                exti.c1imr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            } else if #[cfg(any(feature = "g4", feature = "wb", feature = "wl"))] {
-                                exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());
-                            } else {
-                                exti.imr1.modify(|_, w| w.[<mr $num>]().set_bit());
-                            }
-                        }
-
                         cfg_if! {
                             if #[cfg(any(feature = "g4", feature = "wb", feature = "wl"))] {
                                 exti.rtsr1.modify(|_, w| w.[<rt $num>]().bit($rising));
@@ -376,7 +361,7 @@ macro_rules! set_exti {
     }
 }
 
-#[cfg(feature = "f4")]
+#[cfg(any(feature = "f373", feature = "f4"))]
 // Similar to `set_exti`, but with reg names sans `1`.
 macro_rules! set_exti_f4 {
     ($pin:expr, $rising:expr, $falling:expr, $val:expr, [$(($num:expr, $crnum:expr)),+]) => {
@@ -387,7 +372,6 @@ macro_rules! set_exti_f4 {
             match $pin {
                 $(
                     $num => {
-                        exti.imr.modify(|_, w| w.[<mr $num>]().unmasked());
                         exti.rtsr.modify(|_, w| w.[<tr $num>]().bit($rising));
                         exti.ftsr.modify(|_, w| w.[<tr $num>]().bit($falling));
                         syscfg
@@ -411,7 +395,6 @@ macro_rules! set_exti_l5 {
             match $pin {
                 $(
                     $num => {
-                        exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());  // unmask
                         exti.rtsr1.modify(|_, w| w.[<rt $num>]().bit($rising));  // Rising trigger
                         exti.ftsr1.modify(|_, w| w.[<ft $num>]().bit($falling));   // Falling trigger
                         exti
@@ -435,7 +418,6 @@ macro_rules! set_exti_g0 {
             match $pin {
                 $(
                     $num => {
-                        exti.imr1.modify(|_, w| w.[<im $num>]().set_bit());  // unmask
                         exti.rtsr1.modify(|_, w| w.[<tr $num>]().bit($rising));  // Rising trigger
                         // This field name is probably a PAC error.
                         exti.ftsr1.modify(|_, w| w.[<tr $num>]().bit($falling));   // Falling trigger
@@ -450,6 +432,84 @@ macro_rules! set_exti_g0 {
     }
 }
 
+// EXTI mask, trigger and pending register names vary by family and core. We access them using raw
+// bits, since field names vary as well. This also lets us use lines above 15.
+cfg_if! {
+    if #[cfg(any(feature = "f373", feature = "f4"))] {
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).imr };
+            (emr) => { (*EXTI::ptr()).emr };
+            (rtsr) => { (*EXTI::ptr()).rtsr };
+            (ftsr) => { (*EXTI::ptr()).ftsr };
+            (pr) => { (*EXTI::ptr()).pr };
+        }
+    } else if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).c1imr1 };
+            (emr) => { (*EXTI::ptr()).c1emr1 };
+            (rtsr) => { (*EXTI::ptr()).rtsr1 };
+            (ftsr) => { (*EXTI::ptr()).ftsr1 };
+            (pr) => { (*EXTI::ptr()).c1pr1 };
+        }
+    } else if #[cfg(feature = "h7")] {
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).cpuimr1 };
+            (emr) => { (*EXTI::ptr()).cpuemr1 };
+            (rtsr) => { (*EXTI::ptr()).rtsr1 };
+            (ftsr) => { (*EXTI::ptr()).ftsr1 };
+            (pr) => { (*EXTI::ptr()).cpupr1 };
+        }
+    } else if #[cfg(feature = "wl")] {
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).c1imr1 };
+            (emr) => { (*EXTI::ptr()).emr1 };
+            (rtsr) => { (*EXTI::ptr()).rtsr1 };
+            (ftsr) => { (*EXTI::ptr()).ftsr1 };
+            (pr) => { (*EXTI::ptr()).pr1 };
+        }
+    } else if #[cfg(any(feature = "l5", feature = "g0"))] {
+        // These have separate rising and falling edge pending registers.
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).imr1 };
+            (emr) => { (*EXTI::ptr()).emr1 };
+            (rtsr) => { (*EXTI::ptr()).rtsr1 };
+            (ftsr) => { (*EXTI::ptr()).ftsr1 };
+            (rpr) => { (*EXTI::ptr()).rpr1 };
+            (fpr) => { (*EXTI::ptr()).fpr1 };
+        }
+    } else {
+        macro_rules! exti_reg {
+            (imr) => { (*EXTI::ptr()).imr1 };
+            (emr) => { (*EXTI::ptr()).emr1 };
+            (rtsr) => { (*EXTI::ptr()).rtsr1 };
+            (ftsr) => { (*EXTI::ptr()).ftsr1 };
+            (pr) => { (*EXTI::ptr()).pr1 };
+        }
+    }
+}
+
+// Set or clear a line's bit in an EXTI register, leaving other lines unchanged.
+macro_rules! set_exti_bit {
+    ($reg:ident, $line:expr, $val:expr) => {
+        unsafe {
+            exti_reg!($reg).modify(|r, w| {
+                if $val {
+                    w.bits(r.bits() | (1 << $line))
+                } else {
+                    w.bits(r.bits() & !(1 << $line))
+                }
+            })
+        }
+    };
+}
+
+// Check if a line's bit is set in an EXTI register.
+macro_rules! get_exti_bit {
+    ($reg:ident, $line:expr) => {
+        unsafe { exti_reg!($reg).read().bits() & (1 << $line) != 0 }
+    };
+}
+
 /// Represents a single GPIO pin. Allows configuration, and reading/setting state.
 pub struct Pin {
     /// The GPIO Port letter. Eg A, B, C.
@@ -896,9 +956,64 @@ impl Pin {
         }
     }
 
-    #[cfg(not(any(feature = "f373", feature = "wl")))]
-    /// Configure this pin as an interrupt source. Set the edge as Rising or Falling.
+    /// Configure this pin as an interrupt source. Set the edge as Rising or Falling. Sets the
+    /// EXTI edge trigger registers, selects this pin's port for its EXTI line, and unmasks the
+    /// line's interrupt.
     pub fn enable_interrupt(&mut self, edge: Edge) {
+        self.set_exti_triggers(edge);
+        set_exti_bit!(imr, self.pin, true);
+    }
+
+    /// Stop this pin from generating interrupts, by masking its EXTI line. If the line isn't
+    /// configured as an event source, its edge triggers are disabled as well.
+    pub fn disable_interrupt(&mut self) {
+        set_exti_bit!(imr, self.pin, false);
+
+        if !get_exti_bit!(emr, self.pin) {
+            self.clear_exti_triggers();
+        }
+    }
+
+    /// Configure this pin as an event source, eg to wake the CPU from `WFE`. Set the edge as
+    /// Rising or Falling. Unlike an interrupt, an event doesn't run an interrupt handler. Note
+    /// that on most families (eg F3, F4 and L4), the edge trigger still sets the line's pending
+    /// flag when its interrupt is masked; `dispatch_exti` skips masked lines. Sets the EXTI edge
+    /// trigger registers, selects this pin's port for its EXTI line, and unmasks the line's event.
+    pub fn enable_event(&mut self, edge: Edge) {
+        self.set_exti_triggers(edge);
+        set_exti_bit!(emr, self.pin, true);
+    }
+
+    /// Stop this pin from generating events, by masking its EXTI line. If the line isn't
+    /// configured as an interrupt source, its edge triggers are disabled as well.
+    pub fn disable_event(&mut self) {
+        set_exti_bit!(emr, self.pin, false);
+
+        if !get_exti_bit!(imr, self.pin) {
+            self.clear_exti_triggers();
+        }
+    }
+
+    /// Check if this pin's EXTI line has a pending interrupt. Note that EXTI lines are shared
+    /// between ports, eg PA3 and PB3 both use line 3.
+    pub fn is_pending(&self) -> bool {
+        exti_is_pending(self.pin)
+    }
+
+    /// Clear this pin's pending interrupt flag. Do this in its interrupt handler, to prevent
+    /// the interrupt from re-firing.
+    pub fn clear_interrupt(&mut self) {
+        clear_exti_interrupt(self.pin);
+    }
+
+    fn clear_exti_triggers(&mut self) {
+        set_exti_bit!(rtsr, self.pin, false);
+        set_exti_bit!(ftsr, self.pin, false);
+    }
+
+    /// Set the EXTI edge triggers, and select this pin's port for its line. Shared by interrupts
+    /// and events.
+    fn set_exti_triggers(&mut self, edge: Edge) {
         let rising = match edge {
             Edge::Falling => false,
             _ => true, // rising or either.
@@ -922,7 +1037,7 @@ impl Pin {
                     (9, 3, 8_15), (10, 3, 8_15), (11, 3, 8_15), (12, 4, 8_15),
                     (13, 4, 8_15), (14, 4, 8_15), (15, 4, 8_15)]
                 );
-            } else if #[cfg(feature = "f4")] {
+            } else if #[cfg(any(feature = "f373", feature = "f4"))] {
                 set_exti_f4!(self.pin, rising, falling, self.port.cr_val(), [(0, 1), (1, 1), (2, 1),
                         (3, 1), (4, 2), (5, 2), (6, 2), (7, 2), (8, 3), (9, 3), (10, 3), (11, 3), (12, 4),
                         (13, 4), (14, 4), (15, 4)]
@@ -1067,147 +1182,197 @@ pub fn set_state(port: Port, pin: u8, value: PinState) {
     );
 }
 
-/// Clear an EXTI interrupt, lines 0 - 15. Note that this function currently doesn't support
-/// higher extis, but will work for all GPIO interrupts.
-pub fn clear_exti_interrupt(line: u8) {
-    // todo: Macro to avoid DRY?
-    unsafe {
-        cfg_if! {
-            if #[cfg(any(feature = "h747cm4", feature = "h747cm7"))] {
-                (*EXTI::ptr()).c1pr1.modify(|_, w| {
-                    match line {
-                        0 => w.pr0().set_bit(),
-                        1 => w.pr1().set_bit(),
-                        2 => w.pr2().set_bit(),
-                        3 => w.pr3().set_bit(),
-                        4 => w.pr4().set_bit(),
-                        5 => w.pr5().set_bit(),
-                        6 => w.pr6().set_bit(),
-                        7 => w.pr7().set_bit(),
-                        8 => w.pr8().set_bit(),
-                        9 => w.pr9().set_bit(),
-                        10 => w.pr10().set_bit(),
-                        11 => w.pr11().set_bit(),
-                        12 => w.pr12().set_bit(),
-                        13 => w.pr13().set_bit(),
-                        14 => w.pr14().set_bit(),
-                        15 => w.pr15().set_bit(),
-                        _ => panic!(),
-                    }
-                });
-            } else if #[cfg(feature = "h7")] {
-                (*EXTI::ptr()).d3pmr1.modify(|_, w| {
-                    match line {
-                        0 => w.mr0().set_bit(),
-                        1 => w.mr1().set_bit(),
-                        2 => w.mr2().set_bit(),
-                        3 => w.mr3().set_bit(),
-                        4 => w.mr4().set_bit(),
-                        5 => w.mr5().set_bit(),
-                        6 => w.mr6().set_bit(),
-                        7 => w.mr7().set_bit(),
-                        8 => w.mr8().set_bit(),
-                        9 => w.mr9().set_bit(),
-                        10 => w.mr10().set_bit(),
-                        11 => w.mr11().set_bit(),
-                        12 => w.mr12().set_bit(),
-                        13 => w.mr13().set_bit(),
-                        14 => w.mr14().set_bit(),
-                        15 => w.mr15().set_bit(),
-                        _ => panic!(),
-                    }
-                });
-            } else if #[cfg(any(feature = "l5", feature = "g0"))] {
-                (*EXTI::ptr()).rpr1.modify(|_, w| {
-                    match line {
-                        0 => w.rpif0().set_bit(),
-                        1 => w.rpif1().set_bit(),
-                        2 => w.rpif2().set_bit(),
-                        3 => w.rpif3().set_bit(),
-                        4 => w.rpif4().set_bit(),
-                        5 => w.rpif5().set_bit(),
-                        6 => w.rpif6().set_bit(),
-                        7 => w.rpif7().set_bit(),
-                        8 => w.rpif8().set_bit(),
-                        9 => w.rpif9().set_bit(),
-                        10 => w.rpif10().set_bit(),
-                        11 => w.rpif11().set_bit(),
-                        12 => w.rpif12().set_bit(),
-                        13 => w.rpif13().set_bit(),
-                        14 => w.rpif14().set_bit(),
-                        15 => w.rpif15().set_bit(),
-                        _ => panic!(),
-                    }
-                });
-            } else if #[cfg(any(feature = "f373", feature = "f4"))] {
-                (*EXTI::ptr()).pr.modify(|_, w| {
-                    match line {
-                        0 => w.pr0().set_bit(),
-                        1 => w.pr1().set_bit(),
-                        2 => w.pr2().set_bit(),
-                        3 => w.pr3().set_bit(),
-                        4 => w.pr4().set_bit(),
-                        5 => w.pr5().set_bit(),
-                        6 => w.pr6().set_bit(),
-                        7 => w.pr7().set_bit(),
-                        8 => w.pr8().set_bit(),
-                        9 => w.pr9().set_bit(),
-                        10 => w.pr10().set_bit(),
-                        11 => w.pr11().set_bit(),
-                        12 => w.pr12().set_bit(),
-                        13 => w.pr13().set_bit(),
-                        14 => w.pr14().set_bit(),
-                        15 => w.pr15().set_bit(),
-                        _ => panic!(),
-                    }
-                });
-            } else if #[cfg(any(feature = "f3", feature = "l4"))] {
-                (*EXTI::ptr()).pr1.modify(|_, w| {
-                    match line {
-                        0 => w.pr0().set_bit(),
-                        1 => w.pr1().set_bit(),
-                        2 => w.pr2().set_bit(),
-                        3 => w.pr3().set_bit(),
-                        4 => w.pr4().set_bit(),
-                        5 => w.pr5().set_bit(),
-                        6 => w.pr6().set_bit(),
-                        7 => w.pr7().set_bit(),
-                        8 => w.pr8().set_bit(),
-                        9 => w.pr9().set_bit(),
-                        10 => w.pr10().set_bit(),
-                        11 => w.pr11().set_bit(),
-                        12 => w.pr12().set_bit(),
-                        13 => w.pr13().set_bit(),
-                        14 => w.pr14().set_bit(),
-                        15 => w.pr15().set_bit(),
-                        _ => panic!(),
-                    }
-                });
-            } else { // eg G4
-                (*EXTI::ptr()).pr1.modify(|_, w| {
-                    match line {
-                        0 => w.pif0().set_bit(),
-                        1 => w.pif1().set_bit(),
-                        2 => w.pif2().set_bit(),
-                        3 => w.pif3().set_bit(),
-                        4 => w.pif4().set_bit(),
-                        5 => w.pif5().set_bit(),
-                        6 => w.pif6().set_bit(),
-                        7 => w.pif7().set_bit(),
-                        8 => w.pif8().set_bit(),
-                        9 => w.pif9().set_bit(),
-                        10 => w.pif10().set_bit(),
-                        11 => w.pif11().set_bit(),
-                        12 => w.pif12().set_bit(),
-                        13 => w.pif13().set_bit(),
-                        14 => w.pif14().set_bit(),
-                        15 => w.pif15().set_bit(),
-                        _ => panic!(),
-                    }
+#[derive(Clone, Copy)]
+/// A group of pins on a single port, selected by a bit mask, eg for a parallel bus. Values read
+/// and written use the port's bit positions: Bit 0 is pin 0, bit 15 is pin 15. Pins outside the
+/// mask are never modified.
+pub struct PortGroup {
+    pub port: Port,
+    pub mask: u16,
+}
+
+impl PortGroup {
+    /// Create a new pin group, configuring each pin in the mask with the same mode. Enables
+    /// the RCC peripheral clock to the port, if not already enabled.
+    pub fn new(port: Port, mask: u16, mode: PinMode) -> Self {
+        for pin in 0..16 {
+            if mask & (1 << pin) != 0 {
+                Pin::new(port, pin, mode);
+            }
+        }
+
+        Self { port, mask }
+    }
+
+    /// Set the output type of each pin in the group. Sets the `OTYPER` register.
+    pub fn output_type(&mut self, value: OutputType) {
+        self.for_each_pin(|pin| pin.output_type(value));
+    }
+
+    /// Set the output speed of each pin in the group. Sets the `OSPEEDR` register.
+    pub fn output_speed(&mut self, value: OutputSpeed) {
+        self.for_each_pin(|pin| pin.output_speed(value));
+    }
+
+    /// Set the internal pull-up or pull-down resistor of each pin in the group. Sets the
+    /// `PUPDR` register.
+    pub fn pull(&mut self, value: Pull) {
+        self.for_each_pin(|pin| pin.pull(value));
+    }
+
+    fn for_each_pin(&self, mut f: impl FnMut(&mut Pin)) {
+        for pin in 0..16 {
+            if self.mask & (1 << pin) != 0 {
+                f(&mut Pin {
+                    port: self.port,
+                    pin,
                 });
             }
         }
     }
+
+    /// Compute the `BSRR` word that sets the group's pins to `value`: Pins in the mask whose
+    /// bit is set are driven high, and the others in the mask are driven low. Use this to build
+    /// buffers for `write_dma`.
+    pub const fn bsrr_val(&self, value: u16) -> u32 {
+        let set = value & self.mask;
+        let reset = !value & self.mask;
+        ((reset as u32) << 16) | set as u32
+    }
+
+    /// Set the output voltage of all pins in the group at once. Sets the `BSRR` register. Atomic.
+    pub fn write(&mut self, value: u16) {
+        let bsrr_val = self.bsrr_val(value);
+        unsafe { (*regs(self.port)).bsrr.write(|w| w.bits(bsrr_val)) }
+    }
+
+    /// Read the input voltage of all pins in the group. Pins outside the mask read as 0. Reads
+    /// from the `IDR` register.
+    pub fn read(&self) -> u16 {
+        unsafe { (*regs(self.port)).idr.read().bits() as u16 & self.mask }
+    }
+
+    /// Set the pins in `mask` high, leaving the rest of the group unchanged. Pins outside the
+    /// group's mask are ignored. Sets the `BSRR` register. Atomic.
+    pub fn set_mask(&mut self, mask: u16) {
+        let bits = (mask & self.mask) as u32;
+        unsafe { (*regs(self.port)).bsrr.write(|w| w.bits(bits)) }
+    }
+
+    /// Set the pins in `mask` low, leaving the rest of the group unchanged. Pins outside the
+    /// group's mask are ignored. Sets the `BSRR` register. Atomic.
+    pub fn clear_mask(&mut self, mask: u16) {
+        let bits = ((mask & self.mask) as u32) << 16;
+        unsafe { (*regs(self.port)).bsrr.write(|w| w.bits(bits)) }
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    /// Output a waveform on the group's pins using DMA, writing one word of `buf` to the `BSRR`
+    /// register per DMA request. Build each word with `bsrr_val`. To pace the output with a
+    /// timer, set the timer's frequency to the sample rate, enable its update DMA request with
+    /// `enable_interrupt(TimerInterrupt::UpdateDma)`, and route its update request to
+    /// `dma_channel` (eg `dma::mux(DmaPeriph::Dma1, dma_channel, DmaInput::Tim2Up)`) before
+    /// enabling it. Set `channel_cfg.circular` to repeat the waveform.
+    ///
+    /// F3 and L4 don't have a DMAMUX; each request is wired to a fixed channel. Pass
+    /// `DmaInput::Tim2Up.dma1_channel()` (DMA1 channel 2) as `dma_channel` to pace with TIM2, and
+    /// on L4, select the request with `dma.channel_select(DmaInput::Tim2Up)`. Not available on
+    /// F4 and L552, since this HAL doesn't support DMA on them.
+    pub unsafe fn write_dma<D>(
+        &mut self,
+        buf: &[u32],
+        dma_channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        write_dma(buf, self.port, dma_channel, channel_cfg, dma);
+    }
+}
+
+/// Clear an EXTI interrupt. Works for GPIO lines (0 - 15), and other lines in the first EXTI
+/// register set, eg line 20 for the RTC wakeup timer on some families. Only clears `line`'s
+/// pending flag.
+pub fn clear_exti_interrupt(line: u8) {
+    cfg_if! {
+        if #[cfg(any(feature = "l5", feature = "g0"))] {
+            unsafe {
+                exti_reg!(rpr).write(|w| w.bits(1 << line));
+                exti_reg!(fpr).write(|w| w.bits(1 << line));
+            }
+        } else {
+            unsafe { exti_reg!(pr).write(|w| w.bits(1 << line)) }
+        }
+    }
+}
+
+/// Check if an EXTI line has a pending interrupt. See `clear_exti_interrupt` for the lines
+/// supported.
+pub fn exti_is_pending(line: u8) -> bool {
+    cfg_if! {
+        if #[cfg(any(feature = "l5", feature = "g0"))] {
+            get_exti_bit!(rpr, line) || get_exti_bit!(fpr, line)
+        } else {
+            get_exti_bit!(pr, line)
+        }
+    }
+}
+
+static EXTI_HANDLERS: Mutex<Cell<[Option<fn()>; 16]>> = Mutex::new(Cell::new([None; 16]));
+
+/// Set a function to run from `dispatch_exti` when a GPIO EXTI line (0 - 15) has a pending
+/// interrupt. Replaces any handler previously set for the line.
+pub fn set_exti_handler(line: u8, handler: fn()) {
+    assert!(line <= 15, "EXTI handler lines must be 0 - 15.");
+
+    free(|cs| {
+        let handlers = EXTI_HANDLERS.borrow(cs);
+        let mut h = handlers.get();
+        h[line as usize] = Some(handler);
+        handlers.set(h);
+    });
+}
+
+/// Remove the function set with `set_exti_handler` for a GPIO EXTI line.
+pub fn remove_exti_handler(line: u8) {
+    assert!(line <= 15, "EXTI handler lines must be 0 - 15.");
+
+    free(|cs| {
+        let handlers = EXTI_HANDLERS.borrow(cs);
+        let mut h = handlers.get();
+        h[line as usize] = None;
+        handlers.set(h);
+    });
+}
+
+/// Handle an EXTI interrupt vector shared by several lines, eg `EXTI9_5`, or `EXTI4_15` on G0.
+/// For each line in `lines` with its interrupt unmasked (eg with `Pin::enable_interrupt`), and a
+/// pending interrupt, clears its flag, then runs the function set for it with `set_exti_handler`,
+/// if any. Masked lines are skipped, since their pending flags can be set by event-only
+/// triggers. `lines` must be within the GPIO EXTI lines, 0 - 15.
+///
+/// Example:
+/// ```rust
+/// #[interrupt]
+/// fn EXTI15_10() {
+///     gpio::dispatch_exti(10..=15);
+/// }
+/// ```
+pub fn dispatch_exti(lines: RangeInclusive<u8>) {
+    assert!(*lines.end() <= 15, "EXTI handler lines must be 0 - 15.");
+
+    let handlers = free(|cs| EXTI_HANDLERS.borrow(cs).get());
+
+    for line in lines {
+        if get_exti_bit!(imr, line) && exti_is_pending(line) {
+            clear_exti_interrupt(line);
+
+            if let Some(handler) = handlers[line as usize] {
+                handler();
+            }
+        }
+    }
 }
 
 const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
@@ -1270,7 +1435,7 @@ const fn regs(port: Port) -> *const pac::gpioa::RegisterBlock {
     }
 }
 
-#[cfg(not(any(feature = "f4", feature = "l5", feature = "f3", feature = "l4")))]
+#[cfg(not(any(feature = "f4", feature = "l552")))]
 /// Write a series of words to the BSRR (atomic output) register. Note that these are direct writes
 /// to the full, 2-sided register - not a series of low/high values.
 pub unsafe fn write_dma<D>(
@@ -1304,3 +1469,29 @@ pub unsafe fn write_dma<D>(
         channel_cfg,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bsrr_val() {
+        let group = PortGroup {
+            port: Port::A,
+            mask: 0x0F00,
+        };
+        // Pins set in the value go to the lower (set) half, and the group's other pins to the
+        // upper (reset) half.
+        assert_eq!(group.bsrr_val(0x0500), 0x0A00_0500);
+        assert_eq!(group.bsrr_val(0), 0x0F00_0000);
+        // Pins outside the mask are left alone.
+        assert_eq!(group.bsrr_val(0xFFFF), 0x0000_0F00);
+        assert_eq!(group.bsrr_val(0xF0FF), 0x0F00_0000);
+
+        let port = PortGroup {
+            port: Port::B,
+            mask: 0xFFFF,
+        };
+        assert_eq!(port.bsrr_val(0x1234), 0xEDCB_1234);
+    }
+}
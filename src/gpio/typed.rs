@@ -0,0 +1,284 @@
+//! An optional, type-level layer over `Pin`. Typed pins encode their port, number and mode in
+//! their type, eg `PA9<Alternate<7>>`. Peripheral constructors like `Usart::new_with_pins` accept
+//! these, using the traits in this module (eg `TxPin<USART1>`) to reject pins, or alternate
+//! functions, that don't belong to the peripheral at compile time. The dynamic `Pin` API is
+//! unaffected; use `into_dynamic` to convert a typed pin.
+//!
+//! Example:
+//! ```
+//! let tx = PA9::new().into_alternate::<7>();
+//! let rx = PA10::new().into_alternate::<7>();
+//! let uart = Usart::new_with_pins(dp.USART1, tx, rx, 115_200, Default::default(), &clock_cfg);
+//! ```
+//!
+//! The pin and AF tables at the bottom of this module are hand-written from the datasheets'
+//! alternate function tables. They're not exhaustive: They only cover USART1, USART2, SPI1 and
+//! I2C1, on the common pins shared by most parts of each family. Check your part's datasheet;
+//! not every package bonds out every pin listed. The traits aren't sealed, so you can implement
+//! them for combinations missing from these tables.
+//!
+//! `tools/af_tables.py` generates these tables from ST's open pin data (STM32_open_pin_data), for
+//! any set of peripherals; the tables here haven't been regenerated with it yet.
+
+use core::marker::PhantomData;
+
+use crate::{
+    gpio::{OutputType, Pin, PinMode, Port},
+    pac,
+};
+
+use cfg_if::cfg_if;
+use paste::paste;
+
+/// Type state for a pin configured as an input.
+pub struct Input;
+
+/// Type state for a pin configured as an output.
+pub struct Output;
+
+/// Type state for a pin configured in analog mode.
+pub struct Analog;
+
+/// Type state for a pin configured with alternate function `A`.
+pub struct Alternate<const A: u8>;
+
+/// A GPIO pin with its port letter, pin number and mode encoded in its type. Usually referred to
+/// by aliases like `PA9`.
+pub struct TypedPin<const P: char, const N: u8, MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+/// Convert a port letter to a `Port`. Fails to compile if this MCU doesn't have the port.
+const fn port(letter: char) -> Port {
+    match letter {
+        'A' => Port::A,
+        'B' => Port::B,
+        #[cfg(not(feature = "wl"))]
+        'C' => Port::C,
+        #[cfg(not(any(feature = "f410", feature = "wl")))]
+        'D' => Port::D,
+        #[cfg(not(any(
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f410",
+            feature = "g0",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'E' => Port::E,
+        #[cfg(not(any(
+            feature = "f401",
+            feature = "f410",
+            feature = "f411",
+            feature = "l4x1",
+            feature = "l4x2",
+            feature = "l412",
+            feature = "l4x3",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'F' => Port::F,
+        #[cfg(not(any(
+            feature = "f373",
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f401",
+            feature = "f410",
+            feature = "f411",
+            feature = "l4",
+            feature = "g0",
+            feature = "g4",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'G' => Port::G,
+        #[cfg(not(any(
+            feature = "f373",
+            feature = "f301",
+            feature = "f3x4",
+            feature = "f410",
+            feature = "l4",
+            feature = "g0",
+            feature = "g4",
+            feature = "wb",
+            feature = "wl"
+        )))]
+        'H' => Port::H,
+        _ => panic!("This MCU doesn't have this GPIO port."),
+    }
+}
+
+impl<const P: char, const N: u8, MODE> TypedPin<P, N, MODE> {
+    /// The pin's port. Evaluated at compile time.
+    pub const PORT: Port = port(P);
+
+    const fn state<NEW>() -> TypedPin<P, N, NEW> {
+        TypedPin { _mode: PhantomData }
+    }
+
+    /// A dynamic `Pin` referring to the same pin, used to access its registers.
+    fn dynamic(&self) -> Pin {
+        Pin {
+            port: Self::PORT,
+            pin: N,
+        }
+    }
+
+    /// Configure the pin as an input. Sets the `MODER` register.
+    pub fn into_input(self) -> TypedPin<P, N, Input> {
+        self.dynamic().mode(PinMode::Input);
+        Self::state()
+    }
+
+    /// Configure the pin as an output. Sets the `MODER` register.
+    pub fn into_output(self) -> TypedPin<P, N, Output> {
+        self.dynamic().mode(PinMode::Output);
+        Self::state()
+    }
+
+    /// Configure the pin in analog mode, eg for use with the ADC or DAC. Sets the `MODER` register.
+    pub fn into_analog(self) -> TypedPin<P, N, Analog> {
+        self.dynamic().mode(PinMode::Analog);
+        Self::state()
+    }
+
+    /// Configure the pin with alternate function `A`, using a push-pull output. Sets the `MODER`,
+    /// `AFRL` and `AFRH` registers.
+    pub fn into_alternate<const A: u8>(self) -> TypedPin<P, N, Alternate<A>> {
+        let mut pin = self.dynamic();
+        pin.mode(PinMode::Alt(A));
+        pin.output_type(OutputType::PushPull);
+        Self::state()
+    }
+
+    /// Configure the pin with alternate function `A`, using an open-drain output, eg for I2C.
+    /// Sets the `MODER`, `OTYPER`, `AFRL` and `AFRH` registers.
+    pub fn into_alternate_open_drain<const A: u8>(self) -> TypedPin<P, N, Alternate<A>> {
+        let mut pin = self.dynamic();
+        pin.mode(PinMode::Alt(A));
+        pin.output_type(OutputType::OpenDrain);
+        Self::state()
+    }
+
+    /// Convert to a dynamic `Pin`, eg to store pins of different types together. The pin's mode
+    /// is unchanged.
+    pub fn into_dynamic(self) -> Pin {
+        self.dynamic()
+    }
+
+    /// Check if the pin's input voltage is high. Reads from the `IDR` register.
+    pub fn is_high(&self) -> bool {
+        self.dynamic().is_high()
+    }
+
+    /// Check if the pin's input voltage is low. Reads from the `IDR` register.
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl<const P: char, const N: u8> TypedPin<P, N, Input> {
+    /// Take the pin, and configure it as an input. Enables the RCC peripheral clock to the
+    /// port, if not already enabled. Similar to `Pin::new`, this doesn't check if the pin has
+    /// already been taken.
+    pub fn new() -> Self {
+        Pin::new(Self::PORT, N, PinMode::Input);
+        Self::state()
+    }
+}
+
+impl<const P: char, const N: u8> TypedPin<P, N, Output> {
+    /// Set the pin's output voltage to high. Sets the `BSRR` register. Atomic.
+    pub fn set_high(&mut self) {
+        self.dynamic().set_high();
+    }
+
+    /// Set the pin's output voltage to low. Sets the `BSRR` register. Atomic.
+    pub fn set_low(&mut self) {
+        self.dynamic().set_low();
+    }
+}
+
+// Create aliases like `PA9<MODE = Input>` for each port and pin number.
+macro_rules! pin_aliases {
+    ($($port:literal: $P:ident),+) => {
+        $(
+            pin_aliases!(@port $port, $P, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        )+
+    };
+    (@port $port:literal, $P:ident, [$($N:literal),+]) => {
+        paste! {
+            $(
+                #[doc = concat!("Typed pin P", stringify!($P), stringify!($N), ".")]
+                pub type [<P $P $N>]<MODE = Input> = TypedPin<$port, $N, MODE>;
+            )+
+        }
+    };
+}
+
+pin_aliases!('A': A, 'B': B, 'C': C, 'D': D, 'E': E, 'F': F, 'G': G, 'H': H);
+
+/// A pin that can be used as a U[S]ART TX output.
+pub trait TxPin<U> {}
+/// A pin that can be used as a U[S]ART RX input.
+pub trait RxPin<U> {}
+/// A pin that can be used as an SPI SCK (clock) signal.
+pub trait SckPin<S> {}
+/// A pin that can be used as an SPI MISO signal.
+pub trait MisoPin<S> {}
+/// A pin that can be used as an SPI MOSI signal.
+pub trait MosiPin<S> {}
+/// A pin that can be used as an I2C SCL (clock) signal.
+pub trait SclPin<I> {}
+/// A pin that can be used as an I2C SDA (data) signal.
+pub trait SdaPin<I> {}
+
+// Implement a pin trait for a list of (port letter, pin number, alternate function) entries.
+macro_rules! af_table {
+    ($($trait:ident<$periph:ident>: [$(($port:literal, $N:literal, $af:literal)),+ $(,)?];)+) => {
+        $(
+            $(
+                impl $trait<pac::$periph> for TypedPin<$port, $N, Alternate<$af>> {}
+            )+
+        )+
+    };
+}
+
+cfg_if! {
+    if #[cfg(feature = "g0")] {
+        af_table! {
+            TxPin<USART1>: [('A', 9, 1), ('B', 6, 0)];
+            RxPin<USART1>: [('A', 10, 1), ('B', 7, 0)];
+            TxPin<USART2>: [('A', 2, 1)];
+            RxPin<USART2>: [('A', 3, 1)];
+            SckPin<SPI1>: [('A', 5, 0), ('B', 3, 0)];
+            MisoPin<SPI1>: [('A', 6, 0), ('B', 4, 0)];
+            MosiPin<SPI1>: [('A', 7, 0), ('B', 5, 0)];
+            SclPin<I2C1>: [('B', 6, 6), ('B', 8, 6)];
+            SdaPin<I2C1>: [('B', 7, 6), ('B', 9, 6)];
+        }
+    } else {
+        // F3, F4, L4, L5, G4, H7, WB and WL share these assignments.
+        af_table! {
+            TxPin<USART1>: [('A', 9, 7), ('B', 6, 7)];
+            RxPin<USART1>: [('A', 10, 7), ('B', 7, 7)];
+            SckPin<SPI1>: [('A', 5, 5), ('B', 3, 5)];
+            MisoPin<SPI1>: [('A', 6, 5), ('B', 4, 5)];
+            MosiPin<SPI1>: [('A', 7, 5), ('B', 5, 5)];
+            SclPin<I2C1>: [('B', 6, 4)];
+            SdaPin<I2C1>: [('B', 7, 4)];
+        }
+
+        #[cfg(not(feature = "wb"))]
+        af_table! {
+            TxPin<USART2>: [('A', 2, 7)];
+            RxPin<USART2>: [('A', 3, 7)];
+        }
+
+        #[cfg(not(feature = "wl"))]
+        af_table! {
+            SclPin<I2C1>: [('B', 8, 4)];
+            SdaPin<I2C1>: [('B', 9, 4)];
+        }
+    }
+}
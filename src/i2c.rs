@@ -11,9 +11,10 @@ use cortex_m::interrupt::free;
 use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, Reclock},
+    gpio::typed::{SclPin, SdaPin},
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{BaudPeriph, RccPeriph},
 };
 
 use cfg_if::cfg_if;
@@ -172,7 +173,7 @@ pub struct I2c<R> {
 
 impl<R> I2c<R>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + BaudPeriph,
 {
     /// Initialize a I2C peripheral, including configuration register writes, and enabling and resetting
     /// its RCC peripheral clock. `freq` is in Hz.
@@ -188,6 +189,63 @@ where
         // todo: Slave currently nonfunctional!
         // todo: Check out the RM recipes for slave transmitter and receiver.
 
+        // Before enabling the I2C peripheral by setting the PE bit in I2C_CR1 register, the user must
+        // configure the noise filters, if needed. By default, an analog noise filter is present on the SDA
+        // and SCL inputs. This analog filter is compliant with the I2C specification which requires the
+        // suppression of spikes with a pulse width up to 50 ns in Fast-mode and Fast-mode Plus. The
+        // user can disable this analog filter by setting the ANFOFF bit, and/or select a digital filter by
+        // configuring the DNF[3:0] bit in the I2C_CR1 register.
+        // When the digital filter is enabled, the level of the SCL or the SDA line is internally changed
+        // only if it remains stable for more than DNF x I2CCLK periods. This allows spikes with a
+        // programmable length of 1 to 15 I2CCLK periods to be suppressed.
+        let (anf_bit, dnf_bits) = match cfg.noise_filter {
+            NoiseFilter::Analog => (false, 0),
+            NoiseFilter::Digital(filtering_len) => {
+                assert!(filtering_len <= 0b1111);
+                (true, filtering_len)
+            }
+            NoiseFilter::Disabled => (true, 0),
+        };
+
+        regs.cr1.modify(|_, w| unsafe {
+            w.anfoff().bit(anf_bit);
+            w.dnf().bits(dnf_bits)
+        });
+
+        if let I2cMode::Slave = cfg.mode {
+            regs.cr1.modify(|_, w| w.nostretch().bit(cfg.nostretch));
+        }
+
+        let mut result = Self { regs, cfg };
+
+        result.set_timing(clocks);
+
+        if result.cfg.smbus {
+            result.enable_smbus();
+        }
+
+        // Enable the peripheral
+        result.regs.cr1.write(|w| w.pe().set_bit());
+
+        result
+    }
+
+    /// Similar to `new`, but also takes the SCL and SDA pins, configured with their alternate
+    /// function; usually open-drain. Pins or alternate functions that don't belong to this
+    /// peripheral fail to compile. See the `gpio::typed` module.
+    pub fn new_with_pins<SCL: SclPin<R>, SDA: SdaPin<R>>(
+        regs: R,
+        _scl: SCL,
+        _sda: SDA,
+        cfg: I2cConfig,
+        clocks: &Clocks,
+    ) -> Self {
+        Self::new(regs, cfg, clocks)
+    }
+
+    /// Set the I2C timings, for the configured speed and the I2C clock speed. The peripheral
+    /// must be disabled when running this.
+    fn set_timing(&mut self, clocks: &Clocks) {
         // RM: I2C timings:
         // The timings must be configured in order to guarantee a correct data hold and setup time,
         // used in master and slave modes. This is done by programming the PRESC[3:0],
@@ -196,7 +254,7 @@ where
         // programming the PRESC[3:0], SCLH[7:0] and SCLL[7:0] bits in the I2C_TIMINGR register
 
         // For these speed and frequency variables, we use the RM's conventions.
-        let t_i2cclk = clocks.apb1();
+        let t_i2cclk = R::baud(clocks);
 
         // assert!(t_i2cclk < (t_low - f_f) / 4);
         // assert!(t_i2cclk < t_high);
@@ -208,7 +266,7 @@ where
         // values.
 
         // We use this constant in several calculations.
-        let presc_const = match cfg.speed {
+        let presc_const = match self.cfg.speed {
             I2cSpeed::Standard10K => 4_000_000,
             I2cSpeed::Standard100K => 4_000_000,
             I2cSpeed::Fast400K => 8_000_000,
@@ -230,7 +288,7 @@ where
         // Hit the target freq by setting up t_scll (Period of SCL low)
         // to be half the whole period. These constants
         // are from the tables.
-        let freq = match cfg.speed {
+        let freq = match self.cfg.speed {
             I2cSpeed::Standard10K => 10_000,
             I2cSpeed::Standard100K => 100_000,
             I2cSpeed::Fast400K => 400_000,
@@ -253,7 +311,7 @@ where
         // 10K. THis may be due to delays
         // involved. The ratio is different for Fast-mode and Fast-mode+.
         // todo: Come back to this. How should we set this?
-        let sclh_val = match cfg.speed {
+        let sclh_val = match self.cfg.speed {
             I2cSpeed::Standard10K => scll_val - 4,
             I2cSpeed::Standard100K => scll_val - 4,
             I2cSpeed::Fast400K => scll_val * 4 / 10,
@@ -289,7 +347,7 @@ where
         // tSDADEL= SDADEL x tPRESC
         // Note: SDADEL is used to generate tHD:DAT timing
         // Sets TIMINGR reg, SDADEL field.
-        let sdadel = match cfg.speed {
+        let sdadel = match self.cfg.speed {
             I2cSpeed::Standard10K => 0x2,
             I2cSpeed::Standard100K => 0x2,
             I2cSpeed::Fast400K => 0x3,
@@ -303,7 +361,7 @@ where
         // tSCLDEL = (SCLDEL+1) x tPRESC
         // Note: tSCLDEL is used to generate tSU:DAT timing
         // Sets TIMINGR reg, SCLDEL field.
-        let scldel = match cfg.speed {
+        let scldel = match self.cfg.speed {
             I2cSpeed::Standard10K => 0x4,
             I2cSpeed::Standard100K => 0x4,
             I2cSpeed::Fast400K => 0x3,
@@ -319,51 +377,13 @@ where
         assert!(scll <= 255);
         assert!(sclh <= 255);
 
-        regs.timingr.write(|w| unsafe {
+        self.regs.timingr.write(|w| unsafe {
             w.presc().bits(presc as u8);
             w.scldel().bits(scldel as u8);
             w.sdadel().bits(sdadel as u8);
             w.sclh().bits(sclh as u8);
             w.scll().bits(scll as u8)
         });
-
-        // Before enabling the I2C peripheral by setting the PE bit in I2C_CR1 register, the user must
-        // configure the noise filters, if needed. By default, an analog noise filter is present on the SDA
-        // and SCL inputs. This analog filter is compliant with the I2C specification which requires the
-        // suppression of spikes with a pulse width up to 50 ns in Fast-mode and Fast-mode Plus. The
-        // user can disable this analog filter by setting the ANFOFF bit, and/or select a digital filter by
-        // configuring the DNF[3:0] bit in the I2C_CR1 register.
-        // When the digital filter is enabled, the level of the SCL or the SDA line is internally changed
-        // only if it remains stable for more than DNF x I2CCLK periods. This allows spikes with a
-        // programmable length of 1 to 15 I2CCLK periods to be suppressed.
-        let (anf_bit, dnf_bits) = match cfg.noise_filter {
-            NoiseFilter::Analog => (false, 0),
-            NoiseFilter::Digital(filtering_len) => {
-                assert!(filtering_len <= 0b1111);
-                (true, filtering_len)
-            }
-            NoiseFilter::Disabled => (true, 0),
-        };
-
-        regs.cr1.modify(|_, w| unsafe {
-            w.anfoff().bit(anf_bit);
-            w.dnf().bits(dnf_bits)
-        });
-
-        if let I2cMode::Slave = cfg.mode {
-            regs.cr1.modify(|_, w| w.nostretch().bit(cfg.nostretch));
-        }
-
-        let mut result = Self { regs, cfg };
-
-        if result.cfg.smbus {
-            result.enable_smbus();
-        }
-
-        // Enable the peripheral
-        result.regs.cr1.write(|w| w.pe().set_bit());
-
-        result
     }
 
     /// Enable SMBus support. See L44 RM, section 37.4.11: SMBus initialization
@@ -748,11 +768,31 @@ where
     // }
 }
 
+impl<R> Reclock for I2c<R>
+where
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Re-set the I2C timings, using the new clock speeds.
+    fn reclock(&mut self, clocks: &Clocks) {
+        let originally_enabled = self.regs.cr1.read().pe().bit_is_set();
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().clear_bit());
+            while self.regs.cr1.read().pe().bit_is_set() {}
+        }
+
+        self.set_timing(clocks);
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.pe().set_bit());
+        }
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> Write for I2c<R>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + BaudPeriph,
 {
     type Error = Error;
 
@@ -765,7 +805,7 @@ where
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> Read for I2c<R>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + BaudPeriph,
 {
     type Error = Error;
 
@@ -778,7 +818,7 @@ where
 // #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> WriteRead for I2c<R>
 where
-    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph,
+    R: Deref<Target = pac::i2c1::RegisterBlock> + RccPeriph + BaudPeriph,
 {
     type Error = Error;
 
@@ -13,7 +13,7 @@ use cortex_m::interrupt::free;
 use embedded_hal::blocking::i2c::{Read, Write, WriteRead};
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, Reclock},
     pac::{self, i2c1, RCC},
     util::rcc_en_reset,
 };
@@ -43,6 +43,8 @@ pub enum Error {
 /// Represents an Inter-Integrated Circuit (I2C) peripheral.
 pub struct I2c<R> {
     pub regs: R,
+    /// Bus speed, in Hz.
+    speed: u32,
 }
 
 impl<R> I2c<R>
@@ -67,7 +69,7 @@ where
             }
         });
 
-        let result = Self { regs };
+        let result = Self { regs, speed };
         result.i2c_init(speed, clocks.apb1());
         result
     }
@@ -252,6 +254,16 @@ where
     }
 }
 
+impl<R> Reclock for I2c<R>
+where
+    R: Deref<Target = i2c1::RegisterBlock>,
+{
+    /// Re-set the bus frequency, rise time, and clock control, using the new clock speeds.
+    fn reclock(&mut self, clocks: &Clocks) {
+        self.i2c_init(self.speed, clocks.apb1());
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
 #[cfg_attr(docsrs, doc(cfg(feature = "embedded-hal")))]
 impl<R> WriteRead for I2c<R>
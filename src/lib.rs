@@ -371,6 +371,8 @@ pub use stm32wl::stm32wle5 as pac;
 #[cfg(not(any(feature = "f301", feature = "f302")))]
 pub mod adc;
 
+pub mod bus;
+
 // bxCAN families: F3, F4, L4,
 // fdCAN families: L5, U5, G4, H7
 // H7 suppords fd and can_ccu. (What's that?)
@@ -451,8 +453,8 @@ pub mod low_power;
 #[cfg(any(feature = "h747cm4", feature = "h747cm7"))]
 pub mod power;
 
-// F3, F4, G0, and WL don't have Quad SPI. L5 and newer H variants (eg H735) use OctoSPI,
-// also supported by this module.
+// F3, F4, G0, and WL don't have Quad SPI. Newer H variants (eg H735) use OctoSPI, also supported
+// by this module. L5 also uses OctoSPI, but isn't supported yet; see the todo below.
 #[cfg(not(any(
 feature = "f3",
 feature = "f4",
@@ -464,7 +466,7 @@ feature = "g471",
 feature = "g491",
 feature = "g4a1",
 feature = "wl",
-feature = "l5", // todo: PAC errors on some regs.
+feature = "l5", // todo: The L5 PAC's OCTOSPI1 field definitions are shifted for every register after DCR3 (eg `sr` has `fcr`'s fields), so this module would need raw register access throughout.
 )))]
 pub mod qspi;
 
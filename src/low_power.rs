@@ -27,8 +27,8 @@ pub enum StopMode {
 
 /// L4 RM, table 24
 /// This assumes you're using MSI as the clock source, and changes speed by lowering the MSI speed.
-/// You must select an MSI speed of 2Mhz or lower. Note that you may need to adjust peripheral
-/// implementations that rely on system clock or APB speed.
+/// You must select an MSI speed of 2Mhz or lower. Run `Reclock::reclock` on peripherals that
+/// rely on system clock or APB speed after running this.
 #[cfg(any(feature = "l4", feature = "l5"))]
 pub fn low_power_run(clocks: &mut Clocks, speed: MsiRange) {
     let rcc = unsafe { &(*pac::RCC::ptr()) };
@@ -151,6 +151,7 @@ cfg_if! {
         /// Enter Stop 0, Stop 1, or Stop 2 modes. L4 Reference manual, section 5.3.6. Tables 27, 28, and 29.
         /// G0 RMs, tables 30, 31, 32.
         /// G4 Table 45, 47, 47.
+        /// To wake on serial reception, eg on LPUART in Stop 2, see `Usart::enable_stop_wakeup`.
         /// Run `Clocks::reselect_input()` after to re-enable PLL etc after exiting this mode.
         pub fn stop(mode: StopMode) {
             let mut scb = unsafe { Peripherals::steal().SCB };
@@ -267,6 +268,22 @@ cfg_if! {
             // – The RTC flag corresponding to the chosen wakeup source (RTC
             // Alarm A, RTC Alarm B, RTC wakeup, tamper or timestamp flags) is
             // cleared
+            wfi();
+        }
+    } else if #[cfg(any(feature = "wb", feature = "wl"))] {
+        /// Enter Stop 0, Stop 1, or Stop 2 modes. WB RM, section 6.4.7. WL RM, section 6.4.7.
+        /// On WB, the system only enters Stop mode once CPU2 is also in Stop mode.
+        /// To wake on serial reception, eg on LPUART in Stop 2, see `Usart::enable_stop_wakeup`.
+        /// Run `Clocks::reselect_input()` after to re-enable PLL etc after exiting this mode.
+        pub fn stop(mode: StopMode) {
+            let mut scb = unsafe { Peripherals::steal().SCB };
+            let pwr = unsafe { &(*PWR::ptr()) };
+
+            // – SLEEPDEEP bit is set in Cortex®-M4 System Control register
+            scb.set_sleepdeep();
+            // – LPMS = (according to mode) in PWR_CR1
+            pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(mode as u8) });
+
             wfi();
         }
     } else { // H7
@@ -1,13 +1,26 @@
 //! Quad Serial Peripheral Interface (SPI) bus: A specialized interface used for
 //! high-speed communications with external flash memory. Also supports OctoSPI
-//! on variants that support it.
+//! on variants that support it, including octal and DTR modes, HyperBus memories, and the
+//! OctoSPI I/O manager.
 
-use crate::{clocks::Clocks, pac::RCC};
+use crate::{
+    clocks::{ClockTree, Clocks, KernelClockPeriph},
+    pac::RCC,
+};
+
+#[cfg(not(feature = "h7"))]
+use core::ops::Deref;
+
+#[cfg(not(feature = "h7"))]
+use crate::{
+    dma::{self, ChannelCfg, Dma, DmaChannel},
+    pac::dma1 as dma_p,
+};
 
 use cfg_if::cfg_if;
 
 cfg_if! {
-    if #[cfg(any(feature = "l5", feature = "h735", feature = "h7b3"))] {
+    if #[cfg(any(feature = "h735", feature = "h7b3"))] {
         use crate::pac::OCTOSPI1 as QUADSPI;
     } else {
         use crate::pac::QUADSPI;
@@ -18,14 +31,32 @@ use core::ptr;
 
 use cortex_m::interrupt::free;
 
-// todo: Status-polling mode.
+pub mod flash;
 
 // todo: Is this avail in PAC? Feature-gate if diff on diff platforms?
 const MEM_MAPPED_BASE_ADDR: usize = 0x9000_0000;
 
-#[derive(Copy, Clone)]
+// The FIFO is 16 bytes on QUADSPI, except on H7, where it's 32. OCTOSPI's is 32.
+cfg_if! {
+    if #[cfg(feature = "h7")] {
+        const FIFO_SIZE: u8 = 32;
+    } else {
+        const FIFO_SIZE: u8 = 16;
+    }
+}
+
+/// The FSIZE (or DEVSIZE, on OctoSPI) value for a memory of `mem_size` MB, rounded down to a
+/// power of two. RM: "The FSIZE[4:0] field defines the size of external memory using the
+/// following formula: Number of bytes in Flash memory = 2^[FSIZE+1]". The addressable space in
+/// memory-mapped mode is limited to 256MB.
+fn fsize(mem_size: u32) -> u8 {
+    // `mem_size` is in MB, ie 2^20 bytes.
+    (31 - mem_size.leading_zeros() + 20 - 1) as u8
+}
+
+#[derive(Copy, Clone, PartialEq)]
 #[repr(u8)]
-/// Sets the Qspi mode to single, dual, or quad. Affects the IMODE, ADMODE, ABMODE,
+/// Sets the Qspi mode to single, dual, quad, or octal. Affects the IMODE, ADMODE, ABMODE,
 /// and DMODE fields of the CCR reg. Each of these fields affects a different mode of operation.
 pub enum ProtocolMode {
     /// Only a single IO line (IO0) is used for transmit and a separate line (IO1) is used for receive.
@@ -34,11 +65,16 @@ pub enum ProtocolMode {
     Dual = 0b10,
     /// All four IO lines are used for transmit/receive.
     Quad = 0b11,
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    /// All eight IO lines are used for transmit/receive. OctoSPI only.
+    Octal = 0b100,
 }
 
 #[derive(Copy, Clone)]
 #[repr(u8)]
-/// Sets the Qspi data mode. Affects the DDRM field of the CCR reg.
+/// Sets the Qspi data mode. Affects the DDRM field of the CCR reg. On OctoSPI, affects the DTR
+/// fields of the address, alternate-byte and data phases, and of the instruction phase, if it's
+/// sent in octal mode.
 pub enum DataMode {
     /// In SDR mode, when the QUADSPI is driving the IO0/SO, IO1, IO2, IO3 signals, these
     /// signals transition only with the falling edge of CLK.
@@ -72,6 +108,72 @@ pub enum AddressSize {
     A32 = 0b11,
 }
 
+/// Number of alternate bytes sent after the address phase. Sets CCR register, ABSIZE field.
+#[derive(Copy, Clone, PartialEq)]
+pub enum AltBytesSize {
+    A8 = 0b00,
+    A16 = 0b01,
+    A24 = 0b10,
+    A32 = 0b11,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+/// Instruction size. OctoSPI only; eg octal DTR flash uses 16-bit instructions. Sets CCR
+/// register, ISIZE field.
+#[derive(Copy, Clone, PartialEq)]
+pub enum InstructionSize {
+    I8 = 0b00,
+    I16 = 0b01,
+    I24 = 0b10,
+    I32 = 0b11,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+/// The type of external memory. OctoSPI only. This affects the byte order in octal DTR mode, and
+/// selects HyperBus mode. Sets DCR1 register, MTYP field.
+#[derive(Copy, Clone, PartialEq)]
+pub enum MemoryType {
+    /// D0 is sent before D1 in octal DTR mode. (Micron)
+    Micron = 0b000,
+    /// D1 is sent before D0 in octal DTR mode. (Macronix)
+    Macronix = 0b001,
+    Standard = 0b010,
+    /// Macronix RAM: D1 before D0, with DQS during writes.
+    MacronixRam = 0b011,
+    /// HyperBus memory space, eg for reading and writing HyperRAM.
+    HyperBusMemory = 0b100,
+    /// HyperBus register space, eg for configuring HyperRAM.
+    HyperBusRegister = 0b101,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+/// HyperBus latency settings; used when `memory_type` is one of the HyperBus variants. Sets
+/// the HLCR register. Consult your memory's datasheet for values.
+#[derive(Copy, Clone)]
+pub struct HyperBusConfig {
+    /// Read-write recovery time, in CLK cycles. (TRWR)
+    pub rw_recovery: u8,
+    /// Access time, in CLK cycles, per the memory's initial latency. (TACC)
+    pub access_time: u8,
+    /// Write with no latency; eg required for HyperRAM register writes. (WZL)
+    pub write_zero_latency: bool,
+    /// Always use twice the access time as latency, instead of letting the memory request
+    /// additional latency on RWDS. (LM)
+    pub fixed_latency: bool,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+impl Default for HyperBusConfig {
+    fn default() -> Self {
+        Self {
+            rw_recovery: 4,
+            access_time: 6,
+            write_zero_latency: false,
+            fixed_latency: true,
+        }
+    }
+}
+
 /// Sampling mode for the QSPI interface
 #[derive(Copy, Clone, PartialEq)]
 pub enum SamplingEdge {
@@ -84,6 +186,10 @@ pub enum SamplingEdge {
 pub enum QspiError {
     Busy,
     Underflow,
+    /// The transfer error flag was set; eg an invalid address was accessed in indirect mode.
+    Transfer,
+    /// Status polling didn't match within `StatusPoll::max_polls` checks.
+    Timeout,
 }
 
 // todo: Use bank on suitable MCUs? Which? F7 / H7?
@@ -99,6 +205,15 @@ pub struct QspiConfig {
     pub sampling_edge: SamplingEdge,
     pub fifo_threshold: u8,
     pub mem_size: u32, // Size of the memory, in Megabytes.
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    /// External memory type. Defaults to Micron, the hardware default.
+    pub memory_type: MemoryType,
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    /// Sample data using the memory's DQS (data strobe) signal, eg for octal DTR flash or
+    /// HyperBus. Defaults to false.
+    pub dqs: bool,
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    pub hyperbus: HyperBusConfig,
 }
 
 impl Default for QspiConfig {
@@ -113,6 +228,148 @@ impl Default for QspiConfig {
             sampling_edge: SamplingEdge::Falling,
             fifo_threshold: 1, // todo: What is this?
             mem_size: 64,
+            #[cfg(any(feature = "h735", feature = "h7b3"))]
+            memory_type: MemoryType::Micron,
+            #[cfg(any(feature = "h735", feature = "h7b3"))]
+            dqs: false,
+            #[cfg(any(feature = "h735", feature = "h7b3"))]
+            hyperbus: Default::default(),
+        }
+    }
+}
+
+/// A command sent to the external memory, made of optional instruction, address, alternate
+/// bytes, dummy cycle, and data phases. Phases set to `None` are skipped. Whether a data phase is
+/// present is determined by the method the command is passed to. Example, for a page program:
+/// ```
+/// let cmd = Command::new(0x32)
+///     .with_address(addr, AddressSize::A24)
+///     .with_data_mode(ProtocolMode::Quad);
+/// qspi.write(&cmd, &page);
+/// ```
+#[derive(Copy, Clone)]
+pub struct Command {
+    /// The instruction. Only the low byte is used, except on OctoSPI, where `instruction_size`
+    /// sets how many bytes are sent.
+    pub instruction: Option<u32>,
+    pub instruction_mode: ProtocolMode,
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    pub instruction_size: InstructionSize,
+    pub address: Option<u32>,
+    pub address_mode: ProtocolMode,
+    pub address_size: AddressSize,
+    pub alternate_bytes: Option<u32>,
+    pub alternate_mode: ProtocolMode,
+    pub alternate_size: AltBytesSize,
+    /// Number of dummy cycles between the address (or alternate bytes) and data phases. 0 - 31.
+    pub dummy_cycles: u8,
+    pub data_mode: ProtocolMode,
+}
+
+impl Command {
+    /// Create a command with an instruction sent on a single line, and no other phases. The data
+    /// phase, if used, defaults to a single line.
+    pub fn new(instruction: u8) -> Self {
+        Self {
+            instruction: Some(instruction as u32),
+            instruction_mode: ProtocolMode::Single,
+            #[cfg(any(feature = "h735", feature = "h7b3"))]
+            instruction_size: InstructionSize::I8,
+            address: None,
+            address_mode: ProtocolMode::Single,
+            address_size: AddressSize::A24,
+            alternate_bytes: None,
+            alternate_mode: ProtocolMode::Single,
+            alternate_size: AltBytesSize::A8,
+            dummy_cycles: 0,
+            data_mode: ProtocolMode::Single,
+        }
+    }
+
+    #[cfg(any(feature = "h735", feature = "h7b3"))]
+    /// Set a multi-byte instruction; eg `0xee11` for a 16-bit octal DTR read.
+    pub fn with_instruction(mut self, instruction: u32, size: InstructionSize) -> Self {
+        self.instruction = Some(instruction);
+        self.instruction_size = size;
+        self
+    }
+
+    pub fn with_instruction_mode(mut self, mode: ProtocolMode) -> Self {
+        self.instruction_mode = mode;
+        self
+    }
+
+    pub fn with_address(mut self, address: u32, size: AddressSize) -> Self {
+        self.address = Some(address);
+        self.address_size = size;
+        self
+    }
+
+    pub fn with_address_mode(mut self, mode: ProtocolMode) -> Self {
+        self.address_mode = mode;
+        self
+    }
+
+    pub fn with_alternate_bytes(mut self, bytes: u32, size: AltBytesSize) -> Self {
+        self.alternate_bytes = Some(bytes);
+        self.alternate_size = size;
+        self
+    }
+
+    pub fn with_alternate_mode(mut self, mode: ProtocolMode) -> Self {
+        self.alternate_mode = mode;
+        self
+    }
+
+    pub fn with_dummy_cycles(mut self, cycles: u8) -> Self {
+        assert!(cycles < 32, "Dummy cycles must be between 0 and 31.");
+        self.dummy_cycles = cycles;
+        self
+    }
+
+    pub fn with_data_mode(mut self, mode: ProtocolMode) -> Self {
+        self.data_mode = mode;
+        self
+    }
+}
+
+/// Match mode for status polling. Sets CR register, PMM field.
+#[derive(Copy, Clone, PartialEq)]
+pub enum PollMatch {
+    /// Match when all unmasked bits match.
+    And = 0,
+    /// Match when any unmasked bit matches.
+    Or = 1,
+}
+
+/// Settings for automatic status-polling mode: The peripheral repeatedly sends a command, and
+/// compares the received status bytes against `value`, for the bits set in `mask`. Example, to
+/// wait until a NOR flash's WIP bit clears: `mask: 0x01, value: 0x00`.
+#[derive(Copy, Clone)]
+pub struct StatusPoll {
+    pub mask: u32,
+    pub value: u32,
+    pub match_mode: PollMatch,
+    /// Number of CLK cycles between reads. Sets the PIR register.
+    pub interval: u16,
+    /// Number of status bytes to read; 1 - 4.
+    pub num_bytes: u8,
+    /// The number of times `poll_status` checks the status match flag before giving up. Each
+    /// check takes a few core clock cycles; a chip erase can take tens of seconds on large
+    /// memories, so set this according to the operation, and your SYSCLK speed. Defaults to
+    /// 1 billion.
+    pub max_polls: u32,
+}
+
+impl Default for StatusPoll {
+    fn default() -> Self {
+        Self {
+            mask: 0x01,
+            value: 0x00,
+            match_mode: PollMatch::And,
+            interval: 16,
+            num_bytes: 1,
+            max_polls: 1_000_000_000,
         }
     }
 }
@@ -138,7 +395,12 @@ impl Qspi {
     pub fn new(regs: QUADSPI, cfg: QspiConfig, clocks: &Clocks) -> Self {
         assert!(
             cfg.dummy_cycles < 32,
-            "Dumy cycles must be between 0 and 31."
+            "Dummy cycles must be between 0 and 31."
+        );
+        // FSIZE and DEVSIZE can describe up to 2^32 bytes.
+        assert!(
+            cfg.mem_size > 0 && cfg.mem_size <= 4_096,
+            "Memory size must be between 1 and 4096 MB."
         );
 
         free(|_| {
@@ -163,10 +425,6 @@ impl Qspi {
                     rcc.ahb3enr.modify(|_, w| w.octospi1en().set_bit());
                     rcc.ahb3rstr.modify(|_, w| w.octospi1rst().set_bit());
                     rcc.ahb3rstr.modify(|_, w| w.octospi1rst().clear_bit());
-                } else if #[cfg(feature = "l5")] {
-                    rcc.ahb3enr.modify(|_, w| w.ospi1en().set_bit());
-                    rcc.ahb3rstr.modify(|_, w| w.ospi1rst().set_bit());
-                    rcc.ahb3rstr.modify(|_, w| w.ospi1rst().clear_bit());
                 } else {
                     rcc.ahb3enr.modify(|_, w| w.qspien().set_bit());
                     rcc.ahb3rstr.modify(|_, w| w.qspirst().set_bit());
@@ -181,38 +439,56 @@ impl Qspi {
         // Many fields, including all CCR fields, can only be set when `BUSY` is clear.
         while regs.sr.read().busy().bit_is_set() {}
 
+        let ddr = cfg.data_mode as u8 != 0;
+
         regs.ccr.modify(|_, w| unsafe {
             w.abmode().bits(cfg.protocol_mode as u8);
             w.admode().bits(cfg.protocol_mode as u8);
             w.imode().bits(cfg.protocol_mode as u8);
             w.dmode().bits(cfg.protocol_mode as u8);
-            #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-            // todo: Equiv for octo?
-            w.ddrm().bit(cfg.data_mode as u8 != 0);
-            #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-            // todo: Equiv for octo?
+            #[cfg(not(any(feature = "h735", feature = "h7b3")))]
+            w.ddrm().bit(ddr);
+            #[cfg(not(any(feature = "h735", feature = "h7b3")))]
             w.dcyc().bits(cfg.dummy_cycles);
+            #[cfg(any(feature = "h735", feature = "h7b3"))]
+            {
+                w.abdtr().bit(ddr);
+                w.addtr().bit(ddr);
+                w.ddtr().bit(ddr);
+                w.idtr()
+                    .bit(ddr && cfg.protocol_mode == ProtocolMode::Octal);
+                w.dqse().bit(cfg.dqs);
+            }
             w.adsize().bits(cfg.address_size as u8)
         });
 
-        // RM: The FSIZE[4:0] field defines the size of external memory using the following formula:
-        // Number of bytes in Flash memory = 2^[FSIZE+1]
-        // The addressable space in memory-mapped mode is limited to 256MB.
-        // regs.dcr.modify(|_, w| unsafe { w.fsize.bits(cfg.mem_size / 2 - 1) });
+        let fsize = fsize(cfg.mem_size);
 
-        let mut fsize = 0;
-        for shift in 0..32 {
-            if cfg.mem_size >> shift == 0 {
-                fsize = shift - 1;
-                break;
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                regs.dcr1.modify(|_, w| unsafe {
+                    w.mtyp().bits(cfg.memory_type as u8);
+                    #[cfg(feature = "h7")]
+                    // Bypass the delay block; it's unused unless calibrated for DQS.
+                    w.dlybyp().set_bit();
+                    w.devsize().bits(fsize)
+                });
+
+                regs.tcr.modify(|_, w| unsafe { w.dcyc().bits(cfg.dummy_cycles) });
+
+                if let MemoryType::HyperBusMemory | MemoryType::HyperBusRegister = cfg.memory_type {
+                    regs.hlcr.write(|w| unsafe {
+                        w.trwr().bits(cfg.hyperbus.rw_recovery);
+                        w.tacc().bits(cfg.hyperbus.access_time);
+                        w.wzl().bit(cfg.hyperbus.write_zero_latency);
+                        w.lm().bit(cfg.hyperbus.fixed_latency)
+                    });
+                }
+            } else {
+                regs.dcr.modify(|_, w| unsafe { w.fsize().bits(fsize) });
             }
         }
 
-        #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-        // todo: Equiv for octo?
-        regs.dcr.modify(|_, w| unsafe { w.fsize().bits(fsize) }); // todo
-                                                                  // regs.dcr.modify(|_, w| unsafe { w.fsize().bits(24) });
-
         // RM: This field [prescaler] defines the scaler factor for generating CLK based on the
         // clock (value+1).
         // 0: FCLK = F, clock used directly as QUADSPI CLK (prescaler bypassed)
@@ -220,8 +496,9 @@ impl Qspi {
         // 2: FCLK = F/3
         // ...
         // 255: FCLK = F/256
-        // todo: What bus is QSPI on? is it selectable? SAI etc?? APB2 as placeholder.
-        let prescaler = match (clocks.apb2() + cfg.frequency - 1) / cfg.frequency {
+        // QUADSPI and OCTOSPI are on AHB. (On H7, the `rcc_hclk3` kernel clock by default)
+        let kernel_clock = clocks.kernel_clock(KernelClockPeriph::Qspi);
+        let prescaler = match (kernel_clock + cfg.frequency - 1) / cfg.frequency {
             divisor @ 1..=256 => divisor - 1,
             _ => panic!("Invalid QSPI frequency requested"),
         };
@@ -238,13 +515,19 @@ impl Qspi {
             DataMode::Ddr => SamplingEdge::Rising,
         };
 
-        #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-        // todo: Equiv for octo?
-        regs.cr.write(|w| unsafe {
-            w.prescaler().bits(prescaler as u8);
-            w.sshift().bit(sampling_edge as u8 != 0);
-            w.fthres().bits(cfg.fifo_threshold - 1)
-        });
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                regs.dcr2.modify(|_, w| unsafe { w.prescaler().bits(prescaler as u8) });
+                regs.tcr.modify(|_, w| w.sshift().bit(sampling_edge as u8 != 0));
+                regs.cr.write(|w| unsafe { w.fthres().bits(cfg.fifo_threshold - 1) });
+            } else {
+                regs.cr.write(|w| unsafe {
+                    w.prescaler().bits(prescaler as u8);
+                    w.sshift().bit(sampling_edge as u8 != 0);
+                    w.fthres().bits(cfg.fifo_threshold - 1)
+                });
+            }
+        }
 
         // Enable ther peripheral
         regs.cr.modify(|_, w| w.en().set_bit());
@@ -286,12 +569,6 @@ impl Qspi {
         // FMODE, and perhaps othe rfields can only be set when BUSY = 0.
         while self.is_busy() {}
 
-        // todo: Fix this
-        assert!(
-            data.len() <= 32,
-            "Transactions larger than the QSPI FIFO are currently unsupported"
-        );
-
         // RM: Indirect Mode procedure:
         // When FMODE is programmed to 00, indirect write mode is selected and data can be sent to
         // the Flash memory. With FMODE = 01, indirect read mode is selected where data can be
@@ -314,11 +591,7 @@ impl Qspi {
         // and DMAEN = 1, then QUADSPI_AR should be specified before QUADSPI_CR,
         // because otherwise QUADSPI_DR might be written by the DMA before QUADSPI_AR
         // is updated (if the DMA controller has already been enabled)
-        #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-        // todo: Equiv for octo?
-        self.regs
-            .ccr
-            .modify(|_, w| unsafe { w.fmode().bits(FunctionalMode::IndirectWrite as u8) });
+        self.set_fmode(FunctionalMode::IndirectWrite);
         // 5. Specify the targeted address in the QUADSPI_AR.
         self.regs
             .ar
@@ -349,8 +622,33 @@ impl Qspi {
         // * The way the data have to be sent/received (None/1/2/4 lines) through the DMODE bits
         // (Above items handled in init)
 
-        unsafe {
-            for word in data {
+        self.write_fifo(data);
+    }
+
+    /// Perform a memory read in indirect mode.
+    pub fn read_indirect(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), QspiError> {
+        // todo: Do we want to use interrupt flats in these blocking fns?
+        self.clear_interrupt(QspiInterrupt::TransferComplete);
+        while self.is_busy() {}
+
+        // Steps are equivalent to those listed in `write_indirect`.
+        self.regs
+            .dlr
+            .write(|w| unsafe { w.dl().bits(buf.len() as u32 - 1) });
+        self.set_fmode(FunctionalMode::IndirectRead);
+        self.regs
+            .ar
+            .modify(|_, w| unsafe { w.address().bits(addr) });
+
+        self.read_fifo(buf)
+    }
+
+    /// Write data to the FIFO as space becomes available, then wait for the transfer to complete.
+    /// This allows transfers of any length, independent of the FIFO size.
+    fn write_fifo(&mut self, data: &[u8]) {
+        for word in data {
+            while self.regs.sr.read().flevel().bits() >= FIFO_SIZE {}
+            unsafe {
                 ptr::write_volatile(&self.regs.dr as *const _ as *mut u8, *word);
             }
         }
@@ -362,64 +660,453 @@ impl Qspi {
         while self.is_busy() {}
     }
 
-    /// Perform a memory read in indirect mode.
-    pub fn read_indirect(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), QspiError> {
-        // todo: Do we want to use interrupt flats in these blocking fns?
+    /// Read data from the FIFO as it arrives, then wait for the transfer to complete. This allows
+    /// transfers of any length, independent of the FIFO size.
+    fn read_fifo(&mut self, buf: &mut [u8]) -> Result<(), QspiError> {
+        for word in buf {
+            loop {
+                let sr = self.regs.sr.read();
+                if sr.tef().bit_is_set() {
+                    self.clear_interrupt(QspiInterrupt::TransferError);
+                    return Err(QspiError::Transfer);
+                }
+                if sr.flevel().bits() > 0 {
+                    break;
+                }
+                // The transfer completed, but there's no more data for our buffer.
+                if sr.tcf().bit_is_set() {
+                    return Err(QspiError::Underflow);
+                }
+            }
+
+            *word = unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) };
+        }
+
+        // Wait for the peripheral to indicate it is no longer busy.
+        while self.is_busy() {}
+
+        Ok(())
+    }
+
+    /// Set the functional mode. This is in the CCR register on QUADSPI, and CR on OctoSPI.
+    fn set_fmode(&mut self, fmode: FunctionalMode) {
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                self.regs.cr.modify(|_, w| unsafe { w.fmode().bits(fmode as u8) });
+            } else {
+                self.regs.ccr.modify(|_, w| unsafe { w.fmode().bits(fmode as u8) });
+            }
+        }
+    }
+
+    /// Set up the registers for a command, in a given functional mode. The transaction starts
+    /// once the last required register is written: CCR if there's no address, otherwise AR. (In
+    /// indirect write mode with data, once data is written to the FIFO)
+    fn start_command(&mut self, cmd: &Command, fmode: FunctionalMode, data_len: usize) {
+        while self.is_busy() {}
         self.clear_interrupt(QspiInterrupt::TransferComplete);
+
+        if data_len > 0 {
+            self.regs
+                .dlr
+                .write(|w| unsafe { w.dl().bits(data_len as u32 - 1) });
+        }
+
+        if let Some(ab) = cmd.alternate_bytes {
+            self.regs.abr.write(|w| unsafe { w.alternate().bits(ab) });
+        }
+
+        // A mode value of 0 skips the phase.
+        let imode = if cmd.instruction.is_some() {
+            cmd.instruction_mode as u8
+        } else {
+            0
+        };
+        let admode = if cmd.address.is_some() {
+            cmd.address_mode as u8
+        } else {
+            0
+        };
+        let abmode = if cmd.alternate_bytes.is_some() {
+            cmd.alternate_mode as u8
+        } else {
+            0
+        };
+        // In memory-mapped mode, the data length and address come from the bus access.
+        let mem_mapped = matches!(fmode, FunctionalMode::MemoryMapped);
+        let dmode = if data_len > 0 || mem_mapped {
+            cmd.data_mode as u8
+        } else {
+            0
+        };
+        let ddr = self.cfg.data_mode as u8 != 0;
+
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                self.set_fmode(fmode);
+
+                // In HyperBus mode, the command, address and latency are generated by hardware;
+                // only the address (and DLR) are used.
+                if let MemoryType::HyperBusMemory | MemoryType::HyperBusRegister = self.cfg.memory_type {
+                } else {
+                    self.regs.tcr.modify(|_, w| unsafe { w.dcyc().bits(cmd.dummy_cycles) });
+
+                    self.regs.ccr.write(|w| unsafe {
+                        w.dqse().bit(self.cfg.dqs);
+                        w.ddtr().bit(ddr);
+                        w.dmode().bits(dmode);
+                        w.absize().bits(cmd.alternate_size as u8);
+                        w.abdtr().bit(ddr);
+                        w.abmode().bits(abmode);
+                        w.adsize().bits(cmd.address_size as u8);
+                        w.addtr().bit(ddr);
+                        w.admode().bits(admode);
+                        w.isize().bits(cmd.instruction_size as u8);
+                        w.idtr().bit(ddr && cmd.instruction_mode == ProtocolMode::Octal);
+                        w.imode().bits(imode)
+                    });
+
+                    // Writing the instruction starts the transaction, if there's no address.
+                    if let Some(instruction) = cmd.instruction {
+                        self.regs.ir.write(|w| unsafe { w.instruction().bits(instruction) });
+                    }
+                }
+            } else {
+                self.regs.ccr.write(|w| unsafe {
+                    w.ddrm().bit(ddr);
+                    w.fmode().bits(fmode as u8);
+                    w.dmode().bits(dmode);
+                    w.dcyc().bits(cmd.dummy_cycles);
+                    w.absize().bits(cmd.alternate_size as u8);
+                    w.abmode().bits(abmode);
+                    w.adsize().bits(cmd.address_size as u8);
+                    w.admode().bits(admode);
+                    w.imode().bits(imode);
+                    w.instruction().bits(cmd.instruction.unwrap_or(0) as u8)
+                });
+            }
+        }
+
+        if let Some(addr) = cmd.address {
+            if !mem_mapped {
+                self.regs.ar.write(|w| unsafe { w.address().bits(addr) });
+            }
+        }
+    }
+
+    /// Enter memory-mapped mode, using `cmd` as the read command. The external memory can then
+    /// be read directly at the memory-mapped region's address. (0x9000_0000) The command's
+    /// address value is ignored, but it must have one set, to enable the address phase. Reads of
+    /// the region are performed with the command's data mode.
+    pub fn enable_memory_mapped(&mut self, cmd: &Command) {
+        self.start_command(cmd, FunctionalMode::MemoryMapped, 0);
+    }
+
+    /// Exit memory-mapped mode, by aborting the ongoing (or prefetching) access.
+    pub fn disable_memory_mapped(&mut self) {
+        self.regs.cr.modify(|_, w| w.abort().set_bit());
+        while self.regs.cr.read().abort().bit_is_set() {}
         while self.is_busy() {}
 
-        // todo: Fix this
+        self.set_fmode(FunctionalMode::IndirectWrite);
+    }
+
+    /// Send a command with no data phase; eg write-enable, or chip-erase. Blocks until complete.
+    pub fn command(&mut self, cmd: &Command) {
+        self.start_command(cmd, FunctionalMode::IndirectWrite, 0);
+
+        while self.regs.sr.read().tcf().bit_is_clear() {}
+        while self.is_busy() {}
+    }
+
+    /// Send a command, followed by a data phase writing `data`, in indirect mode. Any length
+    /// is supported; the FIFO is refilled as it drains.
+    pub fn write(&mut self, cmd: &Command, data: &[u8]) {
+        if data.is_empty() {
+            self.command(cmd);
+            return;
+        }
+
+        self.start_command(cmd, FunctionalMode::IndirectWrite, data.len());
+        self.write_fifo(data);
+    }
+
+    /// Send a command, followed by a data phase reading into `buf`, in indirect mode. Any length
+    /// is supported; the FIFO is drained as it fills.
+    pub fn read(&mut self, cmd: &Command, buf: &mut [u8]) -> Result<(), QspiError> {
+        self.start_command(cmd, FunctionalMode::IndirectRead, buf.len());
+        self.read_fifo(buf)
+    }
+
+    /// Start automatic status-polling mode: The command is sent repeatedly, until the status
+    /// bytes match. The peripheral stops polling automatically on a match, setting the status
+    /// match flag. Use `QspiInterrupt::StatusMatch` to be notified, or use `poll_status` to block.
+    pub fn start_status_polling(&mut self, cmd: &Command, poll: &StatusPoll) {
         assert!(
-            buf.len() <= 32,
-            "Transactions larger than the QSPI FIFO are currently unsupported"
+            poll.num_bytes >= 1 && poll.num_bytes <= 4,
+            "Status polling reads 1 - 4 bytes."
         );
 
-        // Steps are equivalent to those listed in `write_indirect`.
+        while self.is_busy() {}
+        self.clear_interrupt(QspiInterrupt::StatusMatch);
+
+        // RM: In automatic status-polling mode, [...] The QUADSPI_PSMKR register is used to
+        // mask the status bytes received [...] and the QUADSPI_PSMAR register holds the value
+        // to compare with. These registers, and PIR, must be written when BUSY = 0.
         self.regs
-            .dlr
-            .write(|w| unsafe { w.dl().bits(buf.len() as u32 - 1) });
-        #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-        // todo: Equiv for octo?
+            .psmkr
+            .write(|w| unsafe { w.mask().bits(poll.mask) });
         self.regs
-            .ccr
-            .modify(|_, w| unsafe { w.fmode().bits(FunctionalMode::IndirectRead as u8) });
+            .psmar
+            .write(|w| unsafe { w.match_().bits(poll.value) });
         self.regs
-            .ar
-            .modify(|_, w| unsafe { w.address().bits(addr) });
+            .pir
+            .write(|w| unsafe { w.interval().bits(poll.interval) });
 
-        // Check for underflow on the FIFO.
-        if (self.regs.sr.read().flevel().bits() as usize) < buf.len() {
-            return Err(QspiError::Underflow);
-        }
+        // APMS: Stop polling as soon as there's a match.
+        self.regs.cr.modify(|_, w| {
+            w.pmm().bit(poll.match_mode as u8 != 0);
+            w.apms().set_bit()
+        });
 
-        unsafe {
-            for word in buf {
-                *word = ptr::read_volatile(&self.regs.dr as *const _ as *const u8);
+        self.start_command(cmd, FunctionalMode::StatusPolling, poll.num_bytes as usize);
+    }
+
+    /// Poll the status until it matches, eg to wait for a flash's write-in-progress bit to
+    /// clear after a program or erase. Blocks until a match, or returns `QspiError::Timeout`
+    /// if there's none after `poll.max_polls` checks; polling is aborted in that case.
+    pub fn poll_status(&mut self, cmd: &Command, poll: &StatusPoll) -> Result<(), QspiError> {
+        self.start_status_polling(cmd, poll);
+
+        let mut i = 0;
+        while self.regs.sr.read().smf().bit_is_clear() {
+            i += 1;
+            if i >= poll.max_polls {
+                self.regs.cr.modify(|_, w| w.abort().set_bit());
+                while self.regs.cr.read().abort().bit_is_set() {}
+                return Err(QspiError::Timeout);
             }
         }
+        self.clear_interrupt(QspiInterrupt::StatusMatch);
 
-        // Wait for the peripheral to indicate it is no longer busy.
         while self.is_busy() {}
-
         Ok(())
     }
 
-    // todo: write_indirect_dma fn.
+    #[cfg(not(feature = "h7"))]
+    /// Send a command, followed by a data phase writing `buf` using DMA. Configure channel
+    /// selection or DMAMUX for QUADSPI separately. Run `stop_dma` once the transfer is complete.
+    /// (H7 connects QUADSPI to MDMA only, so this isn't available there.)
+    pub unsafe fn write_dma<D>(
+        &mut self,
+        cmd: &Command,
+        buf: &[u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let (ptr, len) = (buf.as_ptr(), buf.len());
+        assert!(
+            len <= u16::MAX as usize,
+            "QSPI DMA transfers must be 65535 bytes or less."
+        );
+
+        // RM: If FMODE = 00 (indirect write mode) and DMAEN = 1, then QUADSPI_AR should be
+        // specified before QUADSPI_CR.
+        self.start_command(cmd, FunctionalMode::IndirectWrite, len);
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.dr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromMem,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        self.regs.cr.modify(|_, w| w.dmaen().set_bit());
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Send a command, followed by a data phase reading into `buf` using DMA. Configure channel
+    /// selection or DMAMUX for QUADSPI separately. Run `stop_dma` once the transfer is complete.
+    pub unsafe fn read_dma<D>(
+        &mut self,
+        cmd: &Command,
+        buf: &mut [u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        let (ptr, len) = (buf.as_mut_ptr(), buf.len());
+        assert!(
+            len <= u16::MAX as usize,
+            "QSPI DMA transfers must be 65535 bytes or less."
+        );
+
+        dma.cfg_channel(
+            channel,
+            &self.regs.dr as *const _ as u32,
+            ptr as u32,
+            len as u16,
+            dma::Direction::ReadFromPeriph,
+            dma::DataSize::S8,
+            dma::DataSize::S8,
+            channel_cfg,
+        );
+
+        self.regs.cr.modify(|_, w| w.dmaen().set_bit());
+
+        self.start_command(cmd, FunctionalMode::IndirectRead, len);
+    }
+
+    #[cfg(not(feature = "h7"))]
+    /// Stop a DMA transfer, and disable DMA requests from the peripheral.
+    pub fn stop_dma<D>(&mut self, channel: DmaChannel, dma: &mut Dma<D>)
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        dma.stop(channel);
+
+        while self.is_busy() {}
+        self.regs.cr.modify(|_, w| w.dmaen().clear_bit());
+    }
 
     /// Read one word from memory in memory-mapped mode
     pub fn read_1_mem_mapped(&mut self, offset: isize) -> u32 {
         // todo: unsafe fn? word size?
         while self.is_busy() {}
 
-        #[cfg(not(any(feature = "l5", feature = "h735", feature = "h7b3")))]
-        // todo: Equiv for octo?
-        if self.regs.ccr.read().fmode().bits() != FunctionalMode::MemoryMapped as u8 {
-            self.regs
-                .ccr
-                .modify(|_, w| unsafe { w.fmode().bits(FunctionalMode::MemoryMapped as u8) });
+        cfg_if! {
+            if #[cfg(any(feature = "h735", feature = "h7b3"))] {
+                let fmode = self.regs.cr.read().fmode().bits();
+            } else {
+                let fmode = self.regs.ccr.read().fmode().bits();
+            }
+        }
+
+        if fmode != FunctionalMode::MemoryMapped as u8 {
+            self.set_fmode(FunctionalMode::MemoryMapped);
         }
 
         let addr = MEM_MAPPED_BASE_ADDR as *const u32; // as const what?
         unsafe { core::ptr::read(addr.offset(offset)) }
     }
 }
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+#[derive(Copy, Clone, PartialEq)]
+/// An OctoSPI peripheral instance, for routing with the I/O manager.
+pub enum OctospiInstance {
+    Octospi1 = 0,
+    Octospi2 = 1,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+#[derive(Copy, Clone, PartialEq)]
+/// The source of a port's IO[3:0] or IO[7:4] pins. Sets the IOLSRC and IOHSRC fields.
+pub enum IoSource {
+    /// IO[3:0] of OCTOSPI1
+    Octospi1Low = 0b00,
+    /// IO[7:4] of OCTOSPI1
+    Octospi1High = 0b01,
+    /// IO[3:0] of OCTOSPI2
+    Octospi2Low = 0b10,
+    /// IO[7:4] of OCTOSPI2
+    Octospi2High = 0b11,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+/// Routing for one OctoSPI I/O manager port; ie which OctoSPI instance drives that port's pins.
+/// Signals set to `None` are disabled on the port.
+pub struct OctospimPort {
+    pub clk: Option<OctospiInstance>,
+    pub dqs: Option<OctospiInstance>,
+    pub ncs: Option<OctospiInstance>,
+    pub io_low: Option<IoSource>,
+    pub io_high: Option<IoSource>,
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+impl OctospimPort {
+    /// Route all signals of an OctoSPI instance to this port.
+    pub fn routed_to(instance: OctospiInstance) -> Self {
+        let (io_low, io_high) = match instance {
+            OctospiInstance::Octospi1 => (IoSource::Octospi1Low, IoSource::Octospi1High),
+            OctospiInstance::Octospi2 => (IoSource::Octospi2Low, IoSource::Octospi2High),
+        };
+
+        Self {
+            clk: Some(instance),
+            dqs: Some(instance),
+            ncs: Some(instance),
+            io_low: Some(io_low),
+            io_high: Some(io_high),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            clk: None,
+            dqs: None,
+            ncs: None,
+            io_low: None,
+            io_high: None,
+        }
+    }
+}
+
+#[cfg(any(feature = "h735", feature = "h7b3"))]
+/// Configure the OctoSPI I/O manager, which routes the OctoSPI peripherals to the two sets of
+/// OctoSPI pins. (Ports 1 and 2) By default, OCTOSPI1 uses port 1, and OCTOSPI2 uses port 2.
+/// `multiplexed` time-multiplexes both peripherals onto the same port. Run this before
+/// initializing the OctoSPI peripherals, with them disabled.
+pub fn configure_io_manager(
+    regs: &mut crate::pac::OCTOSPII_O_MANAGER,
+    port1: &OctospimPort,
+    port2: &OctospimPort,
+    multiplexed: bool,
+) {
+    free(|_| {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        cfg_if! {
+            if #[cfg(feature = "h735")] {
+                rcc.ahb3enr.modify(|_, w| w.iomngren().set_bit());
+                rcc.ahb3rstr.modify(|_, w| w.iomngrrst().set_bit());
+                rcc.ahb3rstr.modify(|_, w| w.iomngrrst().clear_bit());
+            } else {
+                rcc.ahb3enr.modify(|_, w| w.octospimen().set_bit());
+            }
+        }
+    });
+
+    regs.cr.modify(|_, w| w.muxen().bit(multiplexed));
+
+    // Note: Each port's fields are identical; the PAC has separate types for them.
+    macro_rules! set_port {
+        ($reg:ident, $port:expr) => {
+            regs.$reg.write(|w| unsafe {
+                w.clken().bit($port.clk.is_some());
+                w.clksrc().bit($port.clk == Some(OctospiInstance::Octospi2));
+                w.dqsen().bit($port.dqs.is_some());
+                w.dqssrc().bit($port.dqs == Some(OctospiInstance::Octospi2));
+                w.ncsen().bit($port.ncs.is_some());
+                w.ncssrc().bit($port.ncs == Some(OctospiInstance::Octospi2));
+                w.iolen().bit($port.io_low.is_some());
+                w.iolsrc().bits($port.io_low.map(|s| s as u8).unwrap_or(0));
+                w.iohen().bit($port.io_high.is_some());
+                w.iohsrc().bits($port.io_high.map(|s| s as u8).unwrap_or(0))
+            })
+        };
+    }
+
+    set_port!(p1cr, port1);
+    set_port!(p2cr, port2);
+}
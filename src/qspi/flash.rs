@@ -0,0 +1,760 @@
+//! Driver for external serial NOR flash, eg Winbond W25Q and Macronix MX25 parts, on top of
+//! `Qspi`. Reads the JEDEC ID, and the SFDP (Serial Flash Discoverable Parameters, JESD216)
+//! tables to learn the memory's size, page size, erase types and quad-enable method. Provides
+//! reads, page programs, erases with busy polling, and memory-mapped (XIP) access.
+//!
+//! SFDP parsing doesn't access hardware; `FlashParams::from_sfdp` can be run on captured dumps.
+
+use core::ops::Deref;
+
+use super::{
+    fsize, AddressSize, Command, ProtocolMode, Qspi, QspiError, StatusPoll, MEM_MAPPED_BASE_ADDR,
+};
+
+// Standard SPI NOR instructions. These are common to W25Q, MX25 and most others.
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_SR1: u8 = 0x05;
+const CMD_READ_SR2: u8 = 0x35;
+const CMD_WRITE_SR: u8 = 0x01;
+const CMD_WRITE_SR2: u8 = 0x31;
+const CMD_READ_SR2_BIT7: u8 = 0x3f;
+const CMD_WRITE_SR2_BIT7: u8 = 0x3e;
+const CMD_READ_JEDEC_ID: u8 = 0x9f;
+const CMD_READ_SFDP: u8 = 0x5a;
+const CMD_FAST_READ: u8 = 0x0b;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_CHIP_ERASE: u8 = 0xc7;
+const CMD_ENTER_4_BYTE_ADDR: u8 = 0xb7;
+
+/// `SFDP`, little endian.
+const SFDP_SIGNATURE: u32 = 0x5044_4653;
+/// Parameter ID of the Basic Flash Parameter Table.
+const BFPT_ID: u16 = 0xff00;
+/// Number of bytes read from the start of the SFDP area. Basic tables are within this on
+/// the parts we've seen.
+const SFDP_READ_LEN: usize = 256;
+
+/// Status register 1 write-in-progress bit.
+const SR1_WIP: u32 = 1 << 0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// A QSPI transfer failed.
+    Qspi,
+    /// The SFDP header doesn't start with the `SFDP` signature.
+    SfdpSignature,
+    /// There's no Basic Flash Parameter Table.
+    SfdpNoBasicTable,
+    /// The SFDP data is truncated: The header, a parameter header, or the Basic Flash Parameter
+    /// Table is past the end of the data read.
+    SfdpOutOfRange,
+    /// The address or length is past the end of the memory, or there's no matching erase type.
+    InvalidArgument,
+}
+
+impl From<QspiError> for Error {
+    fn from(_e: QspiError) -> Self {
+        Self::Qspi
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Manufacturer, memory type, and capacity bytes, as returned by the Read JEDEC ID command.
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// How to set the Quad Enable bit. From the BFPT's QER field, DWORD 15 bits 22:20.
+pub enum QuadEnable {
+    /// No QE bit; quad mode is always available, or not supported.
+    None,
+    /// QE is bit 1 of status register 2, and is set by writing SR1 and SR2 together with `0x01`.
+    Sr2Bit1WriteSr1Sr2,
+    /// QE is bit 6 of status register 1. (eg Macronix)
+    Sr1Bit6,
+    /// QE is bit 7 of status register 2, read with `0x3f` and written with `0x3e`.
+    Sr2Bit7,
+    /// QE is bit 1 of status register 2, written on its own with `0x31`. (eg newer Winbond)
+    Sr2Bit1WriteSr2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// An erase type supported by the memory.
+pub struct EraseType {
+    /// Size, in bytes.
+    pub size: u32,
+    pub instruction: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// A fast read command, and its dummy cycles. (Including mode clocks)
+pub struct ReadCmd {
+    pub instruction: u8,
+    pub dummy_cycles: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Memory parameters, as discovered from SFDP.
+pub struct FlashParams {
+    /// Size, in bytes.
+    pub size: u32,
+    /// Page program size, in bytes.
+    pub page_size: u32,
+    /// Supported erase types, ordered as listed in the BFPT. (Usually smallest first)
+    pub erase_types: [Option<EraseType>; 4],
+    pub quad_enable: QuadEnable,
+    /// 1-1-4 fast read (instruction and address on 1 line, data on 4), if supported.
+    pub read_1_1_4: Option<ReadCmd>,
+    /// 4-byte addresses are required, either by the memory, or to access all of it.
+    pub four_byte_addr: bool,
+}
+
+/// Read a little-endian DWORD. `n` is 1-indexed, as in JESD216.
+fn dword(table: &[u8], n: usize) -> Option<u32> {
+    let i = (n - 1) * 4;
+    let b = table.get(i..i + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+impl FlashParams {
+    /// Parse SFDP data, starting at SFDP address 0. `sfdp` must include the header, parameter
+    /// headers, and the Basic Flash Parameter Table.
+    pub fn from_sfdp(sfdp: &[u8]) -> Result<Self, Error> {
+        if dword(sfdp, 1) != Some(SFDP_SIGNATURE) {
+            return Err(Error::SfdpSignature);
+        }
+
+        // Byte 6 is the number of parameter headers, minus one. Parameter headers start at byte 8,
+        // and are 8 bytes each.
+        let num_headers = *sfdp.get(6).ok_or(Error::SfdpOutOfRange)? as usize + 1;
+
+        let mut bfpt = None;
+        for i in 0..num_headers {
+            let h = sfdp
+                .get(8 + i * 8..16 + i * 8)
+                .ok_or(Error::SfdpOutOfRange)?;
+
+            let id = u16::from_le_bytes([h[0], h[7]]);
+            if id == BFPT_ID {
+                let len = h[3] as usize * 4;
+                let ptr = u32::from_le_bytes([h[4], h[5], h[6], 0]) as usize;
+                bfpt = Some((ptr, len));
+                break;
+            }
+        }
+
+        let (ptr, len) = bfpt.ok_or(Error::SfdpNoBasicTable)?;
+        let table = sfdp.get(ptr..ptr + len).ok_or(Error::SfdpOutOfRange)?;
+
+        Self::from_bfpt(table)
+    }
+
+    /// Parse the Basic Flash Parameter Table. JESD216 revisions before B have fewer DWORDs;
+    /// missing fields use common defaults.
+    pub fn from_bfpt(table: &[u8]) -> Result<Self, Error> {
+        let dw1 = dword(table, 1).ok_or(Error::SfdpOutOfRange)?;
+        let dw2 = dword(table, 2).ok_or(Error::SfdpOutOfRange)?;
+
+        // DWORD 2: Density. If bit 31 is clear, the density in bits is the value + 1. Otherwise,
+        // it's 2^N bits.
+        let size = if dw2 & 0x8000_0000 == 0 {
+            (dw2 + 1) / 8
+        } else {
+            let n = dw2 & 0x7fff_ffff;
+            if !(3..35).contains(&n) {
+                return Err(Error::InvalidArgument);
+            }
+            1 << (n - 3)
+        };
+
+        // DWORD 1, bits 18:17: Address bytes. 0b00: 3 bytes only. 0b01: 3 or 4. 0b10: 4 only.
+        // Memories larger than 16MB need 4-byte addresses to reach their upper area.
+        let four_byte_addr = (dw1 >> 17) & 0b11 == 0b10 || size > 0x100_0000;
+
+        // DWORD 1, bit 22: 1-1-4 fast read supported. DWORD 3, bits 31:16: Its instruction,
+        // mode clocks, and dummy clocks. The peripheral supports up to 31 dummy cycles; treat
+        // reads needing more as unsupported.
+        let read_1_1_4 = if dw1 & (1 << 22) != 0 {
+            dword(table, 3)
+                .map(|dw3| ReadCmd {
+                    instruction: (dw3 >> 24) as u8,
+                    dummy_cycles: ((dw3 >> 16) & 0x1f) as u8 + ((dw3 >> 21) & 0b111) as u8,
+                })
+                .filter(|read| read.dummy_cycles < 32)
+        } else {
+            None
+        };
+
+        // DWORDs 8 and 9: Erase types 1 - 4. Each is a size exponent byte, then an instruction.
+        let mut erase_types = [None; 4];
+        if let (Some(dw8), Some(dw9)) = (dword(table, 8), dword(table, 9)) {
+            let raw = [
+                dw8 as u16,
+                (dw8 >> 16) as u16,
+                dw9 as u16,
+                (dw9 >> 16) as u16,
+            ];
+            for (i, et) in raw.iter().enumerate() {
+                let exp = (et & 0xff) as u32;
+                if exp != 0 && exp < 32 {
+                    erase_types[i] = Some(EraseType {
+                        size: 1 << exp,
+                        instruction: (et >> 8) as u8,
+                    });
+                }
+            }
+        }
+
+        // If erase types aren't listed, fall back to the 4KB erase in DWORD 1. (Bits 1:0 = 0b01)
+        if erase_types.iter().all(|e| e.is_none()) && dw1 & 0b11 == 0b01 {
+            erase_types[0] = Some(EraseType {
+                size: 4_096,
+                instruction: (dw1 >> 8) as u8,
+            });
+        }
+
+        // DWORD 11, bits 7:4: Page size is 2^N bytes. (JESD216A and later)
+        let page_size = match dword(table, 11) {
+            Some(dw11) => 1 << ((dw11 >> 4) & 0xf),
+            None => 256,
+        };
+
+        // DWORD 15, bits 22:20: Quad Enable Requirements.
+        let quad_enable = match dword(table, 15).map(|dw15| (dw15 >> 20) & 0b111) {
+            Some(0b001) | Some(0b100) | Some(0b101) => QuadEnable::Sr2Bit1WriteSr1Sr2,
+            Some(0b010) => QuadEnable::Sr1Bit6,
+            Some(0b011) => QuadEnable::Sr2Bit7,
+            Some(0b110) => QuadEnable::Sr2Bit1WriteSr2,
+            _ => QuadEnable::None,
+        };
+
+        Ok(Self {
+            size,
+            page_size,
+            erase_types,
+            quad_enable,
+            read_1_1_4,
+            four_byte_addr,
+        })
+    }
+}
+
+/// An external NOR flash memory, connected over QSPI.
+pub struct NorFlash {
+    pub qspi: Qspi,
+    pub params: FlashParams,
+    /// Quad mode has been enabled with `enable_quad`; reads use the 1-1-4 read command.
+    quad: bool,
+    mem_mapped: bool,
+}
+
+impl NorFlash {
+    /// Create the driver, reading SFDP parameters from the memory. If the memory is larger than
+    /// 16MB, this switches it to 4-byte address mode.
+    pub fn new(qspi: Qspi) -> Result<Self, Error> {
+        let mut result = Self {
+            qspi,
+            // Placeholder until we've read SFDP; the read uses 3-byte addresses.
+            params: FlashParams {
+                size: 0,
+                page_size: 256,
+                erase_types: [None; 4],
+                quad_enable: QuadEnable::None,
+                read_1_1_4: None,
+                four_byte_addr: false,
+            },
+            quad: false,
+            mem_mapped: false,
+        };
+
+        let mut sfdp = [0; SFDP_READ_LEN];
+        result.read_sfdp(0, &mut sfdp)?;
+        result.params = FlashParams::from_sfdp(&sfdp)?;
+
+        if result.params.four_byte_addr {
+            result.qspi.command(&Command::new(CMD_ENTER_4_BYTE_ADDR));
+        }
+
+        Ok(result)
+    }
+
+    fn addr_size(&self) -> AddressSize {
+        if self.params.four_byte_addr {
+            AddressSize::A32
+        } else {
+            AddressSize::A24
+        }
+    }
+
+    /// Leave memory-mapped mode, if in it, before an indirect-mode command.
+    fn unmap(&mut self) {
+        if self.mem_mapped {
+            self.qspi.disable_memory_mapped();
+            self.mem_mapped = false;
+        }
+    }
+
+    /// Read the manufacturer and device ID.
+    pub fn read_jedec_id(&mut self) -> Result<JedecId, Error> {
+        self.unmap();
+
+        let mut buf = [0; 3];
+        self.qspi.read(&Command::new(CMD_READ_JEDEC_ID), &mut buf)?;
+
+        Ok(JedecId {
+            manufacturer: buf[0],
+            memory_type: buf[1],
+            capacity: buf[2],
+        })
+    }
+
+    /// Read raw SFDP data, starting at SFDP address `addr`. Per JESD216, this always uses a
+    /// 3-byte address and 8 dummy cycles.
+    pub fn read_sfdp(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.unmap();
+
+        let cmd = Command::new(CMD_READ_SFDP)
+            .with_address(addr, AddressSize::A24)
+            .with_dummy_cycles(8);
+
+        Ok(self.qspi.read(&cmd, buf)?)
+    }
+
+    /// Read status register 1.
+    pub fn read_status(&mut self) -> Result<u8, Error> {
+        self.unmap();
+        self.read_reg(CMD_READ_SR1)
+    }
+
+    fn read_reg(&mut self, instruction: u8) -> Result<u8, Error> {
+        let mut buf = [0];
+        self.qspi.read(&Command::new(instruction), &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_enable(&mut self) {
+        self.qspi.command(&Command::new(CMD_WRITE_ENABLE));
+    }
+
+    /// Block until the write-in-progress bit clears, using the peripheral's automatic
+    /// status-polling mode. Returns an error if it doesn't clear within the default
+    /// `StatusPoll::max_polls`.
+    pub fn wait_busy(&mut self) -> Result<(), Error> {
+        self.unmap();
+
+        let poll = StatusPoll {
+            mask: SR1_WIP,
+            value: 0,
+            ..Default::default()
+        };
+
+        Ok(self.qspi.poll_status(&Command::new(CMD_READ_SR1), &poll)?)
+    }
+
+    /// Set the Quad Enable bit, using the method reported by SFDP, and use quad (1-1-4) reads from
+    /// now on. The QE bit is non-volatile on most parts.
+    pub fn enable_quad(&mut self) -> Result<(), Error> {
+        self.unmap();
+
+        match self.params.quad_enable {
+            QuadEnable::None => (),
+            QuadEnable::Sr1Bit6 => {
+                let sr1 = self.read_reg(CMD_READ_SR1)?;
+                self.write_enable();
+                self.qspi
+                    .write(&Command::new(CMD_WRITE_SR), &[sr1 | 1 << 6]);
+            }
+            QuadEnable::Sr2Bit1WriteSr1Sr2 => {
+                let sr1 = self.read_reg(CMD_READ_SR1)?;
+                let sr2 = self.read_reg(CMD_READ_SR2)?;
+                self.write_enable();
+                self.qspi
+                    .write(&Command::new(CMD_WRITE_SR), &[sr1, sr2 | 1 << 1]);
+            }
+            QuadEnable::Sr2Bit1WriteSr2 => {
+                let sr2 = self.read_reg(CMD_READ_SR2)?;
+                self.write_enable();
+                self.qspi
+                    .write(&Command::new(CMD_WRITE_SR2), &[sr2 | 1 << 1]);
+            }
+            QuadEnable::Sr2Bit7 => {
+                let sr2 = self.read_reg(CMD_READ_SR2_BIT7)?;
+                self.write_enable();
+                self.qspi
+                    .write(&Command::new(CMD_WRITE_SR2_BIT7), &[sr2 | 1 << 7]);
+            }
+        }
+
+        self.wait_busy()?;
+        self.quad = self.params.read_1_1_4.is_some();
+
+        Ok(())
+    }
+
+    /// The command used for reads, and memory-mapped mode: 1-1-4 if quad mode is enabled,
+    /// otherwise single-line fast read.
+    fn read_cmd(&self, addr: u32) -> Command {
+        match (self.quad, self.params.read_1_1_4) {
+            (true, Some(read)) => Command::new(read.instruction)
+                .with_address(addr, self.addr_size())
+                .with_dummy_cycles(read.dummy_cycles)
+                .with_data_mode(ProtocolMode::Quad),
+            _ => Command::new(CMD_FAST_READ)
+                .with_address(addr, self.addr_size())
+                .with_dummy_cycles(8),
+        }
+    }
+
+    fn check_range(&self, addr: u32, len: usize) -> Result<(), Error> {
+        if in_range(self.params.size, addr, len) {
+            Ok(())
+        } else {
+            Err(Error::InvalidArgument)
+        }
+    }
+
+    /// Read data, starting at `addr`.
+    pub fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.check_range(addr, buf.len())?;
+        self.unmap();
+
+        let cmd = self.read_cmd(addr);
+        Ok(self.qspi.read(&cmd, buf)?)
+    }
+
+    /// Program up to one page. The data must not cross a page boundary, and the area must be
+    /// erased first. Blocks until complete.
+    pub fn page_program(&mut self, addr: u32, data: &[u8]) -> Result<(), Error> {
+        let page = self.params.page_size;
+        if data.len() as u32 > page - addr % page {
+            return Err(Error::InvalidArgument);
+        }
+        self.check_range(addr, data.len())?;
+        self.unmap();
+
+        self.write_enable();
+        let cmd = Command::new(CMD_PAGE_PROGRAM).with_address(addr, self.addr_size());
+        self.qspi.write(&cmd, data);
+        self.wait_busy()
+    }
+
+    /// Program data of any length, split into page programs. The area must be erased first.
+    pub fn write(&mut self, mut addr: u32, mut data: &[u8]) -> Result<(), Error> {
+        self.check_range(addr, data.len())?;
+
+        let page = self.params.page_size;
+        while !data.is_empty() {
+            let len = ((page - addr % page) as usize).min(data.len());
+            self.page_program(addr, &data[..len])?;
+
+            addr += len as u32;
+            data = &data[len..];
+        }
+
+        Ok(())
+    }
+
+    /// Erase a block of `size` bytes, using the erase type of that size reported by SFDP. `addr`
+    /// is rounded down to the block boundary by the memory. Blocks until complete.
+    pub fn erase(&mut self, addr: u32, size: u32) -> Result<(), Error> {
+        // The byte at `addr` is erased, so it must be in the memory.
+        self.check_range(addr, 1)?;
+
+        let erase = self
+            .params
+            .erase_types
+            .iter()
+            .flatten()
+            .find(|e| e.size == size)
+            .ok_or(Error::InvalidArgument)?;
+        let instruction = erase.instruction;
+
+        self.erase_with(instruction, addr)
+    }
+
+    /// Erase the 4KB sector containing `addr`. Blocks until complete.
+    pub fn erase_sector(&mut self, addr: u32) -> Result<(), Error> {
+        self.check_range(addr, 1)?;
+
+        let instruction = self
+            .params
+            .erase_types
+            .iter()
+            .flatten()
+            .find(|e| e.size == 4_096)
+            .map(|e| e.instruction)
+            .unwrap_or(CMD_SECTOR_ERASE);
+
+        self.erase_with(instruction, addr)
+    }
+
+    fn erase_with(&mut self, instruction: u8, addr: u32) -> Result<(), Error> {
+        self.unmap();
+
+        self.write_enable();
+        self.qspi
+            .command(&Command::new(instruction).with_address(addr, self.addr_size()));
+        self.wait_busy()
+    }
+
+    /// Erase the whole memory. Blocks until complete; this can take tens of seconds.
+    pub fn erase_chip(&mut self) -> Result<(), Error> {
+        self.unmap();
+
+        self.write_enable();
+        self.qspi.command(&Command::new(CMD_CHIP_ERASE));
+        self.wait_busy()
+    }
+
+    /// Switch the peripheral to memory-mapped mode, eg for XIP, or reading assets without
+    /// copying. The returned guard derefs to the memory contents as a slice. It borrows the
+    /// driver, so the memory can't be programmed or erased while the slice is accessible.
+    /// Memory-mapped mode is left when it's dropped.
+    pub fn memory_mapped(&mut self) -> MemoryMapped<'_> {
+        if !self.mem_mapped {
+            let cmd = self.read_cmd(0);
+            self.qspi.enable_memory_mapped(&cmd);
+            self.mem_mapped = true;
+        }
+
+        let len = mapped_len(self.params.size, self.qspi.cfg.mem_size);
+        MemoryMapped { flash: self, len }
+    }
+}
+
+/// Returns `true` if `len` bytes starting at `addr` are within a memory of `size` bytes.
+fn in_range(size: u32, addr: u32, len: usize) -> bool {
+    match (addr as usize).checked_add(len) {
+        Some(end) => end <= size as usize,
+        None => false,
+    }
+}
+
+/// The number of bytes readable in memory-mapped mode: The flash's size, limited to the size
+/// the peripheral is configured for (`QspiConfig::mem_size`, ie FSIZE), and to the 256MB
+/// memory-mapped region. Accesses beyond FSIZE fault.
+fn mapped_len(flash_size: u32, mem_size: u32) -> usize {
+    let configured = 1_u64 << (fsize(mem_size) + 1);
+    (flash_size as u64).min(configured).min(0x1000_0000) as usize
+}
+
+/// Memory-mapped access to a `NorFlash`, returned by `NorFlash::memory_mapped`. Derefs to the
+/// memory contents.
+pub struct MemoryMapped<'a> {
+    flash: &'a mut NorFlash,
+    len: usize,
+}
+
+impl Deref for MemoryMapped<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // The peripheral stays in memory-mapped mode while we hold the driver's borrow, so
+        // the region is readable, and its contents don't change.
+        unsafe { core::slice::from_raw_parts(MEM_MAPPED_BASE_ADDR as *const u8, self.len) }
+    }
+}
+
+impl Drop for MemoryMapped<'_> {
+    fn drop(&mut self) {
+        self.flash.unmap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SFDP header and parameter header: one parameter header, for a 16-DWORD JESD216B BFPT at
+    /// 0x80.
+    const SFDP_HEADER: [u8; 16] = [
+        0x53, 0x46, 0x44, 0x50, 0x05, 0x01, 0x00, 0xff, 0x00, 0x05, 0x01, 0x10, 0x80, 0x00, 0x00,
+        0xff,
+    ];
+
+    /// BFPT read from a W25Q128JV.
+    const W25Q128JV_BFPT: [u8; 64] = [
+        0xe5, 0x20, 0xf9, 0xff, 0xff, 0xff, 0xff, 0x07, 0x44, 0xeb, 0x08, 0x6b, 0x08, 0x3b, 0x42,
+        0xbb, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0x40, 0xeb, 0x0c, 0x20,
+        0x0f, 0x52, 0x10, 0xd8, 0x00, 0x00, 0x36, 0x02, 0xa6, 0x00, 0x82, 0xea, 0x14, 0xc9, 0xe9,
+        0x63, 0x76, 0x33, 0x7a, 0x75, 0x7a, 0x75, 0xf7, 0xa2, 0xd5, 0x5c, 0x19, 0xf7, 0x4d, 0xff,
+        0xe9, 0x30, 0xf8, 0x80,
+    ];
+
+    /// The first `SFDP_READ_LEN` bytes of SFDP, as read by `NorFlash::new`.
+    fn w25q128jv_sfdp() -> [u8; SFDP_READ_LEN] {
+        let mut sfdp = [0xff; SFDP_READ_LEN];
+        sfdp[..16].copy_from_slice(&SFDP_HEADER);
+        sfdp[0x80..0xc0].copy_from_slice(&W25Q128JV_BFPT);
+        sfdp
+    }
+
+    fn set_dword(table: &mut [u8], n: usize, val: u32) {
+        table[(n - 1) * 4..n * 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    #[test]
+    fn w25q128jv() {
+        let params = FlashParams::from_sfdp(&w25q128jv_sfdp()).unwrap();
+
+        assert_eq!(
+            params,
+            FlashParams {
+                size: 16 * 1_024 * 1_024,
+                page_size: 256,
+                erase_types: [
+                    Some(EraseType {
+                        size: 4_096,
+                        instruction: 0x20,
+                    }),
+                    Some(EraseType {
+                        size: 32_768,
+                        instruction: 0x52,
+                    }),
+                    Some(EraseType {
+                        size: 65_536,
+                        instruction: 0xd8,
+                    }),
+                    None,
+                ],
+                quad_enable: QuadEnable::Sr2Bit1WriteSr1Sr2,
+                read_1_1_4: Some(ReadCmd {
+                    instruction: 0x6b,
+                    dummy_cycles: 8,
+                }),
+                four_byte_addr: false,
+            }
+        );
+    }
+
+    #[test]
+    fn truncated_sfdp() {
+        let sfdp = w25q128jv_sfdp();
+        for len in 0..0xc0 {
+            assert!(FlashParams::from_sfdp(&sfdp[..len]).is_err(), "len {}", len);
+        }
+        assert_eq!(
+            FlashParams::from_sfdp(&sfdp[..0x10]),
+            Err(Error::SfdpOutOfRange)
+        );
+        assert!(FlashParams::from_sfdp(&sfdp[..0xc0]).is_ok());
+
+        // More parameter headers listed than were read.
+        let mut sfdp = w25q128jv_sfdp();
+        sfdp[6] = 0xff;
+        sfdp[8] = 0x01;
+        assert_eq!(FlashParams::from_sfdp(&sfdp), Err(Error::SfdpOutOfRange));
+    }
+
+    #[test]
+    fn bad_sfdp_header() {
+        let mut sfdp = w25q128jv_sfdp();
+        sfdp[0] = 0;
+        assert_eq!(FlashParams::from_sfdp(&sfdp), Err(Error::SfdpSignature));
+
+        // The only parameter header isn't the BFPT.
+        let mut sfdp = w25q128jv_sfdp();
+        sfdp[8] = 0x81;
+        assert_eq!(FlashParams::from_sfdp(&sfdp), Err(Error::SfdpNoBasicTable));
+    }
+
+    #[test]
+    fn too_many_dummy_cycles() {
+        let mut bfpt = W25Q128JV_BFPT;
+        // 31 dummy clocks, and 7 mode clocks.
+        set_dword(&mut bfpt, 3, 0x6bff_eb44);
+        assert_eq!(FlashParams::from_bfpt(&bfpt).unwrap().read_1_1_4, None);
+
+        // 24 dummy clocks, and 7 mode clocks.
+        set_dword(&mut bfpt, 3, 0x6bf8_eb44);
+        assert_eq!(
+            FlashParams::from_bfpt(&bfpt).unwrap().read_1_1_4,
+            Some(ReadCmd {
+                instruction: 0x6b,
+                dummy_cycles: 31,
+            })
+        );
+    }
+
+    #[test]
+    fn large_memory() {
+        let mut bfpt = W25Q128JV_BFPT;
+        // 256Mbit, as bits - 1.
+        set_dword(&mut bfpt, 2, 0x0fff_ffff);
+        let params = FlashParams::from_bfpt(&bfpt).unwrap();
+        assert_eq!(params.size, 32 * 1_024 * 1_024);
+        assert!(params.four_byte_addr);
+
+        // 2^28 bits.
+        set_dword(&mut bfpt, 2, 0x8000_001c);
+        assert_eq!(
+            FlashParams::from_bfpt(&bfpt).unwrap().size,
+            32 * 1_024 * 1_024
+        );
+
+        // A 16MB memory that only supports 4-byte addresses.
+        let mut bfpt = W25Q128JV_BFPT;
+        set_dword(&mut bfpt, 1, 0xfffd_20e5);
+        assert!(FlashParams::from_bfpt(&bfpt).unwrap().four_byte_addr);
+    }
+
+    #[test]
+    fn jesd216_basic() {
+        // A 9-DWORD table, from before JESD216A: Page size and QER use defaults.
+        let params = FlashParams::from_bfpt(&W25Q128JV_BFPT[..36]).unwrap();
+        assert_eq!(params.page_size, 256);
+        assert_eq!(params.quad_enable, QuadEnable::None);
+        assert_eq!(params.erase_types[2].unwrap().size, 65_536);
+
+        // Without erase types listed, the 4KB erase from DWORD 1 is used.
+        let params = FlashParams::from_bfpt(&W25Q128JV_BFPT[..8]).unwrap();
+        assert_eq!(
+            params.erase_types,
+            [
+                Some(EraseType {
+                    size: 4_096,
+                    instruction: 0x20,
+                }),
+                None,
+                None,
+                None,
+            ]
+        );
+        assert_eq!(params.read_1_1_4, None);
+
+        assert_eq!(
+            FlashParams::from_bfpt(&W25Q128JV_BFPT[..7]),
+            Err(Error::SfdpOutOfRange)
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        let size = 16 * 1_024 * 1_024;
+        assert!(in_range(size, 0, 256));
+        assert!(in_range(size, size - 256, 256));
+        assert!(!in_range(size, size - 255, 256));
+        // Erases check the single byte at the address.
+        assert!(in_range(size, size - 1, 1));
+        assert!(!in_range(size, size, 1));
+        assert!(!in_range(size, u32::MAX, 1));
+    }
+
+    #[test]
+    fn memory_mapped_len() {
+        // 16MB flash, with the peripheral configured for 16MB.
+        assert_eq!(mapped_len(0x100_0000, 16), 0x100_0000);
+        // The peripheral is configured smaller than the flash.
+        assert_eq!(mapped_len(0x100_0000, 8), 0x80_0000);
+        // `mem_size` is rounded down to a power of two.
+        assert_eq!(mapped_len(0x100_0000, 12), 0x80_0000);
+        // The configured size is larger than the flash.
+        assert_eq!(mapped_len(0x80_0000, 64), 0x80_0000);
+        // Both are larger than the 256MB memory-mapped region.
+        assert_eq!(mapped_len(0x8000_0000, 4_096), 0x1000_0000);
+    }
+}
@@ -234,6 +234,66 @@ impl Rtc {
         result
     }
 
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    /// Enable the LSE clock security system (CSS), which detects failure of the LSE. Only valid
+    /// if the LSE is the RTC clock source, and is running. If `interrupt` is true, an RCC interrupt
+    /// is fired on failure, for wakeup from low power modes. (On G0, which has no LSE CSS interrupt
+    /// enable in RCC, use the TAMP interrupt instead)
+    pub fn enable_lse_css(&mut self, interrupt: bool) {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // "LSECSSON must be enabled after the LSE oscillator is enabled (LSEON bit enabled) and
+        // ready (LSERDY flag set by hardware), and after the RTC clock has been selected by
+        // RTCSEL."
+        rcc.bdcr.modify(|_, w| w.lsecsson().set_bit());
+
+        #[cfg(not(feature = "g0"))]
+        rcc.cier.modify(|_, w| w.lsecssie().bit(interrupt));
+        #[cfg(feature = "g0")]
+        let _ = interrupt;
+    }
+
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    /// Check if the LSE CSS has detected an LSE failure.
+    pub fn lse_failure_detected(&self) -> bool {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        #[cfg(feature = "wb")]
+        return rcc.bdcr.read().lsecssd_().bit_is_set();
+        #[cfg(not(feature = "wb"))]
+        return rcc.bdcr.read().lsecssd().bit_is_set();
+    }
+
+    #[cfg(not(any(feature = "f3", feature = "f4")))]
+    /// Handle an LSE failure detected by the LSE CSS: Reset the backup domain, and re-initialize
+    /// the RTC on the LSI, with the existing prescaler settings. Note that this resets the RTC
+    /// date, time, and backup registers; set the time again after running this.
+    pub fn handle_lse_failure(self) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        // "Once the LSE failure is detected, the LSE oscillator must be disabled... [and] in order
+        // to use the RTC again with another clock, a Backup domain reset must be performed, and
+        // LSECSSON must be cleared by software."
+        free(|_| {
+            rcc.bdcr.modify(|_, w| {
+                w.lsecsson().clear_bit();
+                w.lseon().clear_bit()
+            });
+
+            rcc.cicr.write(|w| w.lsecssc().set_bit());
+
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+        });
+
+        let config = RtcConfig {
+            clock_source: RtcClockSource::Lsi,
+            ..self.config
+        };
+
+        Self::new(self.regs, config)
+    }
+
     /// Sets calendar clock to 24 hr format
     pub fn set_24h_fmt(&mut self) {
         self.edit_regs(true, |regs| regs.cr.modify(|_, w| w.fmt().clear_bit()));
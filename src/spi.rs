@@ -10,8 +10,10 @@ use cortex_m::interrupt::free;
 use embedded_hal::spi::FullDuplex;
 
 use crate::{
+    clocks::Clocks,
+    gpio::typed::{MisoPin, MosiPin, SckPin},
     pac::{self, RCC},
-    util::RccPeriph,
+    util::{BaudPeriph, RccPeriph},
 };
 
 use cfg_if::cfg_if;
@@ -40,6 +42,11 @@ pub enum Error {
     ModeFault,
     /// CRC error
     Crc,
+    /// Underrun occurred; in slave mode, the master clocked out more data than was loaded
+    /// for transmission.
+    Underrun,
+    /// TI frame format error; in TI slave mode, NSS pulsed during a data frame.
+    FrameFormat,
 }
 
 /// Possible interrupt types. Enable these in SPIx_CR2. Check and clear with SR. There is no explicit
@@ -97,7 +104,50 @@ pub enum BaudRate {
     Div256 = 0b111,
 }
 
+impl BaudRate {
+    /// The division factor this setting applies to the SPI source clock.
+    pub fn value(&self) -> u32 {
+        2 << (*self as u8)
+    }
+
+    /// Select the smallest division factor (ie the highest SCK frequency) that doesn't exceed
+    /// `freq`, given a source clock of `src_freq`. Both are in Hz. If `freq` is below what
+    /// `Div256` produces, `Div256` is returned.
+    pub fn from_freq(src_freq: u32, freq: u32) -> Self {
+        for br in [
+            Self::Div2,
+            Self::Div4,
+            Self::Div8,
+            Self::Div16,
+            Self::Div32,
+            Self::Div64,
+            Self::Div128,
+        ] {
+            // Compare without dividing, so a divider that doesn't divide `src_freq` evenly
+            // isn't rounded down into producing a slightly-too-fast SCK.
+            if src_freq as u64 <= freq as u64 * br.value() as u64 {
+                return br;
+            }
+        }
+        Self::Div256
+    }
+
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b111 {
+            0b000 => Self::Div2,
+            0b001 => Self::Div4,
+            0b010 => Self::Div8,
+            0b011 => Self::Div16,
+            0b100 => Self::Div32,
+            0b101 => Self::Div64,
+            0b110 => Self::Div128,
+            _ => Self::Div256,
+        }
+    }
+}
+
 /// These bits configure the data length for SPI transfers. Sets `SPI_CR2` register, `DS` field.
+/// (On F4, only 8 and 16 bits are supported, using the `SPI_CR1` register, `DFF` field)
 #[cfg(not(feature = "h7"))]
 #[derive(Copy, Clone)]
 #[repr(u8)]
@@ -153,6 +203,43 @@ pub enum DataSize {
     D32 = 31,
 }
 
+impl DataSize {
+    /// The number of bits in a data frame.
+    pub fn bits(&self) -> u8 {
+        *self as u8 + 1
+    }
+}
+
+/// A word type that can be sent and received over SPI: `u8` for frames up to 8 bits, `u16` for
+/// frames up to 16 bits, and (H7 only) `u32` for frames up to 32 bits. This sets the width of
+/// data register accesses, and of DMA transfers. Buffers of untyped integer literals default to
+/// `i32`, so give them a type, eg `spi.write(&[0x80_u8, 100])`.
+pub trait SpiWord: Copy {
+    /// The number of bits in this word type.
+    const BITS: u8;
+    /// The size to use for both the peripheral and memory sides of a DMA transfer.
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    const DMA_SIZE: dma::DataSize;
+}
+
+impl SpiWord for u8 {
+    const BITS: u8 = 8;
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    const DMA_SIZE: dma::DataSize = dma::DataSize::S8;
+}
+
+impl SpiWord for u16 {
+    const BITS: u8 = 16;
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    const DMA_SIZE: dma::DataSize = dma::DataSize::S16;
+}
+
+#[cfg(feature = "h7")]
+impl SpiWord for u32 {
+    const BITS: u8 = 32;
+    const DMA_SIZE: dma::DataSize = dma::DataSize::S32;
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 /// FIFO reception threshold Sets `SPI_CR2` register, `FRXTH` field.
@@ -175,6 +262,26 @@ pub enum SpiCommMode {
     ReceiveOnly,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+/// Frame format. Sets CR2 register, FRF field. (CFG2 register, SP field on H7)
+pub enum FrameFormat {
+    /// Motorola frame format; the usual SPI protocol.
+    Motorola,
+    /// TI synchronous serial frame format. NSS is pulsed by the master for 1 clock period
+    /// before each frame, and clock polarity and phase settings are ignored.
+    Ti,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Whether this device generates the clock (master), or responds to one (slave). Sets CR1
+/// register, MSTR field. (CFG2 register, MASTER field on H7)
+pub enum SpiRole {
+    Master,
+    /// In slave mode, use `SlaveSelect::HardwareOutDisable` to frame transactions with the NSS pin,
+    /// or `SlaveSelect::Software` to treat the slave as always selected.
+    Slave,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 /// Used for managing NSS / CS pin. Sets CR1 register, SSM field.
 pub enum SlaveSelect {
@@ -269,9 +376,12 @@ cfg_if! {
 }
 
 /// Configuration data for SPI.
+#[derive(Clone)]
 pub struct SpiConfig {
     /// SPI mode associated with Polarity and Phase. Defaults to Mode0: Idle low, capture on first transition.
     pub mode: SpiModeType,
+    /// Master or slave operation. Defaults to master.
+    pub role: SpiRole,
     /// Sets the (duplex) communication mode between the devices. Defaults to full duplex.
     pub comm_mode: SpiCommMode,
     /// Controls use of hardware vs software CS/NSS pin. Defaults to software.
@@ -280,8 +390,25 @@ pub struct SpiConfig {
     pub data_size: DataSize,
     /// FIFO reception threshhold. Defaults to 8 bits.
     pub fifo_reception_thresh: ReceptionThresh,
+    /// Motorola or TI frame format. Defaults to Motorola.
+    pub frame_format: FrameFormat,
+    /// Enables hardware CRC calculation with this polynomial, if set. The CRC length matches the data
+    /// size; 8 or 16 bits. (32 bits max on H7) A CRC frame is sent after each transfer, and the
+    /// received one is checked; a mismatch is reported as `Error::Crc`. Defaults to `None`.
+    pub crc_poly: Option<u32>,
+    #[cfg(feature = "h7")]
+    /// Master SS idleness: The number of clock cycles between NSS assertion and the start of the
+    /// first data frame. 0 - 15. (CFG2 register, MSSI field) Defaults to 0.
+    pub ss_idleness: u8,
+    #[cfg(feature = "h7")]
+    /// Master inter-data idleness: The number of clock cycles between data frames; eg to give
+    /// slow ADCs time to convert. 0 - 15. (CFG2 register, MIDI field) Defaults to 0.
+    pub interdata_idleness: u8,
+    #[cfg(feature = "h7")]
+    /// Swap the functionality of the MISO and MOSI pins. (CFG2 register, IOSWP field)
+    /// Defaults to false.
+    pub swap_miso_mosi: bool,
     // pub cs_delay: f32,
-    // pub swap_miso_mosi: bool,
     // pub suspend_when_inactive: bool,
 }
 
@@ -297,10 +424,19 @@ impl Default for SpiConfig {
 
         Self {
             mode: mode0,
+            role: SpiRole::Master,
             comm_mode: SpiCommMode::FullDuplex,
             slave_select: SlaveSelect::Software,
             data_size: DataSize::D8,
             fifo_reception_thresh: ReceptionThresh::D8,
+            frame_format: FrameFormat::Motorola,
+            crc_poly: None,
+            #[cfg(feature = "h7")]
+            ss_idleness: 0,
+            #[cfg(feature = "h7")]
+            interdata_idleness: 0,
+            #[cfg(feature = "h7")]
+            swap_miso_mosi: false,
         }
     }
 }
@@ -323,19 +459,57 @@ where
             R::en_reset(rcc);
         });
 
+        let mut result = Spi { regs, cfg };
+        result.configure(baud_rate);
+        result
+    }
+
+    /// Apply a new configuration and baud rate, without resetting the peripheral; eg to switch
+    /// between devices on a shared bus. Don't call this while a transfer is in progress. The
+    /// peripheral is disabled while its configuration registers are written, then re-enabled;
+    /// on H7, this waits for the last frame to finish transmitting first.
+    pub fn reconfigure(&mut self, cfg: SpiConfig, baud_rate: BaudRate) {
+        self.cfg = cfg;
+        self.configure(baud_rate);
+    }
+
+    /// Write the configuration registers from `self.cfg`, and enable the peripheral.
+    fn configure(&mut self, baud_rate: BaudRate) {
+        let regs = &self.regs;
+        let cfg = &self.cfg;
+
         cfg_if! {
             if #[cfg(feature = "h7")] {
-                  // Disable SS output
+                // CFG1 and CFG2 are write-protected while SPE is set, eg when called from
+                // `reconfigure`. Let the last frame go out (TXC), then disable the peripheral.
+                if regs.cr1.read().spe().bit_is_set() {
+                    while regs.sr.read().txc().bit_is_clear() {}
+                    regs.cr1.modify(|_, w| w.spe().clear_bit());
+                }
+
+                // Disable SS output
                 regs.cfg2.write(|w| w.ssoe().disabled());
 
+                assert!(cfg.ss_idleness <= 15 && cfg.interdata_idleness <= 15);
+
                 regs.cfg1.modify(|_, w| {
                     w.mbr().bits(baud_rate as u8);
-                    w.dsize().bits(cfg.data_size as u8)
-
+                    w.dsize().bits(cfg.data_size as u8);
+                    // "CRCSIZE: Length of CRC frame to be transacted and compared. Most significant
+                    // bits are taken into account from polynomial calculation when CRC result is
+                    // transacted or compared. The length must be the same as the data size."
+                    w.crcsize().bits(cfg.data_size as u8);
+                    w.crcen().bit(cfg.crc_poly.is_some())
                 });
 
-                // ssi: select slave = master mode
-                regs.cr1.write(|w| w.ssi().slave_not_selected());
+                if let Some(poly) = cfg.crc_poly {
+                    regs.crcpoly.write(|w| w.crcpoly().bits(poly));
+                }
+
+                // SSI sets the internal SS level when SSM is set: High (not selected) for a master, to
+                // avoid a mode fault, and low (selected) for a slave.
+                let ssi = cfg.role == SpiRole::Master;
+                regs.cr1.write(|w| w.ssi().bit(ssi));
 
                 // todo: Data size on H7.
 
@@ -369,22 +543,24 @@ where
                 // lsbfrst: MSB first
                 // comm: full-duplex
                 // todo: Flesh this out.
-                regs.cfg2.write(|w| {
+                regs.cfg2.write(|w| unsafe {
                     w.cpha().bit(cfg.mode.phase as u8 != 0);
                         w.cpol().bit(cfg.mode.polarity as u8 != 0);
-                        w.master().master();
+                        w.master().bit(cfg.role == SpiRole::Master);
+                        // SP: 000: Motorola, 001: TI.
+                        w.sp().bits((cfg.frame_format == FrameFormat::Ti) as u8);
+                        w.mssi().bits(cfg.ss_idleness);
+                        w.midi().bits(cfg.interdata_idleness);
+                        w.ioswp().bit(cfg.swap_miso_mosi);
+                        w.ssm().bit(cfg.slave_select == SlaveSelect::Software);
+                        w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable);
                         w.lsbfrst().msbfirst()
                         // w.ssom().bit(config.suspend_when_inactive);
-                        // w.ssm().bit(config.managed_cs == false);
-                        // w.ssoe().bit(config.managed_cs == true);
-                        // w.mssi().bits(start_cycle_delay);
-                        // w.midi().bits(interdata_cycle_delay);
-                        // w.ioswp().bit(config.swap_miso_mosi == true)
                         // w.comm().variant(communication_mode);
                 });
 
                 // spe: enable the SPI bus
-                regs.cr1.write(|w| w.ssi().slave_not_selected().spe().enabled());
+                regs.cr1.write(|w| w.ssi().bit(ssi).spe().enabled());
             } else {
                 // L44 RM, section 40.4.7: Configuration of SPI
                 // The configuration procedure is almost the same for master and slave. For specific mode
@@ -394,6 +570,17 @@ where
                 // 1. Write proper GPIO registers: Configure GPIO for MOSI, MISO and SCK pins.
                 // (Handled in GPIO modules and user code)
 
+                // Disable the peripheral while configuring it, in case we're re-configuring.
+                regs.cr1.modify(|_, w| w.spe().clear_bit());
+
+                // F4 only supports 8 and 16-bit frames, selected with CR1's DFF bit instead of
+                // CR2's DS field.
+                #[cfg(feature = "f4")]
+                assert!(
+                    matches!(cfg.data_size, DataSize::D8 | DataSize::D16),
+                    "SPI data size must be 8 or 16 bits on F4."
+                );
+
                 // 2. Write to the SPI_CR1 register:
                 regs.cr1.modify(|_, w| unsafe {
                     // a) Configure the serial clock baud rate using the BR[2:0] bits (Note: 4)
@@ -412,19 +599,38 @@ where
                     w.lsbfirst().clear_bit();
                     // e) Configure the CRCL and CRCEN bits if CRC is needed (while SCK clock signal is
                     // at idle state).
-                    w.crcen().clear_bit();
+                    // (CRCL is set below, since the PACs are inconsistent on its name)
+                    w.crcen().bit(cfg.crc_poly.is_some());
+                    // DFF: 0: 8-bit data frame format, 1: 16-bit.
+                    #[cfg(feature = "f4")]
+                    w.dff().bit(cfg.data_size.bits() == 16);
                     // f) Configure SSM and SSI (Notes: 2 & 3).
                     w.ssm().bit(cfg.slave_select == SlaveSelect::Software);
-                    w.ssi().set_bit(); // todo?
+                    // With software slave management, SSI=0 selects a slave.
+                    w.ssi().bit(cfg.role == SpiRole::Master);
                     // g) Configure the MSTR bit (in multimaster NSS configuration, avoid conflict state on
                     // NSS if master is configured to prevent MODF error).
-                    w.mstr().set_bit();
+                    w.mstr().bit(cfg.role == SpiRole::Master);
                     w.spe().set_bit() // Enable SPI
                 });
 
+                // CRCL: 0: 8-bit CRC length, 1: 16-bit CRC length. (On F4, CRC length follows the data
+                // frame format)
+                #[cfg(not(feature = "f4"))]
+                {
+                    let crcl = (cfg.crc_poly.is_some() && cfg.data_size.bits() > 8) as u32;
+                    regs.cr1.modify(|_, w| w.spe().clear_bit());
+                    regs.cr1
+                        .modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 11)) | (crcl << 11)) });
+                    regs.cr1.modify(|_, w| w.spe().set_bit());
+                }
+
                 // 3. Write to SPI_CR2 register:
                 #[cfg(feature = "f4")]
-                regs.cr2.modify(|_, w| w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable));
+                regs.cr2.modify(|_, w| {
+                    w.frf().bit(cfg.frame_format == FrameFormat::Ti);
+                    w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable)
+                });
 
                 #[cfg(not(feature = "f4"))]
                 regs.cr2
@@ -433,17 +639,21 @@ where
                         w.ds().bits(cfg.data_size as u8);
                         // b) Configure SSOE (Notes: 1 & 2 & 3).
                         w.ssoe().bit(cfg.slave_select == SlaveSelect::HardwareOutEnable);
+                        // c) Set the FRF bit if the TI protocol is required (keep NSSP bit cleared in TI mode).
+                        w.frf().bit(cfg.frame_format == FrameFormat::Ti);
                         // e) Configure the FRXTH bit. The RXFIFO threshold must be aligned to the read
                         // access size for the SPIx_DR register.
                         w.frxth().bit(cfg.fifo_reception_thresh as u8 != 0)
                     });
 
-                // c) Set the FRF bit if the TI protocol is required (keep NSSP bit cleared in TI mode).
                 // d) Set the NSSP bit if the NSS pulse mode between two data units is required (keep
                 // CHPA and TI bits cleared in NSSP mode).
 
                 // f) Initialize LDMA_TX and LDMA_RX bits if DMA is used in packed mode.
                 // 4. Write to SPI_CRCPR register: Configure the CRC polynomial if needed.
+                if let Some(poly) = cfg.crc_poly {
+                    regs.crcpr.write(|w| unsafe { w.crcpoly().bits(poly as u16) });
+                }
                 // 5. Write proper DMA registers: Configure DMA streams dedicated for SPI Tx and Rx in
                 // DMA registers if the DMA streams are used.
             }
@@ -452,7 +662,38 @@ where
             // todo: This lets you use hardware CS management, and seems to be teh way the RM
             // todo steers you towards regardless.
         }
-        Spi { regs, cfg }
+    }
+
+    /// Similar to `new`, but also takes the SCK, MISO and MOSI pins, configured with their
+    /// alternate function. Pins or alternate functions that don't belong to this peripheral fail
+    /// to compile. See the `gpio::typed` module.
+    pub fn new_with_pins<SCK: SckPin<R>, MISO: MisoPin<R>, MOSI: MosiPin<R>>(
+        regs: R,
+        _sck: SCK,
+        _miso: MISO,
+        _mosi: MOSI,
+        cfg: SpiConfig,
+        baud_rate: BaudRate,
+    ) -> Self {
+        Self::new(regs, cfg, baud_rate)
+    }
+
+    /// Check that the word type used for a read or write is the smallest one that holds the
+    /// configured data size; eg `u16` for 12-bit frames.
+    fn check_word_size<W: SpiWord>(&self) {
+        let frame_bits = self.cfg.data_size.bits();
+        let required = if frame_bits <= 8 {
+            8
+        } else if frame_bits <= 16 {
+            16
+        } else {
+            32
+        };
+
+        assert!(
+            W::BITS == required,
+            "SPI word type doesn't match the configured data size."
+        );
     }
 
     /// Change the SPI baud rate.
@@ -514,117 +755,192 @@ where
         }
     }
 
-    /// Read a single byte if available, or block until it's available.
-    /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
-    pub fn read(&mut self) -> Result<u8, Error> {
+    /// Check the status register for overrun, mode fault, CRC and TI frame format errors.
+    fn check_errors(&self) -> Result<(), Error> {
         let sr = self.regs.sr.read();
 
         cfg_if! {
             if #[cfg(feature = "h7")] {
                 let crce = sr.crce().bit_is_set();
+                let fre = sr.tifre().bit_is_set();
             } else {
                 let crce = sr.crcerr().bit_is_set();
+                // `FRE`, bit 8. Named inconsistently across PACs (`fre`, `tifrfe`).
+                let fre = sr.bits() & (1 << 8) != 0;
             }
         }
 
         if sr.ovr().bit_is_set() {
-            return Err(Error::Overrun);
+            Err(Error::Overrun)
         } else if sr.modf().bit_is_set() {
-            return Err(Error::ModeFault);
+            Err(Error::ModeFault)
         } else if crce {
-            return Err(Error::Crc);
+            Err(Error::Crc)
+        } else if fre {
+            Err(Error::FrameFormat)
+        } else {
+            Ok(())
         }
+    }
 
+    /// Clear the CRC error flag, and reset the CRC calculation. Run this after a `Error::Crc`, or
+    /// before starting a new CRC-checked transfer. (CRCERR flag; H7: IFCR register, CRCEC field)
+    pub fn reset_crc(&mut self) {
         cfg_if! {
             if #[cfg(feature = "h7")] {
-                while !self.regs.sr.read().rxp().bit_is_set() {}
-                // todo: note: H7 can support words beyond u8. (Can others too?)
-                Ok(unsafe{ ptr::read_volatile(&self.regs.rxdr as *const _ as *const u8) })
+                self.regs.ifcr.write(|w| w.crcec().set_bit());
+                // The CRC calculation is reset each time SPE is set.
+                self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+                self.regs.cr1.modify(|_, w| w.spe().set_bit());
             } else {
-                while !self.regs.sr.read().rxne().bit_is_set() {}
-                Ok(unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) })
+                // "CRCERR: This flag is set by hardware and cleared by software writing 0."
+                self.regs.sr.modify(|_, w| w.crcerr().clear_bit());
+                // "To clear the CRC, clear the CRCEN bit" [while SPE=0]
+                self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+                self.regs.cr1.modify(|_, w| w.crcen().clear_bit());
+                self.regs.cr1.modify(|_, w| w.crcen().bit(self.cfg.crc_poly.is_some()));
+                self.regs.cr1.modify(|_, w| w.spe().set_bit());
             }
         }
     }
 
-    /// Write a single byte if available, or block until it's available.
+    /// In blocking transfers with CRC enabled, send the CRC frame after the last word, and read and
+    /// check the one received. On H7, this is handled by hardware at the end of a transfer sized with
+    /// `set_transfer_size`.
+    fn finish_crc<W: SpiWord>(&mut self) -> Result<(), Error> {
+        #[cfg(not(feature = "h7"))]
+        if self.cfg.crc_poly.is_some() {
+            // Read (and discard) the received CRC frame; the hardware compares it with its own.
+            while !self.regs.sr.read().rxne().bit_is_set() {}
+            unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const W) };
+            while self.regs.sr.read().bsy().bit_is_set() {}
+        }
+
+        self.check_errors()
+    }
+
+    /// Set CRCNEXT after writing the last data word, if CRC is enabled, so the CRC is sent next.
+    fn crc_next(&mut self) {
+        #[cfg(not(feature = "h7"))]
+        if self.cfg.crc_poly.is_some() {
+            self.regs.cr1.modify(|_, w| w.crcnext().set_bit());
+        }
+    }
+
+    #[cfg(feature = "h7")]
+    /// Set the number of data frames in the next transfer. (CR2 register, TSIZE field) Once this
+    /// many frames are sent, the EOT flag is set, (`SpiInterrupt::EotSuspTxc`) and if CRC is
+    /// enabled, the CRC is sent and checked. 0 means the transfer is unbounded.
+    pub fn set_transfer_size(&mut self, size: u16) {
+        // "TSIZE... When these bits are changed by software, the SPI has to be disabled."
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.regs.cr2.modify(|_, w| w.tsize().bits(size));
+        self.regs.cr1.modify(|_, w| w.spe().set_bit());
+    }
+
+    /// Read a single word if available, or block until it's available. The word type must match
+    /// the configured data size; see `SpiWord`.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
-    pub fn write_one(&mut self, byte: u8) -> Result<(), Error> {
-        let sr = self.regs.sr.read();
+    pub fn read<W: SpiWord>(&mut self) -> Result<W, Error> {
+        self.check_word_size::<W>();
+
+        self.check_errors()?;
 
         cfg_if! {
             if #[cfg(feature = "h7")] {
-                let crce = sr.crce().bit_is_set();
+                while !self.regs.sr.read().rxp().bit_is_set() {}
+                // The access width must match the word size, so the FIFO doesn't pack or split frames.
+                Ok(unsafe{ ptr::read_volatile(&self.regs.rxdr as *const _ as *const W) })
             } else {
-                let crce = sr.crcerr().bit_is_set();
+                while !self.regs.sr.read().rxne().bit_is_set() {}
+                // The access width must match the word size, so the FIFO doesn't pack or split frames.
+                Ok(unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const W) })
             }
         }
+    }
 
-        if sr.ovr().bit_is_set() {
-            return Err(Error::Overrun);
-        } else if sr.modf().bit_is_set() {
-            return Err(Error::ModeFault);
-        } else if crce {
-            return Err(Error::Crc);
-        }
+    /// Write a single word if available, or block until it's available. The word type must match
+    /// the configured data size; see `SpiWord`.
+    /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
+    pub fn write_one<W: SpiWord>(&mut self, word: W) -> Result<(), Error> {
+        self.check_word_size::<W>();
+
+        self.check_errors()?;
 
         cfg_if! {
             if #[cfg(feature = "h7")] {
                 while !self.regs.sr.read().txp().bit_is_set() {}
-                // todo: note: H7 can support words beyond u8. (Can others too?)
-                unsafe { ptr::write_volatile(&self.regs.txdr as *const _ as *mut u8, byte) };
+                unsafe { ptr::write_volatile(&self.regs.txdr as *const _ as *mut W, word) };
                 // write CSTART to start a transaction in master mode
                 self.regs.cr1.modify(|_, w| w.cstart().started());
             }
              else {
                 while !self.regs.sr.read().txe().bit_is_set() {}
-                unsafe { ptr::write_volatile(&self.regs.dr as *const _ as *mut u8, byte) };
+                unsafe { ptr::write_volatile(&self.regs.dr as *const _ as *mut W, word) };
             }
         }
 
         Ok(())
     }
 
-    /// Write multiple bytes on the SPI line, blocking until complete.
+    /// Write multiple words on the SPI line, blocking until complete. An empty buffer does nothing.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
-    pub fn write(&mut self, words: &[u8]) -> Result<(), Error> {
-        for word in words {
+    pub fn write<W: SpiWord>(&mut self, words: &[W]) -> Result<(), Error> {
+        // With CRC enabled, no CRC frame would be sent; don't wait for one.
+        if words.is_empty() {
+            return Ok(());
+        }
+
+        for (i, word) in words.iter().enumerate() {
             self.write_one(*word)?;
-            self.read()?;
+            if i == words.len() - 1 {
+                self.crc_next();
+            }
+            self.read::<W>()?;
         }
 
-        Ok(())
+        self.finish_crc::<W>()
     }
 
-    /// Read multiple bytes to a buffer, blocking until complete.
+    /// Read multiple words to a buffer, blocking until complete. An empty buffer does nothing.
     /// See L44 RM, section 40.4.9: Data transmission and reception procedures.
-    pub fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<(), Error> {
-        for word in words.iter_mut() {
+    pub fn transfer<'w, W: SpiWord>(&mut self, words: &'w mut [W]) -> Result<(), Error> {
+        let len = words.len();
+        if len == 0 {
+            return Ok(());
+        }
+
+        for (i, word) in words.iter_mut().enumerate() {
             self.write_one(*word)?;
+            if i == len - 1 {
+                self.crc_next();
+            }
             *word = self.read()?;
         }
 
-        Ok(())
+        self.finish_crc::<W>()
     }
 
     /// Transmit data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
     /// Note that the `channel` argument has no effect on F3 and L4.
     #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn write_dma<D>(
+    pub unsafe fn write_dma<W, D>(
         &mut self,
-        buf: &[u8],
+        buf: &[W],
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
     ) where
+        W: SpiWord,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
+        self.check_word_size::<W>();
+
         // Static write and read buffers?
         let (ptr, len) = (buf.as_ptr(), buf.len());
 
         self.regs.cr1.modify(|_, w| w.spe().clear_bit());
 
-        // todo: Accept u16 words too.
         // todo: Pri and Circular as args?
 
         // A DMA access is requested when the TXE or RXNE enable bit in the SPIx_CR2 register is
@@ -661,8 +977,8 @@ where
             ptr as u32,
             num_data,
             dma::Direction::ReadFromMem,
-            dma::DataSize::S8,
-            dma::DataSize::S8,
+            W::DMA_SIZE,
+            W::DMA_SIZE,
             channel_cfg,
         );
 
@@ -679,16 +995,18 @@ where
     /// Receive data using DMA. See L44 RM, section 40.4.9: Communication using DMA.
     /// Note thay the `channel` argument has no effect on F3 and L4.
     #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn read_dma<D>(
+    pub unsafe fn read_dma<W, D>(
         &mut self,
-        buf: &mut [u8],
+        buf: &mut [W],
         channel: DmaChannel,
         channel_cfg: ChannelCfg,
         dma: &mut Dma<D>,
     ) where
+        W: SpiWord,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        // todo: Accept u16 words too.
+        self.check_word_size::<W>();
+
         let (ptr, len) = (buf.as_mut_ptr(), buf.len());
 
         self.regs.cr1.modify(|_, w| w.spe().clear_bit());
@@ -719,8 +1037,8 @@ where
             ptr as u32,
             num_data,
             dma::Direction::ReadFromPeriph,
-            dma::DataSize::S8,
-            dma::DataSize::S8,
+            W::DMA_SIZE,
+            W::DMA_SIZE,
             channel_cfg,
         );
 
@@ -730,19 +1048,21 @@ where
     /// Transfer data from DMA; this is the basic reading API, using both write and read transfers:
     /// It performs a write with register data, and reads to a buffer.
     #[cfg(not(any(feature = "f4", feature = "l552")))]
-    pub unsafe fn transfer_dma<D>(
+    pub unsafe fn transfer_dma<W, D>(
         &mut self,
-        buf_write: &[u8],
-        buf_read: &mut [u8],
+        buf_write: &[W],
+        buf_read: &mut [W],
         channel_write: DmaChannel,
         channel_read: DmaChannel,
         channel_cfg_write: ChannelCfg,
         channel_cfg_read: ChannelCfg,
         dma: &mut Dma<D>,
     ) where
+        W: SpiWord,
         D: Deref<Target = dma_p::RegisterBlock>,
     {
-        // todo: Accept u16 words too.
+        self.check_word_size::<W>();
+
         let (ptr_write, len_write) = (buf_write.as_ptr(), buf_write.len());
         let (ptr_read, len_read) = (buf_read.as_mut_ptr(), buf_read.len());
 
@@ -788,8 +1108,8 @@ where
             ptr_write as u32,
             num_data_write,
             dma::Direction::ReadFromMem,
-            dma::DataSize::S8,
-            dma::DataSize::S8,
+            W::DMA_SIZE,
+            W::DMA_SIZE,
             channel_cfg_write,
         );
 
@@ -804,8 +1124,8 @@ where
             ptr_read as u32,
             num_data_read,
             dma::Direction::ReadFromPeriph,
-            dma::DataSize::S8,
-            dma::DataSize::S8,
+            W::DMA_SIZE,
+            W::DMA_SIZE,
             channel_cfg_read,
         );
 
@@ -817,6 +1137,133 @@ where
         self.regs.cr1.modify(|_, w| w.spe().set_bit());
     }
 
+    /// Slave mode: Prepare a transaction, by loading `buf_write` for transmission, and setting up
+    /// reception into `buf_read`. The transfer starts when the master selects this device, and
+    /// clocks data. When the master releases NSS, call `slave_transaction_complete`. Detect this
+    /// with an EXTI interrupt on the NSS pin's rising edge, or, on H7, with the
+    /// `SpiInterrupt::EotSuspTxc` interrupt, which fires once `buf_read.len()` words are received.
+    /// On H7, `buf_read` can be at most 65535 words long.
+    /// Note that the `channel` arguments have no effect on F3 and L4.
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    pub unsafe fn slave_transfer_dma<W, D>(
+        &mut self,
+        buf_write: &[W],
+        buf_read: &mut [W],
+        channel_write: DmaChannel,
+        channel_read: DmaChannel,
+        channel_cfg_write: ChannelCfg,
+        channel_cfg_read: ChannelCfg,
+        dma: &mut Dma<D>,
+    ) where
+        W: SpiWord,
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        assert!(
+            self.cfg.role == SpiRole::Slave,
+            "`slave_transfer_dma` requires slave mode."
+        );
+
+        // Clear errors left over from the previous transaction.
+        self.clear_errors();
+
+        // "EOT: This bit is set by hardware as soon as a full transfer is completed" (TSIZE data).
+        #[cfg(feature = "h7")]
+        {
+            // TSIZE is 16 bits wide.
+            assert!(
+                buf_read.len() <= u16::MAX as usize,
+                "`buf_read` must be at most 65535 words long."
+            );
+            self.set_transfer_size(buf_read.len() as u16);
+        }
+
+        self.transfer_dma(
+            buf_write,
+            buf_read,
+            channel_write,
+            channel_read,
+            channel_cfg_write,
+            channel_cfg_read,
+            dma,
+        );
+    }
+
+    /// Slave mode: End a transaction started with `slave_transfer_dma`. Call this when the master
+    /// releases NSS. Stops DMA, and returns the number of words received. Returns an error if data
+    /// was lost to an overrun, or if the master clocked more data than `len_write` (the length of
+    /// the buffer loaded for transmission).
+    /// Note that the `channel` arguments have no effect on F3 and L4.
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    pub fn slave_transaction_complete<D>(
+        &mut self,
+        len_write: usize,
+        len_read: usize,
+        _channel_write: DmaChannel,
+        _channel_read: DmaChannel,
+        dma: &mut Dma<D>,
+    ) -> Result<usize, Error>
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        #[cfg(any(feature = "f3", feature = "l4"))]
+        let (channel_write, channel_read) = (R::write_chan(), R::read_chan());
+        #[cfg(not(any(feature = "f3", feature = "l4")))]
+        let (channel_write, channel_read) = (_channel_write, _channel_read);
+
+        let received = len_read - dma.num_data_remaining(channel_read) as usize;
+
+        self.stop_dma(channel_write, Some(channel_read), dma);
+
+        let sr = self.regs.sr.read();
+        let overrun = sr.ovr().bit_is_set();
+        #[cfg(feature = "h7")]
+        let underrun = sr.udr().bit_is_set() || received > len_write;
+        #[cfg(not(feature = "h7"))]
+        let underrun = received > len_write;
+
+        // Disable the peripheral, to flush data left in the FIFOs; `slave_transfer_dma` re-enables it.
+        self.regs.cr1.modify(|_, w| w.spe().clear_bit());
+        self.clear_errors();
+
+        if overrun {
+            Err(Error::Overrun)
+        } else if underrun {
+            Err(Error::Underrun)
+        } else {
+            Ok(received)
+        }
+    }
+
+    /// Clear the overrun, underrun (H7) and mode fault flags.
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    fn clear_errors(&mut self) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                self.regs.ifcr.write(|w| {
+                    w.ovrc().set_bit();
+                    w.udrc().set_bit();
+                    w.modfc().set_bit()
+                });
+            } else {
+                // "Clearing the OVR bit is done by a read access to the SPI_DR register followed by a
+                // read access to the SPI_SR register."
+                unsafe { ptr::read_volatile(&self.regs.dr as *const _ as *const u8) };
+                let sr = self.regs.sr.read();
+
+                // "Use the following software sequence to clear the MODF bit: 1. Make a read or
+                // write access to the SPIx_SR register while the MODF bit is set. 2. Then write
+                // to the SPIx_CR1 register." A mode fault clears SPE and MSTR; restore them.
+                if sr.modf().bit_is_set() {
+                    let master = self.cfg.role == SpiRole::Master;
+                    self.regs.cr1.modify(|_, w| {
+                        w.mstr().bit(master);
+                        w.spe().set_bit()
+                    });
+                }
+            }
+        }
+    }
+
     /// Stop a DMA transfer. Stops the channel, and disables the `txdmaen` and `rxdmaen` bits.
     /// Run this after each transfer completes - you may wish to do this in an interrupt
     /// (eg DMA transfer complete) instead of blocking. `channel` is an optional second channel
@@ -906,22 +1353,59 @@ where
     }
 }
 
+impl<R> Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Initialize an SPI peripheral, selecting the baud rate divider from a target SCK frequency,
+    /// in Hz, instead of a raw `BaudRate`. The source clock is the APB bus (or on H7, the kernel
+    /// clock) this SPI instance is on. The divider selected is the smallest one that doesn't
+    /// exceed `freq`; use `freq()` to read the frequency achieved.
+    ///
+    /// On H7, SPI1, 2, and 3 default to PLL1 Q; make sure `pll1.pllq_en` is set.
+    pub fn new_with_freq(regs: R, cfg: SpiConfig, freq: u32, clock_cfg: &Clocks) -> Self {
+        let baud_rate = BaudRate::from_freq(R::baud(clock_cfg), freq);
+        Self::new(regs, cfg, baud_rate)
+    }
+
+    /// Change the SPI baud rate to the highest frequency that doesn't exceed `freq`, in Hz.
+    /// Returns the frequency achieved.
+    pub fn reclock_freq(&mut self, freq: u32, clock_cfg: &Clocks) -> u32 {
+        let src_freq = R::baud(clock_cfg);
+        let baud_rate = BaudRate::from_freq(src_freq, freq);
+        self.reclock(baud_rate);
+
+        src_freq / baud_rate.value()
+    }
+
+    /// Get the current SCK frequency, in Hz, from the configured baud rate divider.
+    pub fn freq(&self, clock_cfg: &Clocks) -> u32 {
+        #[cfg(not(feature = "h7"))]
+        let bits = self.regs.cr1.read().br().bits();
+        #[cfg(feature = "h7")]
+        let bits = self.regs.cfg1.read().mbr().bits();
+
+        R::baud(clock_cfg) / BaudRate::from_bits(bits).value()
+    }
+}
+
 #[cfg(feature = "embedded-hal")]
-impl<R> FullDuplex<u8> for Spi<R>
+impl<R, W> FullDuplex<W> for Spi<R>
 where
     R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+    W: SpiWord,
 {
     type Error = Error;
 
-    fn read(&mut self) -> nb::Result<u8, Error> {
+    fn read(&mut self) -> nb::Result<W, Error> {
         match Spi::read(self) {
             Ok(r) => Ok(r),
             Err(e) => Err(nb::Error::Other(e)),
         }
     }
 
-    fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
-        match Spi::write_one(self, byte) {
+    fn send(&mut self, word: W) -> nb::Result<(), Error> {
+        match Spi::write_one(self, word) {
             Ok(r) => Ok(r),
             Err(e) => Err(nb::Error::Other(e)),
         }
@@ -929,13 +1413,43 @@ where
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> embedded_hal::blocking::spi::transfer::Default<u8> for Spi<R> where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph
+impl<R, W> embedded_hal::blocking::spi::transfer::Default<W> for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+    W: SpiWord,
 {
 }
 
 #[cfg(feature = "embedded-hal")]
-impl<R> embedded_hal::blocking::spi::write::Default<u8> for Spi<R> where
-    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph
+impl<R, W> embedded_hal::blocking::spi::write::Default<W> for Spi<R>
+where
+    R: Deref<Target = pac::spi1::RegisterBlock> + RccPeriph,
+    W: SpiWord,
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baud_rate_exact() {
+        assert_eq!(BaudRate::from_freq(80_000_000, 40_000_000).value(), 2);
+        assert_eq!(BaudRate::from_freq(80_000_000, 10_000_000).value(), 8);
+        assert_eq!(BaudRate::from_freq(80_000_000, 80_000_000).value(), 2);
+    }
+
+    #[test]
+    fn baud_rate_never_exceeds_target() {
+        // 10 MHz / 4 = 2.5 MHz, which is too fast for a 2 MHz target.
+        assert_eq!(BaudRate::from_freq(10_000_000, 2_000_000).value(), 8);
+        // 170 MHz / 16 = 10.625 MHz; / 32 = 5.3125 MHz.
+        assert_eq!(BaudRate::from_freq(170_000_000, 10_000_000).value(), 32);
+        assert_eq!(BaudRate::from_freq(170_000_000, 10_625_000).value(), 16);
+    }
+
+    #[test]
+    fn baud_rate_below_range() {
+        assert_eq!(BaudRate::from_freq(80_000_000, 1_000).value(), 256);
+    }
+}
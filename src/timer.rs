@@ -27,7 +27,7 @@ use void::Void;
 // todo: LPTIM (low-power timers) and HRTIM (high-resolution timers). And Advanced control functionality
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, Reclock},
     instant::Instant,
     pac::{self, RCC},
     util::{rcc_en_reset, RccPeriph},
@@ -808,6 +808,45 @@ macro_rules! make_timer {
             }
         }
 
+        impl Reclock for Timer<pac::$TIMX> {
+            /// Keep the timer frequency at the new clock speed. If the prescaler can be scaled
+            /// exactly, the auto-reload and compare values are left as-is, so PWM duty cycles
+            /// are maintained. Otherwise, the frequency is re-set with `set_freq`, and you'll need
+            /// to re-set duty cycles.
+            fn reclock(&mut self, clocks: &Clocks) {
+                let clock_speed = match $apb {
+                    1 => clocks.apb1_timer(),
+                    _ => clocks.apb2_timer(),
+                };
+
+                let psc = self.regs.psc.read().bits() as u64;
+                let arr = self.regs.arr.read().bits() as u64;
+
+                // (PSC+1)*(ARR+1) = TIMclk/Updatefrequency: Keep ARR, and scale PSC+1 with TIMclk.
+                let scaled = (psc + 1) * clock_speed as u64;
+                let psc_new = scaled / self.clock_speed as u64;
+
+                if scaled % self.clock_speed as u64 == 0 && psc_new >= 1 && psc_new <= u16::MAX as u64 + 1 {
+                    self.clock_speed = clock_speed;
+                    self.set_prescaler((psc_new - 1) as u16);
+
+                    let arr_f32 = arr as f32;
+                    let period_secs = psc_new as f32 * (arr_f32 + 1.) / clock_speed as f32;
+                    self.ns_per_tick = period_secs / arr_f32 * 1_000_000_000.;
+                } else {
+                    let mut freq = self.clock_speed as f32 / ((psc + 1) * (arr + 1)) as f32;
+                    // `set_freq` doubles the frequency for center-aligned modes.
+                    match self.cfg.alignment {
+                        Alignment::Edge => (),
+                        _ => freq /= 2.,
+                    }
+
+                    self.clock_speed = clock_speed;
+                    self.set_freq(freq).ok();
+                }
+            }
+        }
+
         #[cfg(feature = "monotonic")]
         impl Monotonic for Timer<pac::$TIMX> {
             type Instant = Instant;
@@ -1852,6 +1891,34 @@ cfg_if! {
                 self.regs.cr2.modify(|_, w| unsafe { w.mms().bits(mode as u8) });
             }
         }
+
+        impl<R> Reclock for BasicTimer<R>
+            where
+                R: Deref<Target = pac::tim6::RegisterBlock> + RccPeriph,
+        {
+            /// Keep the timer frequency at the new clock speed. If the prescaler can be scaled
+            /// exactly, the auto-reload value is left as-is. Otherwise, the frequency is re-set
+            /// with `set_freq`.
+            fn reclock(&mut self, clocks: &Clocks) {
+                let clock_speed = clocks.apb1_timer();
+
+                let psc = self.regs.psc.read().bits() as u64;
+                let arr = self.regs.arr.read().bits() as u64;
+
+                let scaled = (psc + 1) * clock_speed as u64;
+                let psc_new = scaled / self.clock_speed as u64;
+
+                if scaled % self.clock_speed as u64 == 0 && psc_new >= 1 && psc_new <= u16::MAX as u64 + 1 {
+                    self.clock_speed = clock_speed;
+                    self.set_prescaler((psc_new - 1) as u16);
+                } else {
+                    let freq = self.clock_speed as f32 / ((psc + 1) * (arr + 1)) as f32;
+
+                    self.clock_speed = clock_speed;
+                    self.set_freq(freq).ok();
+                }
+            }
+        }
     }
 }
 
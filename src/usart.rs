@@ -2,13 +2,12 @@
 //! Provides APIs to configure, read, and write from
 //! USART, with blocking, nonblocking, and DMA functionality.
 
-// todo: Synchronous mode.
-// todo: Auto baud
-
 // todo: Missing some features (like additional interrupts) on the USARTv3 peripheral . (L5, G etc)
 
 use crate::{
-    clocks::Clocks,
+    clocks::{Clocks, Reclock},
+    gpio::typed::{RxPin, TxPin},
+    gpio::Pin,
     pac::{self, RCC},
     util::{BaudPeriph, RccPeriph},
 };
@@ -41,6 +40,14 @@ use nb;
 
 use cfg_if::cfg_if;
 
+cfg_if! {
+    if #[cfg(any(feature = "l4", feature = "l5", feature = "g4", feature = "wb", feature = "g0b1", feature = "g0c1"))] {
+        use crate::pac::LPUART1;
+    } else if #[cfg(any(feature = "g031", feature = "g041", feature = "g071", feature = "g081", feature = "wl"))] {
+        use crate::pac::LPUART as LPUART1;
+    }
+}
+
 // todo: Prescaler (USART_PRESC) register on v3 (L5, G, H etc)
 
 #[derive(Clone, Copy)]
@@ -99,6 +106,145 @@ pub enum IrdaMode {
     LowPower,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Polarity of the RS-485 driver enable (DE) signal. (USART_CR3, DEP)
+pub enum DePolarity {
+    ActiveHigh = 0,
+    ActiveLow = 1,
+}
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// Driver enable mode, for controlling an RS-485 transceiver. In hardware mode, the DE signal
+/// is output on the USART's RTS pin. (USART_CR3, DEM)
+pub enum DriverEnable {
+    Disabled,
+    Hardware {
+        polarity: DePolarity,
+        /// Time between DE activation and the start of the start bit, in bit durations.
+        /// Maximum 1.94 with 16x oversampling, or 3.875 with 8x. (USART_CR1, DEAT)
+        assertion_time: f32,
+        /// Time between the end of the last stop bit and DE deactivation, in bit durations.
+        /// Same range as `assertion_time`. (USART_CR1, DEDT)
+        deassertion_time: f32,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock polarity, for synchronous mode. (USART_CR2, CPOL)
+pub enum ClockPolarity {
+    /// Steady low value on the CK pin outside the transmission window.
+    IdleLow = 0,
+    /// Steady high value on the CK pin outside the transmission window.
+    IdleHigh = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// Clock phase, for synchronous mode. (USART_CR2, CPHA)
+pub enum ClockPhase {
+    /// The first clock transition is the first data capture edge.
+    CaptureOnFirstTransition = 0,
+    /// The second clock transition is the first data capture edge.
+    CaptureOnSecondTransition = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// Synchronous mode: Output a clock on the CK pin while transmitting, eg to drive shift registers.
+/// (USART_CR2, CLKEN, CPOL, CPHA and LBCL)
+pub enum SyncMode {
+    Disabled,
+    Enabled {
+        polarity: ClockPolarity,
+        phase: ClockPhase,
+        /// Output a clock pulse for the last (MSB) data bit. (LBCL)
+        last_bit_clock: bool,
+    },
+}
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// Smartcard mode, for ISO 7816-3 T=0 communication with SIM cards and secure elements.
+/// When enabled, the word length, parity and stop bits in `UsartConfig` are overridden with
+/// 9-bit words (8 data bits and even parity) and 1.5 stop bits, and the card clock is output on
+/// the CK pin. (USART_CR3, SCEN, NACK, SCARCNT; USART_GTPR)
+pub enum SmartcardMode {
+    Disabled,
+    Enabled {
+        /// Guard time, in baud clock periods. (GTPR, GT)
+        guard_time: u8,
+        /// The source clock is divided by 2x this value to produce the card clock on CK.
+        /// Must be 1 - 31. (GTPR, PSC)
+        prescaler: u8,
+        /// Transmit a NACK when a parity error is received. (CR3, NACK)
+        nack: bool,
+        /// Number of times to retransmit a frame NACKed by the card, and to receive a frame
+        /// with a parity error before reporting it. 0 - 7. (CR3, SCARCNT)
+        retries: u8,
+    },
+}
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy, PartialEq)]
+/// Automatic baud rate detection; measures the baud rate on the first received character, and
+/// updates BRR to match. (USART_CR2, ABREN and ABRMOD)
+pub enum AutoBaud {
+    Disabled,
+    /// Measure the duration of the start bit. The character must start with a 1 bit.
+    StartBit,
+    /// Measure from falling edge to falling edge. The character must start with 10xx bits.
+    FallingEdge,
+    /// Detect a 0x7f frame.
+    Frame7f,
+    /// Detect a 0x55 frame.
+    Frame55,
+}
+
+#[cfg(not(feature = "f4"))]
+impl AutoBaud {
+    /// ABRMOD field value.
+    fn mode_bits(&self) -> u32 {
+        match self {
+            Self::Disabled | Self::StartBit => 0b00,
+            Self::FallingEdge => 0b01,
+            Self::Frame7f => 0b10,
+            Self::Frame55 => 0b11,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+/// LIN break detection length. (USART_CR2, LBDL)
+pub enum LinBreakLen {
+    /// 10-bit break detection.
+    B10 = 0,
+    /// 11-bit break detection.
+    B11 = 1,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+/// LIN checksum model. Classic (LIN 1.x) covers the data bytes only; Enhanced (LIN 2.x) covers
+/// the protected identifier as well. Diagnostic frames (IDs 0x3c and 0x3d) always use the
+/// classic checksum.
+pub enum LinChecksum {
+    Classic,
+    Enhanced,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Errors that can occur when receiving a LIN frame.
+pub enum LinError {
+    /// The protected identifier's parity bits don't match its ID.
+    Parity,
+    /// The received checksum doesn't match the one calculated from the frame.
+    Checksum,
+    /// The sync field wasn't 0x55, or auto baud rate detection failed on it.
+    Sync,
+}
+
 #[cfg(not(feature = "f4"))]
 #[derive(Clone, Copy)]
 /// The type of USART interrupt to configure. Reference the USART_ISR register.
@@ -117,6 +263,21 @@ pub enum UsartInterrupt {
     Tcbgt,
     TransmissionComplete,
     TransmitEmpty,
+    /// Wakeup from Stop mode. Enable with `enable_stop_wakeup`.
+    WakeupFromStop,
+}
+
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy)]
+/// Selects how the end of a variable-length reception is detected, when using
+/// `read_until_idle_dma`.
+pub enum IdleDetect {
+    /// Use the IDLE flag, which is set when the line is idle for one frame after receiving data.
+    Idle,
+    /// Use the receiver timeout (RTOF flag), which is set when the line is idle for the specified
+    /// number of bit durations. (RTOR register, RTO field; 24 bits max) Eg for Modbus RTU, use 35
+    /// for the 3.5-character inter-frame gap with 10-bit characters. Not available on LPUART.
+    ReceiverTimeout(u32),
 }
 
 /// Configuration for Usart. Can be used with default::Default.
@@ -131,6 +292,17 @@ pub struct UsartConfig {
     pub parity: Parity,
     /// IrDA mode: Enables this protocol, which is used to communicate with IR devices.
     pub irda_mode: IrdaMode,
+    #[cfg(not(feature = "f4"))]
+    /// RS-485 hardware driver enable. Defaults to disabled.
+    pub driver_enable: DriverEnable,
+    /// Synchronous mode, which outputs a clock signal. Defaults to disabled.
+    pub sync_mode: SyncMode,
+    #[cfg(not(feature = "f4"))]
+    /// Smartcard (ISO 7816) mode. Defaults to disabled. Can't be used with `sync_mode`.
+    pub smartcard_mode: SmartcardMode,
+    #[cfg(not(feature = "f4"))]
+    /// Automatic baud rate detection. Defaults to disabled.
+    pub auto_baud: AutoBaud,
 }
 
 impl Default for UsartConfig {
@@ -141,10 +313,142 @@ impl Default for UsartConfig {
             oversampling: OverSampling::O16,
             parity: Parity::Disabled,
             irda_mode: IrdaMode::None,
+            #[cfg(not(feature = "f4"))]
+            driver_enable: DriverEnable::Disabled,
+            sync_mode: SyncMode::Disabled,
+            #[cfg(not(feature = "f4"))]
+            smartcard_mode: SmartcardMode::Disabled,
+            #[cfg(not(feature = "f4"))]
+            auto_baud: AutoBaud::Disabled,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+/// The LPUART kernel clock source. (RCC_CCIPR, LPUART1SEL) Set with `Clocks::lpuart1_src`. To
+/// receive in Stop mode, use `Hsi16` or `Lse`; the others stop with the core clock.
+pub type LpuartClockSrc = crate::clocks::UsartSrc;
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+/// The low-power UART, wrapped so it can be used with `Usart`: Pass to `Usart::new` in place of
+/// a USART's registers. Its registers are a subset of USART's, at the same offsets; it has no
+/// guard time or receiver timeout registers, and doesn't support oversampling by 8, smartcard,
+/// IrDA, LIN, synchronous mode, or auto baud rate detection.
+pub struct Lpuart1 {
+    pub regs: LPUART1,
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+impl Lpuart1 {
+    /// Wrap the LPUART registers. The kernel clock source is set by `Clocks::lpuart1_src`, in
+    /// `Clocks::setup`, which also starts the HSI16 or LSE if selected. Returns an error if the
+    /// selected source is the HSI16 or LSE, and it's not running, eg if the clocks haven't been
+    /// set up yet.
+    pub fn new(regs: LPUART1) -> Result<Self, crate::clocks::SpeedError> {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        let running = match Self::clock_src() {
+            LpuartClockSrc::Hsi16 => rcc.cr.read().hsirdy().bit_is_set(),
+            LpuartClockSrc::Lse => crate::clocks::lse_ready(),
+            _ => true,
+        };
+
+        if !running {
+            return Err(crate::clocks::SpeedError::new(
+                "The LPUART1 kernel clock source isn't running",
+            ));
         }
+
+        Ok(Self { regs })
+    }
+
+    /// Read the kernel clock source from the RCC.
+    pub fn clock_src() -> LpuartClockSrc {
+        let rcc = unsafe { &(*RCC::ptr()) };
+
+        #[cfg(feature = "l5")]
+        let bits = rcc.ccipr1.read().lpuart1sel().bits();
+        #[cfg(not(feature = "l5"))]
+        let bits = rcc.ccipr.read().lpuart1sel().bits();
+
+        match bits {
+            0b00 => LpuartClockSrc::Pclk,
+            0b01 => LpuartClockSrc::Sysclk,
+            0b10 => LpuartClockSrc::Hsi16,
+            _ => LpuartClockSrc::Lse,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+impl Deref for Lpuart1 {
+    type Target = pac::usart1::RegisterBlock;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*(LPUART1::ptr() as *const _) }
     }
 }
 
+#[cfg(not(feature = "f4"))]
+#[derive(Clone, Copy)]
+/// The event that wakes the MCU from Stop mode. (USART_CR3, WUS)
+pub enum StopWakeup {
+    /// An address match; the 8-bit address to match. (USART_CR2, ADD)
+    AddressMatch(u8),
+    /// A start bit.
+    StartBit,
+    /// A received character. (RXNE)
+    ReadNotEmpty,
+}
+
 /// Represents the USART peripheral, for serial communications.
 pub struct Usart<R> {
     pub regs: R,
@@ -164,8 +468,32 @@ where
             R::en_reset(rcc);
         });
 
+        // Smartcard mode sets SCEN, which must be kept cleared in synchronous mode.
+        #[cfg(not(feature = "f4"))]
+        assert!(
+            !(matches!(config.sync_mode, SyncMode::Enabled { .. })
+                && matches!(config.smartcard_mode, SmartcardMode::Enabled { .. })),
+            "Synchronous mode and smartcard mode can't both be enabled."
+        );
+
         let mut result = Self { regs, baud, config };
 
+        // See G4 RM, section 37.5.17: "Smartcard mode": "the following bits must be kept cleared...
+        // the smartcard mode is selected by setting the SCEN bit in the USART_CR3. In smartcard
+        // mode, the following bits must be kept cleared: LINEN bit in the USART_CR2 register,
+        // HDSEL and IREN bits in the USART_CR3 register. Moreover, the CLKEN bit may be set in
+        // order to provide a clock to the smartcard. The smartcard interface is designed to
+        // support asynchronous protocol for smartcards as defined in the ISO 7816-3 standard."
+        // ISO 7816-3 characters are 8 data bits plus an even parity bit, with 1.5 stop bits
+        // (STOP=11). The M bits set the word length including the parity bit, so we use W9 to
+        // fit 8 data bits alongside it.
+        #[cfg(not(feature = "f4"))]
+        if let SmartcardMode::Enabled { .. } = result.config.smartcard_mode {
+            result.config.word_len = WordLen::W9;
+            result.config.stop_bits = StopBits::S1_5;
+            result.config.parity = Parity::EnabledEven;
+        }
+
         // This should already be disabled on power up, but disable here just in case;
         // some bits can't be set with USART enabled.
         result.regs.cr1.modify(|_, w| w.ue().clear_bit());
@@ -199,6 +527,37 @@ where
             )
         });
 
+        // See G4 RM, section 37.5.21: RS232 hardware flow control and RS485 driver enable.
+        // "The driver enable feature is enabled by setting bit DEM in the USART_CR3 control
+        // register. This enables the user to activate the external transceiver control, through the
+        // DE (Driver Enable) signal. The assertion time is the time between the activation of the DE
+        // signal and the beginning of the START bit. It is programmed using the DEAT [4:0] bit
+        // fields in the USART_CR1 control register. The de-assertion time is the time between the
+        // end of the last stop bit, in a transmitted message, and the de-activation of the DE
+        // signal. It is programmed using the DEDT [4:0] bit fields in the USART_CR1 control
+        // register. The polarity of the DE signal can be configured using the DEP bit in the
+        // USART_CR3 control register."
+        #[cfg(not(feature = "f4"))]
+        if let DriverEnable::Hardware {
+            polarity,
+            assertion_time,
+            deassertion_time,
+        } = result.config.driver_enable
+        {
+            let deat = de_time_to_samples(assertion_time, result.config.oversampling);
+            let dedt = de_time_to_samples(deassertion_time, result.config.oversampling);
+
+            // DEAT and DEDT are written directly, since some PACs split them into individual bits.
+            result.regs.cr1.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0x3ff << 16)) | (deat << 21) | (dedt << 16))
+            });
+
+            result.regs.cr3.modify(|_, w| {
+                w.dep().bit(polarity == DePolarity::ActiveLow);
+                w.dem().set_bit()
+            });
+        }
+
         // 2. Select the desired baud rate using the USART_BRR register.
         result.set_baud(baud, clock_cfg);
         // 3. Program the number of stop bits in USART_CR2.
@@ -206,6 +565,72 @@ where
             .regs
             .cr2
             .modify(|_, w| unsafe { w.stop().bits(result.config.stop_bits as u8) });
+        // See G4 RM, section 37.5.15: "USART synchronous mode": "The synchronous master mode is
+        // selected by writing the CLKEN bit in the USART_CR2 register to 1. In synchronous mode,
+        // the following bits must be kept cleared: LINEN bit in the USART_CR2 register, SCEN,
+        // HDSEL and IREN bits in the USART_CR3 register."
+        if let SyncMode::Enabled {
+            polarity,
+            phase,
+            last_bit_clock,
+        } = result.config.sync_mode
+        {
+            result.regs.cr2.modify(|_, w| {
+                w.linen().clear_bit();
+                w.cpol().bit(polarity as u8 != 0);
+                w.cpha().bit(phase as u8 != 0);
+                w.lbcl().bit(last_bit_clock);
+                w.clken().set_bit()
+            });
+            result.regs.cr3.modify(|_, w| {
+                w.scen().clear_bit();
+                w.hdsel().clear_bit();
+                w.iren().clear_bit()
+            });
+        }
+
+        #[cfg(not(feature = "f4"))]
+        if let SmartcardMode::Enabled {
+            guard_time,
+            prescaler,
+            nack,
+            retries,
+        } = result.config.smartcard_mode
+        {
+            assert!(
+                prescaler >= 1 && prescaler <= 31,
+                "Smartcard prescaler must be 1 - 31."
+            );
+            assert!(retries <= 7, "Smartcard retries must be 0 - 7.");
+
+            // "The USART_GTPR register... GT[7:0]: Guard time value. This bitfield is used to
+            // program the Guard time value in terms of number of baud clock periods. PSC[4:0]:
+            // In Smartcard mode, this bitfield is used to program the prescaler for dividing the
+            // USART source clock to provide the Smartcard clock. The value given in the register
+            // (5 significant bits) is multiplied by 2 to give the division factor of the source
+            // clock frequency."
+            result.regs.gtpr.modify(|_, w| unsafe {
+                w.gt().bits(guard_time);
+                w.psc().bits(prescaler)
+            });
+
+            result.regs.cr2.modify(|_, w| {
+                w.linen().clear_bit();
+                w.clken().set_bit()
+            });
+
+            result.regs.cr3.modify(|_, w| unsafe {
+                w.hdsel().clear_bit();
+                w.iren().clear_bit();
+                w.scarcnt().bits(retries);
+                w.nack().bit(nack);
+                w.scen().set_bit()
+            });
+        }
+
+        #[cfg(not(feature = "f4"))]
+        result.set_auto_baud(result.config.auto_baud);
+
         // 4. Enable the USART by writing the UE bit in USART_CR1 register to 1.
         result.regs.cr1.modify(|_, w| w.ue().set_bit());
         // 5. Select DMA enable (DMAT[R]] in USART_CR3 if multibuffer communication is to take
@@ -246,6 +671,20 @@ where
         result
     }
 
+    /// Similar to `new`, but also takes the TX and RX pins, configured with their alternate
+    /// function. Pins or alternate functions that don't belong to this peripheral fail to compile.
+    /// See the `gpio::typed` module.
+    pub fn new_with_pins<TX: TxPin<R>, RX: RxPin<R>>(
+        regs: R,
+        _tx: TX,
+        _rx: RX,
+        baud: u32,
+        config: UsartConfig,
+        clock_cfg: &Clocks,
+    ) -> Self {
+        Self::new(regs, baud, config, clock_cfg)
+    }
+
     /// Set the BAUD rate. Called during init, and can be called later to change BAUD
     /// during program execution.
     pub fn set_baud(&mut self, baud: u32, clock_cfg: &Clocks) {
@@ -257,14 +696,22 @@ where
         }
 
         // To set BAUD rate, see L4 RM section 38.5.4: "USART baud rate generation".
-        // todo: This assumes the USART clock is APB1 or 2 depending on which USART.
-        // todo: Take into account the selectable USART clock in both
-        // todo util::baud implementation, and `clocks` module.
         let fclk = R::baud(clock_cfg);
 
-        let usart_div = match self.config.oversampling {
-            OverSampling::O16 => fclk / baud,
-            OverSampling::O8 => 2 * fclk / baud,
+        // LPUART: "LPUARTDIV is coded on the LPUART_BRR register... Tx/Rx baud =
+        // (256 x fck) / LPUARTDIV". It must be at least 0x300. (L4 RM, section 39.4.4)
+        let usart_div = if R::low_power_uart() {
+            let div = (256 * fclk as u64 / baud as u64) as u32;
+            assert!(
+                div >= 0x300 && div <= 0xf_ffff,
+                "LPUART baud rate must be between fck/4096 and fck/3."
+            );
+            div
+        } else {
+            match self.config.oversampling {
+                OverSampling::O16 => fclk / baud,
+                OverSampling::O8 => 2 * fclk / baud,
+            }
         };
 
         // USARTDIV is an unsigned fixed point number that is coded on the USART_BRR register.
@@ -497,6 +944,281 @@ where
         // controller generates an interrupt on the DMA channel interrupt vector.
     }
 
+    #[cfg(not(feature = "f4"))]
+    /// Enable the receiver timeout feature, and set its duration, in bit durations. The RTOF flag
+    /// (`UsartInterrupt::ReceiverTimeout`) is set if no new start bit is detected for this long
+    /// after the last received character. (CR2 RTOEN bit, and RTOR register RTO field) Not
+    /// available on LPUART.
+    pub fn set_receiver_timeout(&mut self, bit_durations: u32) {
+        assert!(!R::low_power_uart(), "LPUART has no receiver timeout.");
+        // The RTO field is 24 bits wide.
+        assert!(bit_durations < (1 << 24));
+        self.regs
+            .rtor
+            .modify(|_, w| unsafe { w.rto().bits(bit_durations) });
+        self.regs.cr2.modify(|_, w| w.rtoen().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Disable the receiver timeout feature. (CR2 RTOEN bit)
+    pub fn disable_receiver_timeout(&mut self) {
+        // LPUART has no RTOEN bit; there's nothing to disable.
+        if R::low_power_uart() {
+            return;
+        }
+        self.regs.cr2.modify(|_, w| w.rtoen().clear_bit());
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    /// Receive a variable-length message using DMA. This starts a DMA read into `buf` (one-shot, or
+    /// circular if set in `channel_cfg`), and enables the IDLE or receiver timeout interrupt,
+    /// which fires when the line goes quiet. In that interrupt's handler, call
+    /// `read_until_idle_dma_len` to clear the flag and find out how much was received.
+    /// Note that the `channel` argument is only used on F3 and L4.
+    pub unsafe fn read_until_idle_dma<D>(
+        &mut self,
+        buf: &mut [u8],
+        channel: DmaChannel,
+        channel_cfg: ChannelCfg,
+        dma: &mut Dma<D>,
+        detect: IdleDetect,
+    ) where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        // Clear any stale flags, so the interrupt doesn't fire immediately. LPUART has no
+        // receiver timeout, so its RTO bits are reserved; leave them alone.
+        self.clear_interrupt(UsartInterrupt::Idle);
+        if !R::low_power_uart() {
+            self.clear_interrupt(UsartInterrupt::ReceiverTimeout);
+        }
+
+        match detect {
+            IdleDetect::Idle => {
+                if !R::low_power_uart() {
+                    self.disable_receiver_timeout();
+                    self.disable_interrupt(UsartInterrupt::ReceiverTimeout);
+                }
+                self.enable_interrupt(UsartInterrupt::Idle);
+            }
+            IdleDetect::ReceiverTimeout(bit_durations) => {
+                self.set_receiver_timeout(bit_durations);
+                self.disable_interrupt(UsartInterrupt::Idle);
+                self.enable_interrupt(UsartInterrupt::ReceiverTimeout);
+            }
+        }
+
+        self.read_dma(buf, channel, channel_cfg, dma);
+    }
+
+    #[cfg(not(any(feature = "f4", feature = "l552")))]
+    /// Call this from the USART interrupt handler after starting a read with `read_until_idle_dma`.
+    /// Clears the IDLE and receiver timeout flags, and returns the number of bytes DMA has written
+    /// to the buffer, based on the channel's remaining transfer count. `buf_len` is the length of
+    /// the buffer passed to `read_until_idle_dma`. In circular mode, this is the buffer index
+    /// the next byte will be written to.
+    ///
+    /// For a one-shot read, call `dma.stop()` on the channel before starting the next read.
+    /// Note that the `channel` argument is only used on F3 and L4.
+    pub fn read_until_idle_dma_len<D>(
+        &mut self,
+        buf_len: usize,
+        channel: DmaChannel,
+        dma: &Dma<D>,
+    ) -> usize
+    where
+        D: Deref<Target = dma_p::RegisterBlock>,
+    {
+        self.clear_interrupt(UsartInterrupt::Idle);
+        if !R::low_power_uart() {
+            self.clear_interrupt(UsartInterrupt::ReceiverTimeout);
+        }
+
+        #[cfg(any(feature = "f3", feature = "l4"))]
+        let channel = R::read_chan();
+
+        // In circular mode, NDTR is reloaded to its original value as soon as the transfer wraps,
+        // so it never reads 0.
+        buf_len - dma.num_data_remaining(channel) as usize
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Configure automatic baud rate detection. (USART_CR2, ABREN and ABRMOD) See G4 RM,
+    /// section 37.5.8: "USART autobaud rate detection". Note that this is only available on
+    /// some USART instances.
+    pub fn set_auto_baud(&mut self, auto_baud: AutoBaud) {
+        let originally_enabled = self.regs.cr1.read().ue().bit_is_set();
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+            while self.regs.cr1.read().ue().bit_is_set() {}
+        }
+
+        // ABRMOD is written directly, since some PACs split it into individual bits.
+        self.regs.cr2.modify(|r, w| unsafe {
+            w.bits(
+                (r.bits() & !(0b111 << 20))
+                    | (auto_baud.mode_bits() << 21)
+                    | ((auto_baud != AutoBaud::Disabled) as u32) << 20,
+            )
+        });
+
+        self.config.auto_baud = auto_baud;
+
+        if originally_enabled {
+            self.regs.cr1.modify(|_, w| w.ue().set_bit());
+        }
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Request a new automatic baud rate measurement on the next received character.
+    /// (USART_RQR, ABRRQ)
+    pub fn request_auto_baud(&mut self) {
+        self.regs.rqr.write(|w| w.abrrq().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Check the result of automatic baud rate detection. Returns `Ok(true)` once the baud rate has
+    /// been measured and BRR updated, and `Ok(false)` while it's in progress. (USART_ISR, ABRF
+    /// and ABRE)
+    pub fn auto_baud_complete(&self) -> Result<bool, Error> {
+        let isr = self.regs.isr.read();
+        if isr.abre().bit_is_set() {
+            return Err(Error::AutoBaud);
+        }
+        Ok(isr.abrf().bit_is_set())
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Enable LIN mode. See G4 RM, section 37.5.13: "USART LIN (local interconnection network)
+    /// mode". Use 8-bit words, 1 stop bit, and no parity.
+    pub fn enable_lin(&mut self, break_len: LinBreakLen) {
+        self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+        while self.regs.cr1.read().ue().bit_is_set() {}
+
+        // "The LIN mode is selected by setting the LINEN bit in the USART_CR2 register. In LIN
+        // mode, the following bits must be kept cleared:
+        // • STOP[1:0] and CLKEN in the USART_CR2 register,
+        // • SCEN, HDSEL and IREN in the USART_CR3 register."
+        self.regs.cr2.modify(|_, w| unsafe {
+            w.stop().bits(0);
+            w.clken().clear_bit();
+            w.lbdl().bit(break_len as u8 != 0);
+            w.linen().set_bit()
+        });
+        self.regs.cr3.modify(|_, w| {
+            w.scen().clear_bit();
+            w.hdsel().clear_bit();
+            w.iren().clear_bit()
+        });
+
+        self.regs.cr1.modify(|_, w| w.ue().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Disable LIN mode. (USART_CR2, LINEN)
+    pub fn disable_lin(&mut self) {
+        self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+        while self.regs.cr1.read().ue().bit_is_set() {}
+
+        self.regs.cr2.modify(|_, w| w.linen().clear_bit());
+
+        self.regs.cr1.modify(|_, w| w.ue().set_bit());
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Send a break character, and block until it's been sent. (USART_RQR, SBKRQ)
+    /// In LIN mode, this is 13 bits low, followed by a 1-bit delimiter.
+    pub fn send_break(&mut self) {
+        // "[SBKF] is set by software, and reset by hardware during the stop bit of break
+        // transmission."
+        while self.regs.isr.read().txe().bit_is_clear() {}
+        self.regs.rqr.write(|w| w.sbkrq().set_bit());
+        while self.regs.isr.read().sbkf().bit_is_set() {}
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// LIN master: Send a frame header: A break, the sync field (0x55), and the protected
+    /// identifier for `id`. `id` is the 6-bit frame identifier.
+    pub fn lin_send_header(&mut self, id: u8) {
+        self.send_break();
+        self.write(&[LIN_SYNC, lin_protected_id(id)]);
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// LIN master or slave: Send a frame response; the data bytes, followed by the checksum.
+    pub fn lin_send_response(&mut self, id: u8, data: &[u8], checksum: LinChecksum) {
+        self.write(data);
+        self.write(&[lin_checksum(id, data, checksum)]);
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// LIN master or slave: Receive a frame response into `buf`, blocking until `buf.len()` data bytes
+    /// and the checksum byte are received. Returns an error if the checksum doesn't match.
+    pub fn lin_read_response(
+        &mut self,
+        id: u8,
+        buf: &mut [u8],
+        checksum: LinChecksum,
+    ) -> Result<(), LinError> {
+        self.read(buf);
+
+        let mut received = [0];
+        self.read(&mut received);
+
+        if received[0] != lin_checksum(id, buf, checksum) {
+            return Err(LinError::Checksum);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// LIN slave: Block until a frame header is received, and return the frame's 6-bit identifier.
+    /// If `autobaud` is true, the baud rate is measured from the sync field, and `BRR` is updated
+    /// to match the master's. The auto baud rate setting is restored afterwards.
+    pub fn lin_read_header(&mut self, autobaud: bool) -> Result<u8, LinError> {
+        // "The LIN break is detected... When the break is detected, the LBDF flag is set". The
+        // break also shows up as a framing error, with 0 data; discard it.
+        while self.regs.isr.read().lbdf().bit_is_clear() {}
+        self.regs.icr.write(|w| {
+            w.lbdcf().set_bit();
+            w.fecf().set_bit()
+        });
+        self.regs.rqr.write(|w| w.rxfrq().set_bit());
+
+        let prev_auto_baud = self.config.auto_baud;
+
+        if autobaud {
+            // Measure the baud rate on the 0x55 sync field.
+            if prev_auto_baud != AutoBaud::Frame55 {
+                self.set_auto_baud(AutoBaud::Frame55);
+            }
+            self.request_auto_baud();
+        }
+
+        let mut header = [0; 2];
+        self.read(&mut header);
+
+        if autobaud {
+            let measured = self.auto_baud_complete();
+
+            // Restore the previous setting, eg clearing ABREN, so later characters don't
+            // re-trigger a measurement. BRR keeps the measured value.
+            if prev_auto_baud != AutoBaud::Frame55 {
+                self.set_auto_baud(prev_auto_baud);
+            }
+
+            if measured != Ok(true) {
+                return Err(LinError::Sync);
+            }
+        }
+
+        if header[0] != LIN_SYNC {
+            return Err(LinError::Sync);
+        }
+
+        lin_id_from_pid(header[1]).ok_or(LinError::Parity)
+    }
+
     /// Flush the transmit buffer.
     pub fn flush(&self) {
         #[cfg(not(feature = "f4"))]
@@ -505,6 +1227,55 @@ where
         while self.regs.sr.read().tc().bit_is_clear() {}
     }
 
+    #[cfg(not(feature = "f4"))]
+    /// Allow this peripheral to wake the MCU from Stop mode, eg `low_power::stop`, on the
+    /// specified event. Enables the wakeup from Stop interrupt (WUFIE); clear it with
+    /// `clear_interrupt(UsartInterrupt::WakeupFromStop)`, and unmask the peripheral's interrupt
+    /// in the NVIC. The kernel clock must keep running in Stop mode: On LPUART, use
+    /// `LpuartClockSrc::Hsi16` or `LpuartClockSrc::Lse`. On L4 and L5, only LPUART1 can do this
+    /// from Stop 2. See L4 RM, section 38.5.20: "Wakeup from Stop mode using USART".
+    pub fn enable_stop_wakeup(&mut self, wakeup: StopWakeup) {
+        // "The UESM bit must be set and the USART clock must be set to HSI16 or LSE... The
+        // WUS bits are written when the USART is disabled (UE=0)."
+        self.regs.cr1.modify(|_, w| w.ue().clear_bit());
+        while self.regs.cr1.read().ue().bit_is_set() {}
+
+        let wus = match wakeup {
+            StopWakeup::AddressMatch(addr) => {
+                self.regs.cr2.modify(|_, w| unsafe {
+                    w.addm7().set_bit();
+                    cfg_if! {
+                        if #[cfg(any(feature = "l5", feature = "g4", feature = "wb"))] {
+                            w.add0_3().bits(addr);
+                            w.add4_7().bits(addr >> 4)
+                        } else {
+                            w.add().bits(addr)
+                        }
+                    }
+                });
+                0b00
+            }
+            StopWakeup::StartBit => 0b10,
+            StopWakeup::ReadNotEmpty => 0b11,
+        };
+
+        self.regs.cr3.modify(|_, w| unsafe {
+            w.wus().bits(wus);
+            w.wufie().set_bit()
+        });
+        self.regs.cr1.modify(|_, w| {
+            w.uesm().set_bit();
+            w.ue().set_bit()
+        });
+    }
+
+    #[cfg(not(feature = "f4"))]
+    /// Stop this peripheral from waking the MCU from Stop mode.
+    pub fn disable_stop_wakeup(&mut self) {
+        self.regs.cr3.modify(|_, w| w.wufie().clear_bit());
+        self.regs.cr1.modify(|_, w| w.uesm().clear_bit());
+    }
+
     #[cfg(not(feature = "f4"))]
     /// Enable a specific type of interrupt. See G4 RM, Table 349: USART interrupt requests.
     pub fn enable_interrupt(&mut self, interrupt: UsartInterrupt) {
@@ -574,6 +1345,9 @@ where
             UsartInterrupt::TransmitEmpty => {
                 self.regs.cr1.modify(|_, w| w.txeie().set_bit());
             }
+            UsartInterrupt::WakeupFromStop => {
+                self.regs.cr3.modify(|_, w| w.wufie().set_bit());
+            }
         }
     }
 
@@ -622,6 +1396,9 @@ where
             UsartInterrupt::TransmitEmpty => {
                 self.regs.cr1.modify(|_, w| w.txeie().clear_bit());
             }
+            UsartInterrupt::WakeupFromStop => {
+                self.regs.cr3.modify(|_, w| w.wufie().clear_bit());
+            }
         }
     }
 
@@ -646,13 +1423,164 @@ where
             UsartInterrupt::Tcbgt => self.regs.icr.write(|w| w.tcbgtc().set_bit()),
             UsartInterrupt::TransmissionComplete => self.regs.icr.write(|w| w.tccf().set_bit()),
             UsartInterrupt::TransmitEmpty => self.regs.rqr.write(|w| w.txfrq().set_bit()),
+            UsartInterrupt::WakeupFromStop => self.regs.icr.write(|w| w.wucf().set_bit()),
+        }
+    }
+}
+
+impl<R> Reclock for Usart<R>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Re-set the baud rate, using the new clock speeds.
+    fn reclock(&mut self, clocks: &Clocks) {
+        self.set_baud(self.baud, clocks);
+    }
+}
+
+#[cfg(not(feature = "f4"))]
+/// Convert a driver enable assertion or deassertion time in bit durations to the number of
+/// sample time units used by the DEAT and DEDT fields, clamped to their 5-bit range.
+fn de_time_to_samples(bit_durations: f32, oversampling: OverSampling) -> u32 {
+    let samples_per_bit = match oversampling {
+        OverSampling::O16 => 16.,
+        OverSampling::O8 => 8.,
+    };
+
+    let samples = (bit_durations * samples_per_bit + 0.5) as u32;
+    if samples > 31 {
+        31
+    } else {
+        samples
+    }
+}
+
+/// Software control of an RS-485 transceiver's driver enable (DE) pin, using the USART's
+/// transmission complete interrupt. Use this on families without hardware driver enable (eg F4),
+/// or when DE can't be routed to the USART's RTS pin. Unlike waiting on TC in a blocking loop,
+/// DE is released as soon as the last stop bit is sent, regardless of baud rate.
+pub struct SoftwareDe {
+    pub pin: Pin,
+    pub polarity: DePolarity,
+}
+
+impl SoftwareDe {
+    /// Create the struct, and deassert DE. `pin` should already be configured as an output.
+    pub fn new(pin: Pin, polarity: DePolarity) -> Self {
+        let mut result = Self { pin, polarity };
+        result.deassert();
+        result
+    }
+
+    fn assert(&mut self) {
+        match self.polarity {
+            DePolarity::ActiveHigh => self.pin.set_high(),
+            DePolarity::ActiveLow => self.pin.set_low(),
+        }
+    }
+
+    fn deassert(&mut self) {
+        match self.polarity {
+            DePolarity::ActiveHigh => self.pin.set_low(),
+            DePolarity::ActiveLow => self.pin.set_high(),
+        }
+    }
+
+    /// Assert DE, and enable the transmission complete interrupt. Call this immediately before
+    /// starting a write, eg with `Usart::write_dma`.
+    pub fn begin_transmission<R>(&mut self, usart: &mut Usart<R>)
+    where
+        R: Deref<Target = pac::usart1::RegisterBlock>,
+    {
+        self.assert();
+
+        // TC is set while the line is idle; clear it so the interrupt only fires once the
+        // upcoming transmission is done.
+        #[cfg(not(feature = "f4"))]
+        usart.regs.icr.write(|w| w.tccf().set_bit());
+        #[cfg(feature = "f4")]
+        usart.regs.sr.modify(|_, w| w.tc().clear_bit());
+
+        usart.regs.cr1.modify(|_, w| w.tcie().set_bit());
+    }
+
+    /// Call this from the USART interrupt handler. If the transmission is complete, deasserts DE,
+    /// and clears and disables the transmission complete interrupt. Returns `true` if it did.
+    pub fn on_interrupt<R>(&mut self, usart: &mut Usart<R>) -> bool
+    where
+        R: Deref<Target = pac::usart1::RegisterBlock>,
+    {
+        #[cfg(not(feature = "f4"))]
+        let complete = usart.regs.isr.read().tc().bit_is_set();
+        #[cfg(feature = "f4")]
+        let complete = usart.regs.sr.read().tc().bit_is_set();
+
+        if !complete || usart.regs.cr1.read().tcie().bit_is_clear() {
+            return false;
         }
+
+        self.deassert();
+
+        #[cfg(not(feature = "f4"))]
+        usart.regs.icr.write(|w| w.tccf().set_bit());
+        #[cfg(feature = "f4")]
+        usart.regs.sr.modify(|_, w| w.tc().clear_bit());
+
+        usart.regs.cr1.modify(|_, w| w.tcie().clear_bit());
+        true
     }
 }
 
+/// The LIN sync field, sent after the break in each frame header.
+pub const LIN_SYNC: u8 = 0x55;
+
+/// Compute a LIN protected identifier: the 6-bit frame ID in bits 0-5, with parity bits
+/// P0 = ID0 ^ ID1 ^ ID2 ^ ID4 in bit 6, and P1 = !(ID1 ^ ID3 ^ ID4 ^ ID5) in bit 7.
+pub fn lin_protected_id(id: u8) -> u8 {
+    let id = id & 0x3f;
+    let bit = |n: u8| (id >> n) & 1;
+
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Extract the 6-bit frame ID from a LIN protected identifier. Returns `None` if its parity
+/// bits are incorrect.
+pub fn lin_id_from_pid(pid: u8) -> Option<u8> {
+    let id = pid & 0x3f;
+    if lin_protected_id(id) == pid {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Compute the checksum of a LIN frame: The inverted 8-bit sum, with carry, of the data bytes,
+/// and, for the enhanced model, the protected identifier. `id` is the 6-bit frame identifier.
+pub fn lin_checksum(id: u8, data: &[u8], checksum: LinChecksum) -> u8 {
+    let id = id & 0x3f;
+    // "The enhanced checksum is used for communication with LIN 2.x slave nodes, except for
+    // diagnostic frames, which always use the classic checksum".
+    let mut sum: u16 = match checksum {
+        LinChecksum::Enhanced if id != 0x3c && id != 0x3d => lin_protected_id(id) as u16,
+        _ => 0,
+    };
+
+    for byte in data {
+        sum += *byte as u16;
+        if sum > 0xff {
+            sum -= 0xff;
+        }
+    }
+
+    !(sum as u8)
+}
+
 /// Serial error
 #[non_exhaustive]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Error {
     /// Framing error
     Framing,
@@ -662,6 +1590,304 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Automatic baud rate detection failed
+    AutoBaud,
+}
+
+/// A fixed-capacity FIFO of bytes, used by `BufferedUsart` to hold data between the USART
+/// interrupt handler and application code. It doesn't touch any registers, so can be used
+/// (and tested) independently of the hardware.
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    /// Index of the oldest byte in the buffer.
+    head: usize,
+    /// Number of bytes currently stored.
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Create an empty buffer.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// The total number of bytes the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of bytes currently stored.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Add a byte to the end of the buffer. Returns `false`, and discards the byte, if
+    /// the buffer is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Remove and return the oldest byte in the buffer, if there is one.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Add as many bytes from `data` as will fit. Returns the number of bytes added.
+    pub fn push_slice(&mut self, data: &[u8]) -> usize {
+        let mut count = 0;
+        for byte in data {
+            if !self.push(*byte) {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Remove bytes into `buf`, oldest first, until either is exhausted. Returns the number
+    /// of bytes removed.
+    pub fn pop_slice(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        for slot in buf.iter_mut() {
+            match self.pop() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Discard all stored bytes.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An interrupt-driven USART, that owns transmit and receive ring buffers. Call `on_interrupt()`
+/// from the USART's ISR; `read()` and `write()` move data to and from the buffers without blocking.
+/// `TX` and `RX` are the buffer sizes, in bytes.
+pub struct BufferedUsart<R, const TX: usize, const RX: usize> {
+    pub usart: Usart<R>,
+    tx_buf: RingBuffer<TX>,
+    rx_buf: RingBuffer<RX>,
+    /// Set when the line goes idle after receiving data; cleared by `idle_detected()`.
+    idle: bool,
+}
+
+impl<R, const TX: usize, const RX: usize> BufferedUsart<R, TX, RX>
+where
+    R: Deref<Target = pac::usart1::RegisterBlock> + RccPeriph + BaudPeriph,
+{
+    /// Wrap an initialized `Usart`, and enable the RXNE, IDLE and error interrupts. The TXE
+    /// interrupt is enabled by `write()` while there's data to send. Make sure to unmask the
+    /// USART's interrupt line in the NVIC.
+    pub fn new(usart: Usart<R>) -> Self {
+        let parity = usart.config.parity != Parity::Disabled;
+        usart.regs.cr1.modify(|_, w| {
+            w.rxneie().set_bit();
+            w.idleie().set_bit();
+            w.peie().bit(parity)
+        });
+        // Framing, noise and overrun errors are all enabled by `EIE`.
+        usart.regs.cr3.modify(|_, w| w.eie().set_bit());
+
+        Self {
+            usart,
+            tx_buf: RingBuffer::new(),
+            rx_buf: RingBuffer::new(),
+            idle: false,
+        }
+    }
+
+    /// Queue data for transmission. Returns the number of bytes queued, which is less than
+    /// `data.len()` if the transmit buffer is full.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let count = free(|_| self.tx_buf.push_slice(data));
+        if count > 0 {
+            // The ISR sends bytes as TDR empties, and disables this interrupt once the buffer drains.
+            self.usart.regs.cr1.modify(|_, w| w.txeie().set_bit());
+        }
+        count
+    }
+
+    /// Move received data into `buf`. Returns the number of bytes read, which is 0 if no
+    /// data is waiting.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        free(|_| self.rx_buf.pop_slice(buf))
+    }
+
+    /// The number of received bytes waiting to be read.
+    pub fn rx_len(&self) -> usize {
+        self.rx_buf.len()
+    }
+
+    /// The number of bytes queued, but not yet sent.
+    pub fn tx_len(&self) -> usize {
+        self.tx_buf.len()
+    }
+
+    /// Returns `true` if the line has gone idle since the last call. Useful for detecting the end
+    /// of a variable-length message.
+    pub fn idle_detected(&mut self) -> bool {
+        let result = self.idle;
+        self.idle = false;
+        result
+    }
+
+    /// Service the USART; call this from its interrupt handler. Moves a received byte into the
+    /// receive buffer, moves a byte from the transmit buffer to TDR, and clears the flags that
+    /// triggered the interrupt. Returns an error if one was flagged by hardware, or if a byte was
+    /// dropped because the receive buffer was full.
+    #[cfg(not(feature = "f4"))]
+    pub fn on_interrupt(&mut self) -> Result<(), Error> {
+        let isr = self.usart.regs.isr.read();
+        let mut result = Ok(());
+
+        // Check errors before reading RDR; they apply to the byte currently in it.
+        if isr.pe().bit_is_set() {
+            self.usart.regs.icr.write(|w| w.pecf().set_bit());
+            result = Err(Error::Parity);
+        }
+        if isr.fe().bit_is_set() {
+            self.usart.regs.icr.write(|w| w.fecf().set_bit());
+            result = Err(Error::Framing);
+        }
+        #[cfg(not(feature = "wl"))]
+        let noise = isr.nf().bit_is_set();
+        #[cfg(feature = "wl")]
+        let noise = isr.ne().bit_is_set();
+        if noise {
+            self.usart.regs.icr.write(|w| w.ncf().set_bit());
+            result = Err(Error::Noise);
+        }
+        if isr.ore().bit_is_set() {
+            self.usart.regs.icr.write(|w| w.orecf().set_bit());
+            result = Err(Error::Overrun);
+        }
+
+        if isr.rxne().bit_is_set() {
+            // Reading RDR clears RXNE.
+            let byte = self.usart.read_one();
+            if !self.rx_buf.push(byte) {
+                result = Err(Error::Overrun);
+            }
+        }
+
+        if isr.idle().bit_is_set() {
+            self.usart.regs.icr.write(|w| w.idlecf().set_bit());
+            self.idle = true;
+        }
+
+        if isr.txe().bit_is_set() && self.usart.regs.cr1.read().txeie().bit_is_set() {
+            match self.tx_buf.pop() {
+                // Writing TDR clears TXE.
+                Some(byte) => self
+                    .usart
+                    .regs
+                    .tdr
+                    .write(|w| unsafe { w.tdr().bits(byte as u16) }),
+                None => self.usart.regs.cr1.modify(|_, w| w.txeie().clear_bit()),
+            }
+        }
+
+        result
+    }
+
+    /// Service the USART; call this from its interrupt handler. Moves a received byte into the
+    /// receive buffer, moves a byte from the transmit buffer to DR, and clears the flags that
+    /// triggered the interrupt. Returns an error if one was flagged by hardware, or if a byte was
+    /// dropped because the receive buffer was full.
+    #[cfg(feature = "f4")]
+    pub fn on_interrupt(&mut self) -> Result<(), Error> {
+        // F4 has no ICR: the error and IDLE flags are cleared by this SR read, followed by a
+        // DR read.
+        let sr = self.usart.regs.sr.read();
+        let mut result = Ok(());
+
+        if sr.pe().bit_is_set() {
+            result = Err(Error::Parity);
+        }
+        if sr.fe().bit_is_set() {
+            result = Err(Error::Framing);
+        }
+        if sr.nf().bit_is_set() {
+            result = Err(Error::Noise);
+        }
+        if sr.ore().bit_is_set() {
+            result = Err(Error::Overrun);
+        }
+
+        if sr.rxne().bit_is_set() {
+            let byte = self.usart.read_one();
+            if !self.rx_buf.push(byte) {
+                result = Err(Error::Overrun);
+            }
+        } else if result.is_err() || sr.idle().bit_is_set() {
+            // Complete the clear sequence; there's no new data in DR.
+            let _ = self.usart.read_one();
+        }
+
+        if sr.idle().bit_is_set() {
+            self.idle = true;
+        }
+
+        if sr.txe().bit_is_set() && self.usart.regs.cr1.read().txeie().bit_is_set() {
+            match self.tx_buf.pop() {
+                // Writing DR clears TXE.
+                Some(byte) => self
+                    .usart
+                    .regs
+                    .dr
+                    .write(|w| unsafe { w.dr().bits(byte as u16) }),
+                None => self.usart.regs.cr1.modify(|_, w| w.txeie().clear_bit()),
+            }
+        }
+
+        result
+    }
+
+    /// Release the underlying `Usart`, disabling the interrupts enabled by this wrapper.
+    pub fn free(self) -> Usart<R> {
+        self.usart.regs.cr1.modify(|_, w| {
+            w.rxneie().clear_bit();
+            w.idleie().clear_bit();
+            w.peie().clear_bit();
+            w.txeie().clear_bit()
+        });
+        self.usart.regs.cr3.modify(|_, w| w.eie().clear_bit());
+        self.usart
+    }
 }
 
 // todo: Use those errors above.
@@ -744,3 +1970,96 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_empty() {
+        let mut buf = RingBuffer::<4>::new();
+        assert!(buf.is_empty());
+        assert!(!buf.is_full());
+        assert_eq!(buf.len(), 0);
+        assert_eq!(buf.pop(), None);
+
+        let mut out = [0; 4];
+        assert_eq!(buf.pop_slice(&mut out), 0);
+    }
+
+    #[test]
+    fn ring_buffer_full() {
+        let mut buf = RingBuffer::<4>::new();
+        assert_eq!(buf.push_slice(&[1, 2, 3, 4, 5, 6]), 4);
+        assert!(buf.is_full());
+        assert_eq!(buf.len(), buf.capacity());
+        assert!(!buf.push(7));
+
+        let mut out = [0; 6];
+        assert_eq!(buf.pop_slice(&mut out), 4);
+        assert_eq!(out[..4], [1, 2, 3, 4]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn ring_buffer_wraparound() {
+        let mut buf = RingBuffer::<4>::new();
+        buf.push_slice(&[1, 2, 3]);
+        assert_eq!(buf.pop(), Some(1));
+        assert_eq!(buf.pop(), Some(2));
+
+        // The head is now at index 2; these writes wrap past the end of the storage.
+        assert_eq!(buf.push_slice(&[4, 5, 6]), 3);
+        assert!(buf.is_full());
+
+        let mut out = [0; 4];
+        assert_eq!(buf.pop_slice(&mut out), 4);
+        assert_eq!(out, [3, 4, 5, 6]);
+
+        for i in 0..10 {
+            assert!(buf.push(i));
+            assert_eq!(buf.pop(), Some(i));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn lin_pid_parity() {
+        // Includes the diagnostic frame IDs, 0x3c and 0x3d.
+        assert_eq!(lin_protected_id(0x00), 0x80);
+        assert_eq!(lin_protected_id(0x01), 0xc1);
+        assert_eq!(lin_protected_id(0x10), 0x50);
+        assert_eq!(lin_protected_id(0x3c), 0x3c);
+        assert_eq!(lin_protected_id(0x3d), 0x7d);
+        assert_eq!(lin_protected_id(0x3f), 0xbf);
+        // Bits above the 6-bit ID are ignored.
+        assert_eq!(lin_protected_id(0xc1), 0xc1);
+
+        for id in 0..0x40 {
+            assert_eq!(lin_id_from_pid(lin_protected_id(id)), Some(id));
+        }
+        // Flipping either parity bit is rejected.
+        assert_eq!(lin_id_from_pid(0x80 ^ 0x40), None);
+        assert_eq!(lin_id_from_pid(0x80 ^ 0x80), None);
+    }
+
+    #[test]
+    fn lin_checksum_models() {
+        let data = [0x4a, 0x55, 0x93, 0xe5];
+
+        // Sum with carry: 0x4a + 0x55 = 0x9f; + 0x93 = 0x132 -> 0x33; + 0xe5 = 0x118 -> 0x19.
+        assert_eq!(lin_checksum(0x10, &data, LinChecksum::Classic), !0x19);
+
+        // The enhanced model starts the sum with the PID, 0x50: 0x9a, 0xef, 0x182 -> 0x83, then
+        // 0x168 -> 0x69.
+        assert_eq!(lin_checksum(0x10, &data, LinChecksum::Enhanced), !0x69);
+
+        // Diagnostic frames always use the classic model.
+        assert_eq!(
+            lin_checksum(0x3c, &data, LinChecksum::Enhanced),
+            lin_checksum(0x3c, &data, LinChecksum::Classic)
+        );
+
+        assert_eq!(lin_checksum(0x10, &[], LinChecksum::Classic), 0xff);
+    }
+}
@@ -4,7 +4,7 @@
 use core::ops::Deref;
 
 use crate::{
-    clocks::Clocks,
+    clocks::{ClockTree, Clocks, KernelClockPeriph},
     pac::{self, rcc::RegisterBlock},
 };
 
@@ -15,6 +15,21 @@ use crate::pac::DMA1;
 #[cfg(any(feature = "f3", feature = "l4"))]
 use crate::dma::{self, Dma, DmaChannel, DmaInput};
 
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+use crate::usart::Lpuart1;
+
 use cfg_if::cfg_if;
 
 #[cfg(not(any(
@@ -132,21 +147,27 @@ macro_rules! rcc_en_reset {
 
 pub(crate) use rcc_en_reset;
 
-// todo: This trait is currently a one-off for usart
+/// Reports the frequency of the clock that feeds a peripheral's baud rate generator, or for I2C,
+/// its timing generator. Used by USART, SPI, and I2C.
 pub trait BaudPeriph {
     fn baud(clock_cfg: &Clocks) -> u32;
+
+    /// `true` for LPUART, whose baud rate register is scaled by 256 relative to USART's.
+    fn low_power_uart() -> bool {
+        false
+    }
 }
 
 impl BaudPeriph for pac::USART1 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb2()
+        clock_cfg.kernel_clock(KernelClockPeriph::Usart1)
     }
 }
 
 #[cfg(not(any(feature = "wb", feature = "wl")))]
 impl BaudPeriph for pac::USART2 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+        clock_cfg.kernel_clock(KernelClockPeriph::Usart2)
     }
 }
 
@@ -163,7 +184,7 @@ impl BaudPeriph for pac::USART2 {
 )))]
 impl BaudPeriph for pac::USART3 {
     fn baud(clock_cfg: &Clocks) -> u32 {
-        clock_cfg.apb1()
+        clock_cfg.kernel_clock(KernelClockPeriph::Usart3)
     }
 }
 
@@ -171,54 +192,150 @@ cfg_if! {
     if #[cfg(any(feature = "l4x6", feature = "h7"))] {
         impl BaudPeriph for pac::UART4 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                clock_cfg.kernel_clock(KernelClockPeriph::Uart4)
             }
         }
 
         impl BaudPeriph for pac::UART5 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                clock_cfg.kernel_clock(KernelClockPeriph::Uart5)
             }
         }
 
         #[cfg(feature = "h7")]
         impl BaudPeriph for pac::USART6 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+                clock_cfg.kernel_clock(KernelClockPeriph::Usart6)
             }
         }
 
         #[cfg(feature = "h7")]
         impl BaudPeriph for pac::UART7 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                clock_cfg.kernel_clock(KernelClockPeriph::Uart7)
             }
         }
 
         #[cfg(feature = "h7")]
         impl BaudPeriph for pac::UART8 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb1()
+                clock_cfg.kernel_clock(KernelClockPeriph::Uart8)
             }
         }
 
         #[cfg(feature = "h735")]
         impl BaudPeriph for pac::UART9 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+                clock_cfg.kernel_clock(KernelClockPeriph::Uart9)
             }
         }
 
         #[cfg(feature = "h735")]
         impl BaudPeriph for pac::USART10 {
             fn baud(clock_cfg: &Clocks) -> u32 {
-                clock_cfg.apb2()
+                clock_cfg.kernel_clock(KernelClockPeriph::Usart10)
             }
         }
 
     }
 }
 
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+impl BaudPeriph for Lpuart1 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::Lpuart1)
+    }
+
+    fn low_power_uart() -> bool {
+        true
+    }
+}
+
+#[cfg(not(feature = "f301"))]
+impl BaudPeriph for pac::SPI1 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::Spi1)
+    }
+}
+
+#[cfg(not(any(feature = "f3x4", feature = "wb", feature = "wl")))]
+impl BaudPeriph for pac::SPI2 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::Spi2)
+    }
+}
+
+#[cfg(not(any(
+    feature = "f3x4",
+    feature = "f410",
+    feature = "g0",
+    feature = "wb",
+    feature = "wl"
+)))]
+impl BaudPeriph for pac::SPI3 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::Spi3)
+    }
+}
+
+#[cfg(feature = "h7")]
+impl BaudPeriph for pac::SPI4 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::Spi4)
+    }
+}
+
+impl BaudPeriph for pac::I2C1 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::I2c1)
+    }
+}
+
+#[cfg(not(any(feature = "wb", feature = "f3x4")))]
+impl BaudPeriph for pac::I2C2 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::I2c2)
+    }
+}
+
+#[cfg(any(feature = "h7", feature = "wb"))]
+impl BaudPeriph for pac::I2C3 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::I2c3)
+    }
+}
+
+#[cfg(any(
+    feature = "l4x1",
+    feature = "l4x2",
+    feature = "l412",
+    feature = "l4x6",
+    feature = "l5",
+    feature = "g471",
+    feature = "g473",
+    feature = "g474",
+    feature = "g483",
+    feature = "g484",
+    feature = "h7",
+))]
+impl BaudPeriph for pac::I2C4 {
+    fn baud(clock_cfg: &Clocks) -> u32 {
+        clock_cfg.kernel_clock(KernelClockPeriph::I2c4)
+    }
+}
+
 // todo: This trait is currently a one-off for adc, and isn't currently used.
 pub trait VrefPeriph {
     fn vref(clock_cfg: &Clocks) -> u32;
@@ -415,6 +532,54 @@ impl RccPeriph for pac::I2C3 {
     }
 }
 
+#[cfg(any(
+    feature = "l4x1",
+    feature = "l4x2",
+    feature = "l412",
+    feature = "l4x6",
+    feature = "l5",
+    feature = "g471",
+    feature = "g473",
+    feature = "g474",
+    feature = "g483",
+    feature = "g484",
+    feature = "h7",
+))]
+impl RccPeriph for pac::I2C4 {
+    fn en_reset(rcc: &RegisterBlock) {
+        cfg_if! {
+            if #[cfg(feature = "h7")] {
+                rcc_en_reset!(apb4, i2c4, rcc);
+            } else {
+                rcc.apb1enr2.modify(|_, w| w.i2c4en().set_bit());
+                rcc.apb1rstr2.modify(|_, w| w.i2c4rst().set_bit());
+                rcc.apb1rstr2.modify(|_, w| w.i2c4rst().clear_bit());
+            }
+        }
+    }
+
+    // I2C4 is on DMA2 only on L4; DMA2 channel selection isn't set up here.
+    #[cfg(feature = "l4")]
+    fn read_chan() -> DmaChannel {
+        unimplemented!()
+    }
+
+    #[cfg(feature = "l4")]
+    fn write_chan() -> DmaChannel {
+        unimplemented!()
+    }
+
+    #[cfg(feature = "l4")]
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+        unimplemented!()
+    }
+
+    #[cfg(feature = "l4")]
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(_dma: &mut Dma<D>) {
+        unimplemented!()
+    }
+}
+
 #[cfg(not(feature = "f301"))] // todo: Not sure what's going on  here.
 impl RccPeriph for pac::SPI1 {
     fn en_reset(rcc: &RegisterBlock) {
@@ -841,6 +1006,55 @@ cfg_if! {
     }
 }
 
+#[cfg(any(
+    feature = "l4",
+    feature = "l5",
+    feature = "g4",
+    feature = "wb",
+    feature = "wl",
+    feature = "g031",
+    feature = "g041",
+    feature = "g071",
+    feature = "g081",
+    feature = "g0b1",
+    feature = "g0c1",
+))]
+impl RccPeriph for Lpuart1 {
+    fn en_reset(rcc: &RegisterBlock) {
+        cfg_if! {
+            if #[cfg(feature = "g0")] {
+                rcc_en_reset!(apb1, lpuart1, rcc);
+            } else {
+                rcc.apb1enr2.modify(|_, w| w.lpuart1en().set_bit());
+                rcc.apb1rstr2.modify(|_, w| w.lpuart1rst().set_bit());
+                rcc.apb1rstr2.modify(|_, w| w.lpuart1rst().clear_bit());
+            }
+        }
+    }
+
+    // LPUART1 is on DMA2 only on L4; pass DMA2 to its DMA methods. Its requests are channel 7
+    // (RX) and channel 6 (TX), with a channel select value of 4. See L44 RM, Table 42.
+    #[cfg(feature = "l4")]
+    fn read_chan() -> DmaChannel {
+        DmaChannel::C7
+    }
+
+    #[cfg(feature = "l4")]
+    fn write_chan() -> DmaChannel {
+        DmaChannel::C6
+    }
+
+    #[cfg(feature = "l4")]
+    fn read_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+        dma.regs.cselr.modify(|_, w| w.c7s().bits(0b0100));
+    }
+
+    #[cfg(feature = "l4")]
+    fn write_sel<D: Deref<Target = dma_p::RegisterBlock>>(dma: &mut Dma<D>) {
+        dma.regs.cselr.modify(|_, w| w.c6s().bits(0b0100));
+    }
+}
+
 #[cfg(not(any(
     feature = "f401",
     feature = "f411",
@@ -929,9 +1143,6 @@ cfg_if! {
     }
 }
 
-// todo: APB1LR2 on L5, and AHB4 on H7. Fix it. (I2C4)
-// I2cDevice::Four => {
-
 // We currently only set up DAC1 DMA, and it's split by channels, not device.
 
 // todo: Use thsi approach for USART and SAI. When you un-macro them, ADC and Timer as well.
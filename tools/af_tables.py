@@ -0,0 +1,165 @@
+#!/usr/bin/env python3
+"""Generate the `af_table!` pin tables in `src/gpio/typed.rs` from ST's open pin data.
+
+Clone https://github.com/STMicroelectronics/STM32_open_pin_data, then run eg:
+
+    python3 tools/af_tables.py ~/STM32_open_pin_data \\
+        --family g0=STM32G0 --family h7=STM32H7 --periph USART1 --periph SPI1
+
+For each `--family feature=prefix`, this reads every `mcu/<prefix>*.xml`, finds the GPIO IP
+version each part uses, and reads its alternate function assignments from
+`mcu/IP/GPIO-<version>_Modes.xml`. Only (pin, AF) entries shared by every GPIO version in the
+family are kept, since one cargo feature covers all of them. Entries are also limited to pins that
+at least one part bonds out. The output is an `af_table!` block per family, to paste into the
+`cfg_if!` at the bottom of `typed.rs`.
+"""
+
+import argparse
+import glob
+import os
+import re
+import sys
+import xml.etree.ElementTree as ET
+
+NS = {"st": "http://mcd.rou.st.com/modules.php?name=mcu"}
+
+# Signal name pattern, and the trait each signal function maps to.
+SIGNALS = [
+    (re.compile(r"^((?:LP)?US?ART\d+)_(TX|RX)$"), {"TX": "TxPin", "RX": "RxPin"}),
+    (
+        re.compile(r"^(SPI\d+)_(SCK|MISO|MOSI)$"),
+        {"SCK": "SckPin", "MISO": "MisoPin", "MOSI": "MosiPin"},
+    ),
+    (re.compile(r"^(I2C\d+)_(SCL|SDA)$"), {"SCL": "SclPin", "SDA": "SdaPin"}),
+]
+
+# Output order: U[S]ART, then SPI, then I2C, as in `typed.rs`.
+TRAIT_ORDER = ["TxPin", "RxPin", "SckPin", "MisoPin", "MosiPin", "SclPin", "SdaPin"]
+TRAIT_KIND = {
+    "TxPin": 0,
+    "RxPin": 0,
+    "SckPin": 1,
+    "MisoPin": 1,
+    "MosiPin": 1,
+    "SclPin": 2,
+    "SdaPin": 2,
+}
+
+PIN_RE = re.compile(r"^P([A-K])(\d+)")
+AF_RE = re.compile(r"^GPIO_AF(\d+)_")
+
+
+def parse_pin(name):
+    """Convert a pin name like `PA9` or `PC14-OSC32_IN` to `('C', 14)`."""
+    m = PIN_RE.match(name)
+    return (m.group(1), int(m.group(2))) if m else None
+
+
+def read_mcus(data_dir, prefix):
+    """Return the set of GPIO IP versions, and the set of bonded-out pins, for parts whose file
+    name starts with `prefix`."""
+    versions = set()
+    pins = set()
+
+    for path in sorted(glob.glob(os.path.join(data_dir, "mcu", prefix + "*.xml"))):
+        root = ET.parse(path).getroot()
+        for ip in root.findall("st:IP", NS):
+            if ip.get("Name") == "GPIO":
+                versions.add(ip.get("Version"))
+        for pin in root.findall("st:Pin", NS):
+            parsed = parse_pin(pin.get("Name"))
+            if parsed:
+                pins.add(parsed)
+
+    return versions, pins
+
+
+def read_modes(data_dir, version, periphs):
+    """Return the set of (trait, periph, port, pin, af) entries in a GPIO modes file."""
+    path = os.path.join(data_dir, "mcu", "IP", "GPIO-" + version + "_Modes.xml")
+    root = ET.parse(path).getroot()
+    entries = set()
+
+    for gpio_pin in root.findall("st:GPIO_Pin", NS):
+        pin = parse_pin(gpio_pin.get("Name"))
+        if not pin:
+            continue
+
+        for signal in gpio_pin.findall("st:PinSignal", NS):
+            for pattern, traits in SIGNALS:
+                m = pattern.match(signal.get("Name"))
+                if not m or m.group(1) not in periphs:
+                    continue
+
+                for value in signal.iterfind(".//st:PossibleValue", NS):
+                    af = AF_RE.match(value.text or "")
+                    if af:
+                        trait = traits[m.group(2)]
+                        entries.add((trait, m.group(1), pin[0], pin[1], int(af.group(1))))
+
+    return entries
+
+
+def family_table(data_dir, feature, prefix, periphs):
+    versions, pins = read_mcus(data_dir, prefix)
+    if not versions:
+        sys.exit(f"No parts matching {prefix}* in {data_dir}/mcu")
+
+    entries = None
+    for version in sorted(versions):
+        modes = read_modes(data_dir, version, periphs)
+        entries = modes if entries is None else entries & modes
+
+    entries = {e for e in entries if (e[2], e[3]) in pins}
+
+    lines = [f"// {feature}: {', '.join(sorted(versions))}", "af_table! {"]
+    groups = {}
+    for trait, periph, port, n, af in entries:
+        groups.setdefault((trait, periph), []).append((port, n, af))
+
+    def key(group):
+        trait, periph = group
+        num = int(re.search(r"\d+$", periph).group())
+        return (TRAIT_KIND[trait], re.sub(r"\d+$", "", periph), num, TRAIT_ORDER.index(trait))
+
+    for trait, periph in sorted(groups, key=key):
+        pins_ = ", ".join(f"('{p}', {n}, {af})" for p, n, af in sorted(groups[(trait, periph)]))
+        lines.append(f"    {trait}<{periph}>: [{pins_}];")
+    lines.append("}")
+
+    return "\n".join(lines)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("data_dir", help="Path to a clone of STM32_open_pin_data")
+    parser.add_argument(
+        "--family",
+        action="append",
+        required=True,
+        metavar="FEATURE=PREFIX",
+        help="Cargo feature, and MCU file name prefix, eg g0=STM32G0. Can be repeated.",
+    )
+    parser.add_argument(
+        "--periph",
+        action="append",
+        metavar="PERIPH",
+        help="Peripheral to include, eg USART1. Can be repeated. Defaults to USART1, USART2, SPI1 "
+        "and I2C1.",
+    )
+    args = parser.parse_args()
+
+    periphs = set(args.periph or ["USART1", "USART2", "SPI1", "I2C1"])
+
+    tables = []
+    for family in args.family:
+        feature, _, prefix = family.partition("=")
+        if not prefix:
+            sys.exit(f"Expected FEATURE=PREFIX, got {family}")
+        tables.append(family_table(args.data_dir, feature, prefix, periphs))
+
+    print("\n\n".join(tables))
+
+
+if __name__ == "__main__":
+    main()